@@ -17,6 +17,7 @@
             jemalloc
             rust-jemalloc-sys
           ];
+          cargoBuildOptions = opts: opts ++ [ "--features" "futhark" ];
           src = ./.;
         };
         devShell = with pkgs;
@@ -1,16 +1,32 @@
+mod fusion;
 mod gate;
+mod optimizer;
 mod unitary;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
 
-use crate::parser::{Argument, Expression, OpCode, QasmStatement};
-use crate::types::{BasisIdx, QubitIndex, Real};
-pub use gate::{Gate, GateDefn, PullApplyOutput, PushApplicable, PushApplyOutput};
-pub use unitary::{Unitary, UnitaryMatrix};
+use nalgebra::DMatrix;
+
+use crate::error::{self, Error};
+use crate::parser::{
+    self, Argument, Expression, FuncCode, LocatedStatement, OpCode, QasmStatement,
+};
+use crate::types::{BasisIdx, Complex, QubitIndex, Real};
+pub use fusion::fuse;
+pub use gate::{Gate, GateDefn, PullApplyOutput, PushApplicable, PushApplyOutput, QubitBasis};
+pub use optimizer::OptimizationReport;
+pub use unitary::{load_unitaries, Unitary, UnitaryMatrix};
 
 #[derive(Debug)]
 pub enum CircuitBuildError {
+    ArityMismatch,
     IndexOutOfBounds,
+    RecursiveGateDecl,
+    RegisterSizeMismatch,
+    UnboundParameter,
+    UnknownCReg,
+    UnknownGate,
     UnknownQReg,
     UnsupportedExpression,
     UnsupportedGateArg,
@@ -18,168 +34,132 @@ pub enum CircuitBuildError {
     UnsupportedOpcode,
 }
 
-pub fn num_qubits(statments: &[QasmStatement]) -> usize {
+impl Display for CircuitBuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            CircuitBuildError::ArityMismatch => "wrong number of parameters or arguments",
+            CircuitBuildError::IndexOutOfBounds => "index out of bounds",
+            CircuitBuildError::RecursiveGateDecl => "recursive gate declaration",
+            CircuitBuildError::RegisterSizeMismatch => "register sizes do not match",
+            CircuitBuildError::UnboundParameter => "unbound parameter",
+            CircuitBuildError::UnknownCReg => "unknown classical register",
+            CircuitBuildError::UnknownGate => "unknown gate",
+            CircuitBuildError::UnknownQReg => "unknown quantum register",
+            CircuitBuildError::UnsupportedExpression => "unsupported expression",
+            CircuitBuildError::UnsupportedGateArg => "unsupported gate argument",
+            CircuitBuildError::UnsupportedIdentifier => "unsupported identifier",
+            CircuitBuildError::UnsupportedOpcode => "unsupported operator",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl std::error::Error for CircuitBuildError {}
+
+pub fn num_qubits(statments: &[LocatedStatement]) -> usize {
     statments
         .iter()
-        .map(|statement| match statement {
-            QasmStatement::QReg { size, .. } => *size,
+        .map(|located| match located.statement {
+            QasmStatement::QReg { size, .. } => size,
             _ => 0,
         })
         .sum()
 }
 
+#[derive(Debug, Clone)]
+pub struct ClassicalRegister {
+    pub name: String,
+    pub start: usize,
+    pub size: usize,
+}
+
 pub struct Circuit<B: BasisIdx> {
     pub num_qubits: usize,
+    pub cregs: Vec<ClassicalRegister>,
     pub gates: Vec<Gate<B>>,
 }
 
 impl<B: BasisIdx> Circuit<B> {
-    pub fn new(statements: Vec<QasmStatement>) -> Result<Self, CircuitBuildError> {
-        let mut num_qubits_so_far: usize = 0;
-        let mut qregs = HashMap::<String, (QubitIndex, QubitIndex)>::new();
-        let mut gates = Vec::<Gate<B>>::new();
+    pub fn new(statements: Vec<LocatedStatement>) -> error::Result<Self> {
+        Self::with_unitaries(statements, HashMap::new())
+    }
 
-        for statement in statements {
-            match statement {
-                QasmStatement::QReg { name, size } => {
-                    qregs.insert(name, (num_qubits_so_far, num_qubits_so_far + size));
-                    num_qubits_so_far += size;
+    /// Parses and builds an OpenQASM 2 or 3 program
+    pub fn from_qasm(source: &str) -> error::Result<Self> {
+        Self::new(parser::parse_program(source)?)
+    }
+
+    /// Builds the circuit of `defns` on `num_qubits` qubits, with a single
+    /// classical register `c` of `num_clbits` bits for measurements
+    pub fn from_gates(
+        num_qubits: usize,
+        num_clbits: usize,
+        defns: impl IntoIterator<Item = GateDefn>,
+    ) -> error::Result<Self> {
+        let gates = defns
+            .into_iter()
+            .map(|defn| {
+                let gate = Gate::new(defn);
+                if gate.touches.iter().any(|qi| *qi >= num_qubits)
+                    || gate_clbits(&gate.defn).any(|clbit| clbit >= num_clbits)
+                {
+                    log::error!(
+                        "gate {:?} is out of bounds of {} qubits and {} classical bits",
+                        gate.defn,
+                        num_qubits,
+                        num_clbits
+                    );
+                    return Err(Error::from(CircuitBuildError::IndexOutOfBounds));
                 }
-                QasmStatement::GateCall { name, params, args } => {
-                    let param_arity = params.len();
-                    let arg_arity = args.len();
-
-                    let get_index = |arg: Argument| -> Result<QubitIndex, CircuitBuildError> {
-                        match arg {
-                            Argument::Id(id) => {
-                                log::error!("unsupported gate arg: identifier {}", id);
-                                Err(CircuitBuildError::UnsupportedIdentifier)
-                            }
-                            Argument::Item(name, index) => {
-                                match qregs.get(&name) {
-                                    Some((start, stop)) => {
-                                        if index >= (stop - start) {
-                                            // NOTE: index is always nonzero as it is of type usize
-                                            log::error!("index out of bounds: {}", index);
-                                            Err(CircuitBuildError::IndexOutOfBounds)
-                                        } else {
-                                            Ok(start + index)
-                                        }
-                                    }
-                                    None => {
-                                        log::error!("unknown qreg: {}", name);
-                                        Err(CircuitBuildError::UnknownQReg)
-                                    }
-                                }
-                            }
-                            arg => {
-                                log::error!("unsupported gate arg: {:?}", arg);
-                                Err(CircuitBuildError::UnsupportedGateArg)
-                            }
-                        }
-                    };
+                Ok(gate)
+            })
+            .collect::<error::Result<Vec<_>>>()?;
 
-                    let args: Vec<QubitIndex> = args
-                        .into_iter()
-                        .map(get_index)
-                        .collect::<Result<Vec<_>, _>>()?;
-                    let params: Vec<Real> = params
-                        .into_iter()
-                        .map(eval)
-                        .collect::<Result<Vec<_>, _>>()?;
-
-                    let gate_defn = match (name.as_str(), param_arity, arg_arity) {
-                        ("ccx", 0, 3) => GateDefn::CCX {
-                            control1: args[0],
-                            control2: args[1],
-                            target: args[2],
-                        },
-                        ("cphase", 1, 2) | ("cp", 1, 2) => GateDefn::CPhase {
-                            control: args[0],
-                            target: args[1],
-                            rot: params[0],
-                        },
-                        ("cswap", 0, 3) => GateDefn::CSwap {
-                            control: args[0],
-                            target1: args[1],
-                            target2: args[2],
-                        },
-                        ("cx", 0, 2) => GateDefn::CX {
-                            control: args[0],
-                            target: args[1],
-                        },
-                        ("cz", 0, 2) => GateDefn::CZ {
-                            control: args[0],
-                            target: args[1],
-                        },
-                        ("fsim", 2, 2) => GateDefn::FSim {
-                            left: args[0],
-                            right: args[1],
-                            theta: params[0],
-                            phi: params[1],
-                        },
-                        ("h", 0, 1) => GateDefn::Hadamard(args[0]),
-                        ("phase", 1, 1) | ("p", 1, 1) => GateDefn::Phase {
-                            target: args[0],
-                            rot: params[0],
-                        },
-                        ("rx", 1, 1) => GateDefn::RX {
-                            rot: params[0],
-                            target: args[0],
-                        },
-                        ("ry", 1, 1) => GateDefn::RY {
-                            rot: params[0],
-                            target: args[0],
-                        },
-                        ("rz", 1, 1) => GateDefn::RZ {
-                            rot: params[0],
-                            target: args[0],
-                        },
-                        ("s", 0, 1) => GateDefn::S(args[0]),
-                        ("sdg", 0, 1) => GateDefn::Sdg(args[0]),
-                        ("swap", 0, 2) => GateDefn::Swap {
-                            target1: args[0],
-                            target2: args[1],
-                        },
-                        ("sx", 0, 1) => GateDefn::SqrtX(args[0]),
-                        ("sxdg", 0, 1) => GateDefn::SqrtXdg(args[0]),
-                        ("t", 0, 1) => GateDefn::T(args[0]),
-                        ("tdg", 0, 1) => GateDefn::Tdg(args[0]),
-                        // NOTE: U3 gate is deprecated
-                        ("u", 3, 1) | ("u3", 3, 1) => GateDefn::U {
-                            target: args[0],
-                            theta: params[0],
-                            phi: params[1],
-                            lambda: params[2],
-                        },
-                        ("u1", 1, 1) => GateDefn::U {
-                            target: args[0],
-                            theta: 0.0,
-                            phi: 0.0,
-                            lambda: params[0],
-                        },
-                        ("u2", 2, 1) => GateDefn::U {
-                            target: args[0],
-                            theta: std::f32::consts::PI / 2.0,
-                            phi: params[0],
-                            lambda: params[1],
-                        },
-                        ("x", 0, 1) => GateDefn::X(args[0]),
-                        ("y", 0, 1) => GateDefn::PauliY(args[0]),
-                        ("z", 0, 1) => GateDefn::PauliZ(args[0]),
-                        _ => {
-                            log::warn!("unknown gate: {}", name);
-                            GateDefn::Other { name, params, args }
-                        }
-                    };
+        let cregs = (num_clbits > 0)
+            .then(|| ClassicalRegister {
+                name: "c".to_string(),
+                start: 0,
+                size: num_clbits,
+            })
+            .into_iter()
+            .collect();
 
-                    gates.push(Gate::new(gate_defn));
-                }
+        Ok(Circuit {
+            num_qubits,
+            cregs,
+            gates,
+        })
+    }
+
+    /// Builds the circuit with additional gates given by their matrices, see
+    /// `load_unitaries`. These take precedence over gate declarations of the
+    /// same name.
+    pub fn with_unitaries(
+        statements: Vec<LocatedStatement>,
+        unitaries: HashMap<String, DMatrix<Complex>>,
+    ) -> error::Result<Self> {
+        let mut builder = CircuitBuilder::default();
+        builder.definitions.unitaries = unitaries;
+        let mut gates = Vec::<Gate<B>>::new();
+
+        for LocatedStatement {
+            location,
+            statement,
+        } in statements
+        {
+            let gate = statement_gate(&statement);
+            let start = gates.len();
+            builder
+                .build(statement, &mut gates)
+                .map_err(|err| Error::from(err).at(Some(location)).in_gate(gate.as_deref()))?;
+            for gate in &mut gates[start..] {
+                gate.location = Some(location);
             }
         }
 
         Ok(Circuit {
-            num_qubits: num_qubits_so_far,
+            num_qubits: builder.num_qubits,
+            cregs: builder.cregs,
             gates,
         })
     }
@@ -188,31 +168,823 @@ impl<B: BasisIdx> Circuit<B> {
         self.gates.len()
     }
 
-    /// Decompose 3 (or more) qubit gates for MPS simulation
+    pub fn num_clbits(&self) -> usize {
+        self.cregs.iter().map(|creg| creg.size).sum()
+    }
+
+    /// The first measurement, reset or classically controlled gate, if any
+    pub fn dynamic_gate(&self) -> Option<&Gate<B>> {
+        self.gates.iter().find(|gate| gate.defn.is_dynamic())
+    }
+
+    /// Symbols that gate parameters refer to, which must be bound by `bind`
+    pub fn parameters(&self) -> BTreeSet<String> {
+        let mut parameters = BTreeSet::new();
+        for gate in &self.gates {
+            collect_parameters(&gate.defn, &mut parameters);
+        }
+        parameters
+    }
+
+    /// Returns a copy of the circuit in which the symbols in gate parameters
+    /// are replaced by `bindings`. The circuit itself is left symbolic, so it
+    /// can be bound to many parameter sets.
+    pub fn bind(&self, bindings: &HashMap<String, Real>) -> error::Result<Self> {
+        let gates = self
+            .gates
+            .iter()
+            .map(|gate| match bind_gate_defn(&gate.defn, bindings) {
+                Ok(defn) => Ok(Gate::new(defn).at(gate.location)),
+                Err(err) => Err(Error::from(err)
+                    .at(gate.location)
+                    .in_gate(parameterized_name(&gate.defn))),
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+
+        Ok(Circuit {
+            num_qubits: self.num_qubits,
+            cregs: self.cregs.clone(),
+            gates,
+        })
+    }
+
+    /// Decompose 3 (or more) qubit gates for MPS simulation. The other
+    /// simulators apply them as they are.
     pub fn decompose(&mut self) -> Self {
         let new_gates: Vec<Gate<B>> = self
             .gates
             .iter_mut()
-            .flat_map(|g| g.defn.decompose_gate())
-            .map(|gdef| Gate::<B>::new(gdef))
+            .flat_map(|g| {
+                let location = g.location;
+                g.defn
+                    .decompose_gate()
+                    .into_iter()
+                    .map(move |gdef| Gate::<B>::new(gdef).at(location))
+            })
             .collect();
         let num_qubits = self.num_qubits;
 
         Circuit {
             num_qubits,
+            cregs: self.cregs.clone(),
             gates: new_gates,
         }
     }
+
+    /// Returns a copy of the circuit with redundant gates removed, see
+    /// `optimizer`, and how many were removed
+    pub fn optimize(&self) -> (Self, OptimizationReport) {
+        let (gates, report) = optimizer::optimize(self.num_qubits, &self.gates);
+        let circuit = Circuit {
+            num_qubits: self.num_qubits,
+            cregs: self.cregs.clone(),
+            gates,
+        };
+        (circuit, report)
+    }
+
+    /// Removes the final measurements, those after which no gate touches the
+    /// measured qubit or reads or writes its classical bit, so that the final
+    /// state can be sampled instead. Returns the rest of the circuit and the
+    /// qubit measured into each classical bit, in program order.
+    pub fn without_final_measurements(self) -> (Self, Vec<(QubitIndex, usize)>) {
+        let mut touched = HashSet::new();
+        let mut used_clbits = HashSet::new();
+        let mut measured = Vec::new();
+        let mut gates = Vec::new();
+
+        for gate in self.gates.into_iter().rev() {
+            if let GateDefn::Measure { target, clbit } = gate.defn {
+                if !touched.contains(&target)
+                    && clbit.is_none_or(|clbit| !used_clbits.contains(&clbit))
+                {
+                    measured.extend(clbit.map(|clbit| (target, clbit)));
+                    touched.insert(target);
+                    used_clbits.extend(clbit);
+                    continue;
+                }
+            }
+            touched.extend(gate.touches.iter().copied());
+            used_clbits.extend(gate_clbits(&gate.defn));
+            gates.push(gate);
+        }
+        gates.reverse();
+        measured.reverse();
+
+        let circuit = Circuit {
+            num_qubits: self.num_qubits,
+            cregs: self.cregs,
+            gates,
+        };
+        (circuit, measured)
+    }
 }
 
-fn eval(exp: Expression) -> Result<Real, CircuitBuildError> {
+/// The classical bits that `defn` writes or is conditioned on
+fn gate_clbits(defn: &GateDefn) -> Box<dyn Iterator<Item = usize> + '_> {
+    match defn {
+        GateDefn::Measure { clbit, .. } => Box::new(clbit.iter().copied()),
+        GateDefn::Conditional { clbits, defn, .. } => {
+            Box::new(clbits.iter().copied().chain(gate_clbits(defn)))
+        }
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+/// The gate called by a statement, for error reports
+fn statement_gate(statement: &QasmStatement) -> Option<String> {
+    match statement {
+        QasmStatement::GateCall { name, .. } => Some(name.clone()),
+        QasmStatement::Measure { .. } => Some("measure".to_string()),
+        QasmStatement::Reset { .. } => Some("reset".to_string()),
+        QasmStatement::Conditional { statement, .. } => statement_gate(statement),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct CircuitBuilder {
+    num_qubits: usize,
+    qregs: HashMap<String, (QubitIndex, QubitIndex)>,
+    cregs: Vec<ClassicalRegister>,
+    definitions: Definitions,
+}
+
+impl CircuitBuilder {
+    fn build<B: BasisIdx>(
+        &mut self,
+        statement: QasmStatement,
+        gates: &mut Vec<Gate<B>>,
+    ) -> Result<(), CircuitBuildError> {
+        match statement {
+            QasmStatement::QReg { name, size } => {
+                self.qregs
+                    .insert(name, (self.num_qubits, self.num_qubits + size));
+                self.num_qubits += size;
+            }
+            QasmStatement::CReg { name, size } => {
+                let start = self.cregs.iter().map(|creg| creg.size).sum();
+                self.cregs.push(ClassicalRegister { name, start, size });
+            }
+            QasmStatement::GateDecl {
+                name,
+                params,
+                args,
+                body,
+            } => {
+                self.definitions
+                    .gate_decls
+                    .insert(name, GateDecl { params, args, body });
+            }
+            QasmStatement::GateCall { name, params, args } => {
+                for args in self.broadcast(args)? {
+                    push_gates(
+                        name.clone(),
+                        params.clone(),
+                        args,
+                        &self.definitions,
+                        &mut vec![],
+                        gates,
+                    )?;
+                }
+            }
+            QasmStatement::Measure { qubit, bit } => {
+                let targets = self.qubit_indices(qubit)?;
+                let clbits = match bit {
+                    Some(bit) => self.clbit_indices(bit)?.into_iter().map(Some).collect(),
+                    None => vec![None; targets.len()],
+                };
+                if targets.len() != clbits.len() {
+                    log::error!(
+                        "cannot measure {} qubits into {} bits",
+                        targets.len(),
+                        clbits.len()
+                    );
+                    return Err(CircuitBuildError::RegisterSizeMismatch);
+                }
+                gates.extend(
+                    targets
+                        .into_iter()
+                        .zip(clbits)
+                        .map(|(target, clbit)| Gate::new(GateDefn::Measure { target, clbit })),
+                );
+            }
+            QasmStatement::Reset { qubit } => {
+                gates.extend(
+                    self.qubit_indices(qubit)?
+                        .into_iter()
+                        .map(|target| Gate::new(GateDefn::Reset(target))),
+                );
+            }
+            QasmStatement::Conditional {
+                bits,
+                value,
+                statement,
+            } => {
+                let clbits = self.clbit_indices(bits)?;
+                let mut conditioned = Vec::<Gate<B>>::new();
+                self.build(*statement, &mut conditioned)?;
+                gates.extend(conditioned.into_iter().map(|gate| {
+                    Gate::new(GateDefn::Conditional {
+                        clbits: clbits.clone(),
+                        value,
+                        defn: Box::new(gate.defn),
+                    })
+                }));
+            }
+            // these are loaded up front, see `with_unitaries`
+            QasmStatement::Unitaries { .. } => (),
+        }
+        Ok(())
+    }
+
+    fn qubit_index(&self, arg: Argument) -> Result<QubitIndex, CircuitBuildError> {
+        match arg {
+            Argument::Id(id) => {
+                log::error!("unsupported gate arg: identifier {}", id);
+                Err(CircuitBuildError::UnsupportedIdentifier)
+            }
+            Argument::Item(name, index) => {
+                match self.qregs.get(&name) {
+                    Some((start, stop)) => {
+                        if index >= (stop - start) {
+                            // NOTE: index is always nonzero as it is of type usize
+                            log::error!("index out of bounds: {}", index);
+                            Err(CircuitBuildError::IndexOutOfBounds)
+                        } else {
+                            Ok(start + index)
+                        }
+                    }
+                    None => {
+                        log::error!("unknown qreg: {}", name);
+                        Err(CircuitBuildError::UnknownQReg)
+                    }
+                }
+            }
+            arg => {
+                log::error!("unsupported gate arg: {:?}", arg);
+                Err(CircuitBuildError::UnsupportedGateArg)
+            }
+        }
+    }
+
+    /// Resolves a qubit, or all qubits of a register
+    fn qubit_indices(&self, arg: Argument) -> Result<Vec<QubitIndex>, CircuitBuildError> {
+        match arg {
+            Argument::Id(name) => match self.qregs.get(&name) {
+                Some((start, stop)) => Ok((*start..*stop).collect()),
+                None => {
+                    log::error!("unknown qreg: {}", name);
+                    Err(CircuitBuildError::UnknownQReg)
+                }
+            },
+            arg => Ok(vec![self.qubit_index(arg)?]),
+        }
+    }
+
+    /// Resolves the arguments of a gate call. A call on whole registers is
+    /// broadcast to one call per qubit, e.g. `cx a, b;` is `cx a[i], b[i];`
+    /// for each `i`, with single qubit arguments repeated in every call.
+    fn broadcast(&self, args: Vec<Argument>) -> Result<Vec<Vec<QubitIndex>>, CircuitBuildError> {
+        let args = args
+            .into_iter()
+            .map(|arg| {
+                let is_register = matches!(arg, Argument::Id(_));
+                Ok((is_register, self.qubit_indices(arg)?))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sizes: Vec<usize> = args
+            .iter()
+            .filter(|(is_register, _)| *is_register)
+            .map(|(_, qubits)| qubits.len())
+            .collect();
+        let size = match sizes.first() {
+            Some(&size) => size,
+            None => {
+                return Ok(vec![args
+                    .into_iter()
+                    .map(|(_, qubits)| qubits[0])
+                    .collect()])
+            }
+        };
+        if sizes.iter().any(|&other| other != size) {
+            log::error!("cannot broadcast over registers of sizes {:?}", sizes);
+            return Err(CircuitBuildError::RegisterSizeMismatch);
+        }
+
+        Ok((0..size)
+            .map(|i| {
+                args.iter()
+                    .map(|(is_register, qubits)| if *is_register { qubits[i] } else { qubits[0] })
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Resolves a classical bit, or all bits of a register (least significant
+    /// first)
+    fn clbit_indices(&self, arg: Argument) -> Result<Vec<usize>, CircuitBuildError> {
+        let (name, index) = match arg {
+            Argument::Id(name) => (name, None),
+            Argument::Item(name, index) => (name, Some(index)),
+            arg => {
+                log::error!("unsupported classical bit arg: {:?}", arg);
+                return Err(CircuitBuildError::UnsupportedGateArg);
+            }
+        };
+        let creg = match self.cregs.iter().find(|creg| creg.name == name) {
+            Some(creg) => creg,
+            None => {
+                log::error!("unknown creg: {}", name);
+                return Err(CircuitBuildError::UnknownCReg);
+            }
+        };
+        match index {
+            None => Ok((creg.start..creg.start + creg.size).collect()),
+            Some(index) if index < creg.size => Ok(vec![creg.start + index]),
+            Some(index) => {
+                log::error!("index out of bounds: {}", index);
+                Err(CircuitBuildError::IndexOutOfBounds)
+            }
+        }
+    }
+}
+
+struct GateDecl {
+    params: Vec<String>,
+    args: Vec<String>,
+    body: Vec<QasmStatement>,
+}
+
+/// The non-native gates that calls may refer to
+#[derive(Default)]
+struct Definitions {
+    gate_decls: HashMap<String, GateDecl>,
+    unitaries: HashMap<String, DMatrix<Complex>>,
+}
+
+/// Pushes the gate(s) for a call of `name`. Calls of user-defined gates are
+/// inlined recursively, down to native gates. Native gates whose parameters
+/// refer to symbols are kept as `GateDefn::Parameterized`.
+fn push_gates<B: BasisIdx>(
+    name: String,
+    raw_params: Vec<Expression>,
+    args: Vec<QubitIndex>,
+    definitions: &Definitions,
+    expanding: &mut Vec<String>,
+    gates: &mut Vec<Gate<B>>,
+) -> Result<(), CircuitBuildError> {
+    let is_symbolic = raw_params.iter().any(has_parameters);
+
+    // NOTE: symbolic parameters are only needed to pick the gate here, so any
+    // placeholder value will do
+    let params: Vec<Real> = if is_symbolic {
+        vec![0.0; raw_params.len()]
+    } else {
+        raw_params
+            .iter()
+            .cloned()
+            .map(|param| eval(param, &HashMap::new()))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let gate_defn = match native_gate(&name, &params, &args) {
+        Some(_) if is_symbolic => GateDefn::Parameterized {
+            name,
+            params: raw_params,
+            args,
+        },
+        Some(gate_defn) => gate_defn,
+        // NOTE: native gates take precedence over declarations of the same
+        // name, such as those in qelib1.inc
+        None if definitions.unitaries.contains_key(&name) => {
+            let mat = &definitions.unitaries[&name];
+            unitary_gate(name, mat, &raw_params, args)?
+        }
+        None => match definitions.gate_decls.get(&name) {
+            Some(decl) => {
+                return expand_gate_decl(
+                    name,
+                    decl,
+                    raw_params,
+                    args,
+                    definitions,
+                    expanding,
+                    gates,
+                );
+            }
+            None if name.contains('@') && !args.is_empty() => {
+                return push_controlled_gates(
+                    name,
+                    raw_params,
+                    args,
+                    definitions,
+                    expanding,
+                    gates,
+                );
+            }
+            None => {
+                log::error!("unknown gate: {}", name);
+                return Err(CircuitBuildError::UnknownGate);
+            }
+        },
+    };
+
+    gates.push(Gate::new(gate_defn));
+    Ok(())
+}
+
+/// A call of a gate given by its matrix. The first argument is the least
+/// significant bit of the matrix indices.
+fn unitary_gate(
+    name: String,
+    mat: &DMatrix<Complex>,
+    params: &[Expression],
+    args: Vec<QubitIndex>,
+) -> Result<GateDefn, CircuitBuildError> {
+    let num_args = mat.nrows().trailing_zeros() as usize;
+    if !params.is_empty() || args.len() != num_args {
+        log::error!(
+            "gate {} expects 0 params and {} args, got {} and {}",
+            name,
+            num_args,
+            params.len(),
+            args.len()
+        );
+        return Err(CircuitBuildError::ArityMismatch);
+    }
+
+    Ok(GateDefn::Unitary {
+        name,
+        matrix: UnitaryMatrix {
+            mat: mat.clone(),
+            qubit_indices: args,
+        },
+    })
+}
+
+/// Pushes a `ctrl @` or `negctrl @` call (see `parser::qasm3`) of a
+/// user-defined gate by controlling each of the gates it expands to
+fn push_controlled_gates<B: BasisIdx>(
+    name: String,
+    raw_params: Vec<Expression>,
+    args: Vec<QubitIndex>,
+    definitions: &Definitions,
+    expanding: &mut Vec<String>,
+    gates: &mut Vec<Gate<B>>,
+) -> Result<(), CircuitBuildError> {
+    let (modifier, base) = name.split_once('@').unwrap();
+    let (&control, base_args) = args.split_first().unwrap();
+
+    let mut base_gates: Vec<Gate<B>> = vec![];
+    push_gates(
+        base.to_string(),
+        raw_params,
+        base_args.to_vec(),
+        definitions,
+        expanding,
+        &mut base_gates,
+    )?;
+
+    let with_control = |args: Vec<QubitIndex>| [vec![control], args].concat();
+    for gate in base_gates {
+        let gate_defn = match gate.defn {
+            // these are resolved by name later on
+            GateDefn::Parameterized { name, params, args } => GateDefn::Parameterized {
+                name: format!("{}@{}", modifier, name),
+                params,
+                args: with_control(args),
+            },
+            defn if modifier == "negctrl" => GateDefn::controlled(vec![], vec![control], defn),
+            defn => GateDefn::controlled(vec![control], vec![], defn),
+        };
+        gates.push(Gate::new(gate_defn));
+    }
+    Ok(())
+}
+
+fn native_gate(name: &str, params: &[Real], args: &[QubitIndex]) -> Option<GateDefn> {
+    // `ctrl @` and `negctrl @` calls of gates without a named controlled
+    // counterpart, see `parser::qasm3`
+    if let Some((modifier, base)) = name.split_once('@') {
+        let (&control, base_args) = args.split_first()?;
+        let base = native_gate(base, params, base_args)?;
+        return match modifier {
+            "ctrl" => Some(GateDefn::controlled(vec![control], vec![], base)),
+            "negctrl" => Some(GateDefn::controlled(vec![], vec![control], base)),
+            _ => None,
+        };
+    }
+
+    let gate_defn = match (name, params.len(), args.len()) {
+        ("ccx", 0, 3) => GateDefn::CCX {
+            control1: args[0],
+            control2: args[1],
+            target: args[2],
+        },
+        ("ch", 0, 2) => GateDefn::CH {
+            control: args[0],
+            target: args[1],
+        },
+        ("cphase", 1, 2) | ("cp", 1, 2) | ("cu1", 1, 2) => GateDefn::CPhase {
+            control: args[0],
+            target: args[1],
+            rot: params[0],
+        },
+        ("crx", 1, 2) => GateDefn::CRX {
+            control: args[0],
+            target: args[1],
+            rot: params[0],
+        },
+        ("cry", 1, 2) => GateDefn::CRY {
+            control: args[0],
+            target: args[1],
+            rot: params[0],
+        },
+        ("crz", 1, 2) => GateDefn::CRZ {
+            control: args[0],
+            target: args[1],
+            rot: params[0],
+        },
+        ("cswap", 0, 3) => GateDefn::CSwap {
+            control: args[0],
+            target1: args[1],
+            target2: args[2],
+        },
+        ("cx", 0, 2) | ("CX", 0, 2) => GateDefn::CX {
+            control: args[0],
+            target: args[1],
+        },
+        ("cu", 4, 2) => GateDefn::CU {
+            control: args[0],
+            target: args[1],
+            theta: params[0],
+            phi: params[1],
+            lambda: params[2],
+            gamma: params[3],
+        },
+        ("cu3", 3, 2) => GateDefn::CU {
+            control: args[0],
+            target: args[1],
+            theta: params[0],
+            phi: params[1],
+            lambda: params[2],
+            gamma: 0.0,
+        },
+        ("cy", 0, 2) => GateDefn::CY {
+            control: args[0],
+            target: args[1],
+        },
+        ("cz", 0, 2) => GateDefn::CZ {
+            control: args[0],
+            target: args[1],
+        },
+        ("c3x", 0, 4) | ("c4x", 0, 5) => GateDefn::controlled(
+            args[..args.len() - 1].to_vec(),
+            vec![],
+            GateDefn::X(args[args.len() - 1]),
+        ),
+        ("ecr", 0, 2) => GateDefn::ECR {
+            target1: args[0],
+            target2: args[1],
+        },
+        ("fsim", 2, 2) => GateDefn::FSim {
+            left: args[0],
+            right: args[1],
+            theta: params[0],
+            phi: params[1],
+        },
+        ("h", 0, 1) => GateDefn::Hadamard(args[0]),
+        ("iswap", 0, 2) => GateDefn::ISwap {
+            target1: args[0],
+            target2: args[1],
+        },
+        ("mcx", 0, num_args) if num_args >= 2 => GateDefn::controlled(
+            args[..num_args - 1].to_vec(),
+            vec![],
+            GateDefn::X(args[num_args - 1]),
+        ),
+        ("phase", 1, 1) | ("p", 1, 1) => GateDefn::Phase {
+            target: args[0],
+            rot: params[0],
+        },
+        ("rx", 1, 1) => GateDefn::RX {
+            rot: params[0],
+            target: args[0],
+        },
+        ("rxx", 1, 2) => GateDefn::RXX {
+            rot: params[0],
+            target1: args[0],
+            target2: args[1],
+        },
+        ("ry", 1, 1) => GateDefn::RY {
+            rot: params[0],
+            target: args[0],
+        },
+        ("ryy", 1, 2) => GateDefn::RYY {
+            rot: params[0],
+            target1: args[0],
+            target2: args[1],
+        },
+        ("rz", 1, 1) => GateDefn::RZ {
+            rot: params[0],
+            target: args[0],
+        },
+        ("rzz", 1, 2) => GateDefn::RZZ {
+            rot: params[0],
+            target1: args[0],
+            target2: args[1],
+        },
+        ("s", 0, 1) => GateDefn::S(args[0]),
+        ("sdg", 0, 1) => GateDefn::Sdg(args[0]),
+        ("swap", 0, 2) => GateDefn::Swap {
+            target1: args[0],
+            target2: args[1],
+        },
+        ("sx", 0, 1) => GateDefn::SqrtX(args[0]),
+        ("sxdg", 0, 1) => GateDefn::SqrtXdg(args[0]),
+        ("t", 0, 1) => GateDefn::T(args[0]),
+        ("tdg", 0, 1) => GateDefn::Tdg(args[0]),
+        // NOTE: U3 gate is deprecated
+        ("u", 3, 1) | ("u3", 3, 1) | ("U", 3, 1) => GateDefn::U {
+            target: args[0],
+            theta: params[0],
+            phi: params[1],
+            lambda: params[2],
+        },
+        ("u1", 1, 1) => GateDefn::U {
+            target: args[0],
+            theta: 0.0,
+            phi: 0.0,
+            lambda: params[0],
+        },
+        ("u2", 2, 1) => GateDefn::U {
+            target: args[0],
+            theta: std::f64::consts::PI / 2.0,
+            phi: params[0],
+            lambda: params[1],
+        },
+        ("x", 0, 1) => GateDefn::X(args[0]),
+        ("y", 0, 1) => GateDefn::PauliY(args[0]),
+        ("z", 0, 1) => GateDefn::PauliZ(args[0]),
+        _ => return None,
+    };
+    Some(gate_defn)
+}
+
+fn bind_gate_defn(
+    defn: &GateDefn,
+    bindings: &HashMap<String, Real>,
+) -> Result<GateDefn, CircuitBuildError> {
+    match defn {
+        GateDefn::Parameterized { name, params, args } => {
+            let params = params
+                .iter()
+                .cloned()
+                .map(|param| eval(param, bindings))
+                .collect::<Result<Vec<_>, _>>()?;
+            native_gate(name, &params, args).ok_or_else(|| {
+                log::error!("unknown gate: {}", name);
+                CircuitBuildError::UnknownGate
+            })
+        }
+        GateDefn::Conditional {
+            clbits,
+            value,
+            defn,
+        } => Ok(GateDefn::Conditional {
+            clbits: clbits.clone(),
+            value: *value,
+            defn: Box::new(bind_gate_defn(defn, bindings)?),
+        }),
+        defn => Ok(defn.clone()),
+    }
+}
+
+fn parameterized_name(defn: &GateDefn) -> Option<&str> {
+    match defn {
+        GateDefn::Parameterized { name, .. } => Some(name),
+        GateDefn::Conditional { defn, .. } => parameterized_name(defn),
+        _ => None,
+    }
+}
+
+fn collect_parameters(defn: &GateDefn, parameters: &mut BTreeSet<String>) {
+    match defn {
+        GateDefn::Parameterized { params, .. } => params
+            .iter()
+            .for_each(|param| collect_symbols(param, parameters)),
+        GateDefn::Conditional { defn, .. } => collect_parameters(defn, parameters),
+        _ => (),
+    }
+}
+
+fn collect_symbols(exp: &Expression, symbols: &mut BTreeSet<String>) {
     match exp {
-        Expression::Pi => Ok(std::f32::consts::PI),
-        Expression::Real(x) => Ok(x as Real),
+        Expression::Id(id) => {
+            symbols.insert(id.clone());
+        }
+        Expression::Op(_, e1, e2) => {
+            collect_symbols(e1, symbols);
+            collect_symbols(e2, symbols);
+        }
+        Expression::Function(_, exp) | Expression::Minus(exp) => collect_symbols(exp, symbols),
+        _ => (),
+    }
+}
+
+fn has_parameters(exp: &Expression) -> bool {
+    let mut symbols = BTreeSet::new();
+    collect_symbols(exp, &mut symbols);
+    !symbols.is_empty()
+}
+
+fn expand_gate_decl<B: BasisIdx>(
+    name: String,
+    decl: &GateDecl,
+    params: Vec<Expression>,
+    args: Vec<QubitIndex>,
+    definitions: &Definitions,
+    expanding: &mut Vec<String>,
+    gates: &mut Vec<Gate<B>>,
+) -> Result<(), CircuitBuildError> {
+    if params.len() != decl.params.len() || args.len() != decl.args.len() {
+        log::error!(
+            "gate {} expects {} params and {} args, got {} and {}",
+            name,
+            decl.params.len(),
+            decl.args.len(),
+            params.len(),
+            args.len()
+        );
+        return Err(CircuitBuildError::ArityMismatch);
+    }
+    if expanding.contains(&name) {
+        log::error!("recursive gate declaration: {}", name);
+        return Err(CircuitBuildError::RecursiveGateDecl);
+    }
+
+    let param_bindings: HashMap<&str, Expression> =
+        decl.params.iter().map(String::as_str).zip(params).collect();
+    let arg_bindings: HashMap<&str, QubitIndex> =
+        decl.args.iter().map(String::as_str).zip(args).collect();
+
+    expanding.push(name);
+    for statement in &decl.body {
+        if let QasmStatement::GateCall { name, params, args } = statement {
+            let args = args
+                .iter()
+                .map(|arg| match arg {
+                    Argument::Id(id) if arg_bindings.contains_key(id.as_str()) => {
+                        Ok(arg_bindings[id.as_str()])
+                    }
+                    arg => {
+                        log::error!("unsupported gate arg in gate body: {:?}", arg);
+                        Err(CircuitBuildError::UnsupportedGateArg)
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let params = params
+                .iter()
+                .map(|param| substitute(param.clone(), &param_bindings))
+                .collect();
+            push_gates(name.clone(), params, args, definitions, expanding, gates)?;
+        }
+    }
+    expanding.pop();
+
+    Ok(())
+}
+
+/// Replaces formal parameters in `exp` with the actual parameters
+fn substitute(exp: Expression, bindings: &HashMap<&str, Expression>) -> Expression {
+    match exp {
+        Expression::Id(id) => match bindings.get(id.as_str()) {
+            Some(value) => value.clone(),
+            None => Expression::Id(id),
+        },
+        Expression::Op(opcode, e1, e2) => Expression::Op(
+            opcode,
+            Box::new(substitute(*e1, bindings)),
+            Box::new(substitute(*e2, bindings)),
+        ),
+        Expression::Function(funccode, exp) => {
+            Expression::Function(funccode, Box::new(substitute(*exp, bindings)))
+        }
+        Expression::Minus(exp) => Expression::Minus(Box::new(substitute(*exp, bindings))),
+        exp => exp,
+    }
+}
+
+fn eval(exp: Expression, bindings: &HashMap<String, Real>) -> Result<Real, CircuitBuildError> {
+    match exp {
+        Expression::Pi => Ok(std::f64::consts::PI),
+        Expression::Real(x) => Ok(x),
         Expression::Int(x) => Ok(x as Real),
         Expression::Op(opcode, e1, e2) => {
-            let v1 = eval(*e1)?;
-            let v2 = eval(*e2)?;
+            let v1 = eval(*e1, bindings)?;
+            let v2 = eval(*e2, bindings)?;
             match opcode {
                 OpCode::Add => Ok(v1 + v2),
                 OpCode::Sub => Ok(v1 - v2),
@@ -222,8 +994,29 @@ fn eval(exp: Expression) -> Result<Real, CircuitBuildError> {
                 _ => Err(CircuitBuildError::UnsupportedOpcode),
             }
         }
-        Expression::Minus(exp) => Ok(-eval(*exp)?),
-        Expression::Id(_) => Err(CircuitBuildError::UnsupportedIdentifier),
+        Expression::Function(funccode, exp) => {
+            let v = eval(*exp, bindings)?;
+            match funccode {
+                FuncCode::Sin => Ok(v.sin()),
+                FuncCode::Cos => Ok(v.cos()),
+                FuncCode::Tan => Ok(v.tan()),
+                FuncCode::Exp => Ok(v.exp()),
+                FuncCode::Ln => Ok(v.ln()),
+                FuncCode::Sqrt => Ok(v.sqrt()),
+                funccode => {
+                    log::error!("unsupported function: {:?}", funccode);
+                    Err(CircuitBuildError::UnsupportedExpression)
+                }
+            }
+        }
+        Expression::Minus(exp) => Ok(-eval(*exp, bindings)?),
+        Expression::Id(id) => match bindings.get(&id) {
+            Some(value) => Ok(*value),
+            None => {
+                log::error!("unbound parameter: {}", id);
+                Err(CircuitBuildError::UnboundParameter)
+            }
+        },
         exp => {
             log::error!("unsupported expression: {:?}", exp);
             Err(CircuitBuildError::UnsupportedExpression)
@@ -234,9 +1027,55 @@ fn eval(exp: Expression) -> Result<Real, CircuitBuildError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::{ErrorKind, Location};
     use crate::parser;
     use crate::types::BasisIdx64;
 
+    #[test]
+    fn test_circuit_from_gates() {
+        let circuit = Circuit::<BasisIdx64>::from_gates(
+            2,
+            1,
+            [
+                GateDefn::Hadamard(0),
+                GateDefn::CX {
+                    control: 0,
+                    target: 1,
+                },
+                GateDefn::Measure {
+                    target: 1,
+                    clbit: Some(0),
+                },
+            ],
+        )
+        .unwrap();
+        assert_eq!(circuit.num_gates(), 3);
+        assert_eq!(circuit.num_clbits(), 1);
+        assert_eq!(circuit.gates[1].touches, vec![0, 1]);
+
+        let err = Circuit::<BasisIdx64>::from_gates(2, 0, [GateDefn::X(2)])
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.kind,
+            ErrorKind::CircuitBuild(CircuitBuildError::IndexOutOfBounds)
+        ));
+        let err = Circuit::<BasisIdx64>::from_gates(
+            2,
+            0,
+            [GateDefn::Measure {
+                target: 0,
+                clbit: Some(0),
+            }],
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err.kind,
+            ErrorKind::CircuitBuild(CircuitBuildError::IndexOutOfBounds)
+        ));
+    }
+
     #[test]
     fn test_circuit_from_source() {
         let source = r#"
@@ -270,4 +1109,250 @@ mod tests {
             vec![false, true, true, true, true, false, false, false, true, true, true, true]
         )
     }
+
+    #[test]
+    fn test_gate_decl_expansion() {
+        let source = r#"
+        OPENQASM 2.0;
+        include "qelib1.inc";
+        gate half_phase(theta) a {
+            p(theta / 2) a;
+        }
+        gate oracle(theta) a, b {
+            cx b, a;
+            half_phase(2 * theta) b;
+            id a;
+        }
+        qreg q[2];
+        oracle(pi) q[0], q[1];
+        "#;
+
+        let program = parser::parse_program(&source).unwrap();
+
+        let circuit = Circuit::<BasisIdx64>::new(program).unwrap();
+        assert_eq!(circuit.gates.len(), 3);
+        match circuit.gates[0].defn {
+            GateDefn::CX { control, target } => assert_eq!((control, target), (1, 0)),
+            ref defn => panic!("expected cx, found {:?}", defn),
+        }
+        match circuit.gates[1].defn {
+            GateDefn::Phase { target, rot } => {
+                assert_eq!(target, 1);
+                assert!((rot - std::f64::consts::PI).abs() < 1e-6);
+            }
+            ref defn => panic!("expected phase, found {:?}", defn),
+        }
+        // `id` is only defined in qelib1.inc, as U(0, 0, 0)
+        assert!(matches!(
+            circuit.gates[2].defn,
+            GateDefn::U { target: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn test_bind_parameters() {
+        let source = r#"
+        OPENQASM 2.0;
+        include "qelib1.inc";
+        gate layer(beta) a, b {
+            rx(2 * beta) a;
+            rzz(beta) a, b;
+        }
+        qreg q[2];
+        h q[0];
+        layer(theta) q[0], q[1];
+        "#;
+
+        let program = parser::parse_program(&source).unwrap();
+
+        let circuit = Circuit::<BasisIdx64>::new(program).unwrap();
+        assert_eq!(
+            circuit.parameters().into_iter().collect::<Vec<_>>(),
+            vec!["theta".to_string()]
+        );
+        assert!(matches!(
+            circuit.bind(&HashMap::new()),
+            Err(Error {
+                kind: ErrorKind::CircuitBuild(CircuitBuildError::UnboundParameter),
+                ..
+            })
+        ));
+
+        let bindings = HashMap::from([("theta".to_string(), 0.25)]);
+        let bound = circuit.bind(&bindings).unwrap();
+        assert!(bound.parameters().is_empty());
+        match bound.gates[1].defn {
+            GateDefn::RX { rot, target: 0 } => assert!((rot - 0.5).abs() < 1e-6),
+            ref defn => panic!("expected rx, found {:?}", defn),
+        }
+    }
+
+    #[test]
+    fn test_eval_functions() {
+        let source = r#"
+        OPENQASM 2.0;
+        include "qelib1.inc";
+        qreg q[1];
+        u3(2 * sin(pi / 6), sqrt(2) ^ 2 - ln(exp(1)), cos(0) + tan(pi / 4)) q[0];
+        rz(1000000000.0 * pi + 0.1 - 1000000000.0 * pi) q[0];
+        "#;
+
+        let program = parser::parse_program(source).unwrap();
+
+        let circuit = Circuit::<BasisIdx64>::new(program).unwrap();
+        match circuit.gates[0].defn {
+            GateDefn::U {
+                theta, phi, lambda, ..
+            } => {
+                assert!((theta - 1.0).abs() < 1e-6);
+                assert!((phi - 1.0).abs() < 1e-6);
+                assert!((lambda - 2.0).abs() < 1e-6);
+            }
+            ref defn => panic!("expected u, found {:?}", defn),
+        }
+        // the large terms only cancel when evaluated in f64
+        match circuit.gates[1].defn {
+            GateDefn::RZ { rot, .. } => assert!((rot - 0.1).abs() < 1e-6),
+            ref defn => panic!("expected rz, found {:?}", defn),
+        }
+    }
+
+    #[test]
+    fn test_register_broadcast() {
+        let source = r#"
+        OPENQASM 2.0;
+        include "qelib1.inc";
+        qreg a[2];
+        qreg b[2];
+        h a;
+        cx a, b;
+        cz a[1], b;
+        "#;
+
+        let program = parser::parse_program(&source).unwrap();
+
+        let circuit = Circuit::<BasisIdx64>::new(program).unwrap();
+        let touches: Vec<Vec<QubitIndex>> = circuit
+            .gates
+            .iter()
+            .map(|gate| gate.touches.clone())
+            .collect();
+        assert_eq!(
+            touches,
+            vec![
+                vec![0],
+                vec![1],
+                vec![0, 2],
+                vec![1, 3],
+                vec![1, 2],
+                vec![1, 3]
+            ]
+        );
+
+        let source = r#"
+        OPENQASM 2.0;
+        include "qelib1.inc";
+        qreg a[2];
+        qreg b[3];
+        cx a, b;
+        "#;
+
+        let program = parser::parse_program(&source).unwrap();
+        match Circuit::<BasisIdx64>::new(program) {
+            Err(Error {
+                kind: ErrorKind::CircuitBuild(CircuitBuildError::RegisterSizeMismatch),
+                location,
+                gate,
+                ..
+            }) => {
+                assert_eq!(location, Some(Location { line: 6, column: 9 }));
+                assert_eq!(gate.as_deref(), Some("cx"));
+            }
+            result => panic!(
+                "expected a register size mismatch, found {:?}",
+                result.err()
+            ),
+        }
+    }
+
+    #[test]
+    fn test_without_final_measurements() {
+        let source = r#"
+        OPENQASM 2.0;
+        include "qelib1.inc";
+        qreg q[3];
+        creg c[3];
+        h q[0];
+        measure q[0] -> c[0];
+        if (c == 1) x q[1];
+        measure q[0] -> c[1];
+        cx q[1], q[2];
+        measure q[2] -> c[2];
+        measure q[1] -> c[0];
+        "#;
+
+        let program = parser::parse_program(source).unwrap();
+        let circuit = Circuit::<BasisIdx64>::new(program).unwrap();
+        let (circuit, measured) = circuit.without_final_measurements();
+
+        // the first measurement is read by the conditional gate, and its
+        // classical bit is written again at the end
+        assert_eq!(measured, vec![(0, 1), (2, 2), (1, 0)]);
+        assert_eq!(circuit.num_gates(), 4);
+        assert!(matches!(
+            circuit.gates[1].defn,
+            GateDefn::Measure {
+                target: 0,
+                clbit: Some(0)
+            }
+        ));
+        assert_eq!(circuit.num_clbits(), 3);
+    }
+
+    #[test]
+    fn test_unitary_gates() {
+        let source = r#"
+        OPENQASM 3.0;
+        include "stdgates.inc";
+        pragma unitaries "gates.json";
+        qubit[3] q;
+        gate w a, b {
+            cx a, b;
+        }
+        w q[2], q[0];
+        ctrl @ w q[1], q[0], q[2];
+        "#;
+        let unitaries = || {
+            let mut mat = DMatrix::<Complex>::identity(4, 4);
+            mat.swap_rows(0, 3);
+            HashMap::from([("w".to_string(), mat)])
+        };
+
+        let program = parser::parse_program(source).unwrap();
+        assert!(matches!(
+            &program[0].statement,
+            QasmStatement::Unitaries { path } if path == "gates.json"
+        ));
+
+        let circuit = Circuit::<BasisIdx64>::with_unitaries(program, unitaries()).unwrap();
+        assert_eq!(circuit.gates.len(), 2);
+        match &circuit.gates[0].defn {
+            GateDefn::Unitary { name, matrix } => {
+                assert_eq!(name, "w");
+                assert_eq!(matrix.qubit_indices, vec![2, 0]);
+            }
+            defn => panic!("expected a unitary, found {:?}", defn),
+        }
+        assert_eq!(circuit.gates[1].touches, vec![1, 0, 2]);
+
+        let program =
+            parser::parse_program("OPENQASM 3;\nqubit[3] q;\nw q[0], q[1], q[2];\n").unwrap();
+        assert!(matches!(
+            Circuit::<BasisIdx64>::with_unitaries(program, unitaries()),
+            Err(Error {
+                kind: ErrorKind::CircuitBuild(CircuitBuildError::ArityMismatch),
+                ..
+            })
+        ));
+    }
 }
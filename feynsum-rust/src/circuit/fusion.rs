@@ -0,0 +1,129 @@
+use nalgebra::DMatrix;
+
+use super::UnitaryMatrix;
+use crate::types::{Complex, QubitIndex};
+
+/// Multiplies unitaries that are applied one after another into a single
+/// unitary on the union of their qubits, so that a dense simulator can apply
+/// the whole run in one pass over the state
+pub fn fuse(unitaries: Vec<UnitaryMatrix>) -> UnitaryMatrix {
+    if unitaries.len() == 1 {
+        return unitaries.into_iter().next().unwrap();
+    }
+
+    let mut qubit_indices = unitaries
+        .iter()
+        .flat_map(|unitary| unitary.qubit_indices.iter().copied())
+        .collect::<Vec<QubitIndex>>();
+    qubit_indices.sort_unstable();
+    qubit_indices.dedup();
+
+    let dim = 1 << qubit_indices.len();
+    let mat = unitaries
+        .iter()
+        .fold(DMatrix::identity(dim, dim), |mat, unitary| {
+            let positions = unitary
+                .qubit_indices
+                .iter()
+                .map(|qi| qubit_indices.binary_search(qi).unwrap())
+                .collect::<Vec<_>>();
+            apply(unitary, &positions, &mat)
+        });
+
+    UnitaryMatrix { mat, qubit_indices }
+}
+
+/// Left-multiplies `mat` by `unitary`, whose qubits are the bits `positions`
+/// of the row and column indices of `mat`
+fn apply(unitary: &UnitaryMatrix, positions: &[usize], mat: &DMatrix<Complex>) -> DMatrix<Complex> {
+    let dim = mat.nrows();
+    let gate_dim = 1 << positions.len();
+    let mask = positions.iter().fold(0, |mask, pos| mask | (1 << pos));
+
+    // the bits of `idx` at `positions`, the first one least significant
+    let gather = |idx: usize| {
+        positions
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (j, pos)| acc | (((idx >> pos) & 1) << j))
+    };
+    let scatter = |bits: usize| {
+        positions
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (j, pos)| acc | (((bits >> j) & 1) << pos))
+    };
+
+    DMatrix::from_fn(dim, dim, |row, col| {
+        let rest = row & !mask;
+        (0..gate_dim)
+            .map(|k| unitary.mat[(gather(row), k)] * mat[(rest | scatter(k), col)])
+            .sum()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::dmatrix;
+
+    use super::*;
+    use crate::types::constants::RECP_SQRT_2;
+
+    fn c(re: f64) -> Complex {
+        Complex::new(re, 0.0)
+    }
+
+    fn hadamard(qi: QubitIndex) -> UnitaryMatrix {
+        UnitaryMatrix {
+            mat: dmatrix![
+                c(RECP_SQRT_2), c(RECP_SQRT_2);
+                c(RECP_SQRT_2), c(-RECP_SQRT_2)
+            ],
+            qubit_indices: vec![qi],
+        }
+    }
+
+    // control first, so the control is the least significant bit
+    fn cx(control: QubitIndex, target: QubitIndex) -> UnitaryMatrix {
+        UnitaryMatrix {
+            mat: dmatrix![
+                c(1.0), c(0.0), c(0.0), c(0.0);
+                c(0.0), c(0.0), c(0.0), c(1.0);
+                c(0.0), c(0.0), c(1.0), c(0.0);
+                c(0.0), c(1.0), c(0.0), c(0.0)
+            ],
+            qubit_indices: vec![control, target],
+        }
+    }
+
+    fn assert_close(actual: &DMatrix<Complex>, expected: &DMatrix<Complex>) {
+        assert!(
+            (actual - expected).iter().all(|entry| entry.norm() < 1e-12),
+            "expected: {}, actual: {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_fuse_bell() {
+        // h q[3]; cx q[3], q[1]; prepares a bell pair from |00>
+        let fused = fuse(vec![hadamard(3), cx(3, 1)]);
+        assert_eq!(fused.qubit_indices, vec![1, 3]);
+
+        // qubit 1 is the least significant bit of the fused unitary
+        let r = c(RECP_SQRT_2);
+        let column = fused.mat.column(0).into_owned();
+        assert_close(
+            &DMatrix::from_column_slice(4, 1, column.as_slice()),
+            &DMatrix::from_column_slice(4, 1, &[r, c(0.0), c(0.0), r]),
+        );
+    }
+
+    #[test]
+    fn test_fuse_inverse() {
+        let fused = fuse(vec![cx(0, 2), hadamard(1), hadamard(1), cx(0, 2)]);
+        assert_eq!(fused.qubit_indices, vec![0, 1, 2]);
+        assert_close(&fused.mat, &DMatrix::identity(8, 8));
+    }
+}
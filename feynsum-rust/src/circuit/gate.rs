@@ -1,20 +1,70 @@
 use derivative::Derivative;
+use nalgebra::DMatrix;
 
+use super::UnitaryMatrix;
 use crate::{
-    types::{constants, BasisIdx, Complex, QubitIndex, Real},
+    error::Location,
+    parser::Expression,
+    types::{constants, BasisIdx, BasisIdxUnlimited, Complex, Precision, QubitIndex, Real, Weight},
     utility,
 };
 
 #[derive(Debug)]
-pub enum PushApplyOutput<B: BasisIdx> {
-    Nonbranching(B, Complex),              // bidx, weight
-    Branching((B, Complex), (B, Complex)), // (bidx, weight), (bidx, weight)
+pub enum PushApplyOutput<B: BasisIdx, P: Precision = Real> {
+    Nonbranching(B, Weight<P>),                // bidx, weight
+    Branching((B, Weight<P>), (B, Weight<P>)), // (bidx, weight), (bidx, weight)
+    Multibranching(Vec<(B, Weight<P>)>),       // more than two (bidx, weight)
 }
 
 #[derive(Debug)]
-pub enum PullApplyOutput<B: BasisIdx> {
-    Nonbranching(B, Complex),              // neighbor, multiplier
-    Branching((B, Complex), (B, Complex)), // (neighbor, multiplier), (neighbor, multiplier)
+pub enum PullApplyOutput<B: BasisIdx, P: Precision = Real> {
+    Nonbranching(B, Weight<P>),                // neighbor, multiplier
+    Branching((B, Weight<P>), (B, Weight<P>)), // (neighbor, multiplier), (neighbor, multiplier)
+    Multibranching(Vec<(B, Weight<P>)>),       // more than two (neighbor, multiplier)
+}
+
+impl<B: BasisIdx> PushApplyOutput<B> {
+    pub fn cast<P: Precision>(self) -> PushApplyOutput<B, P> {
+        match self {
+            PushApplyOutput::Nonbranching(bidx, weight) => {
+                PushApplyOutput::Nonbranching(bidx, P::from_complex(weight))
+            }
+            PushApplyOutput::Branching((bidx1, weight1), (bidx2, weight2)) => {
+                PushApplyOutput::Branching(
+                    (bidx1, P::from_complex(weight1)),
+                    (bidx2, P::from_complex(weight2)),
+                )
+            }
+            PushApplyOutput::Multibranching(successors) => PushApplyOutput::Multibranching(
+                successors
+                    .into_iter()
+                    .map(|(bidx, weight)| (bidx, P::from_complex(weight)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl<B: BasisIdx> PullApplyOutput<B> {
+    pub fn cast<P: Precision>(self) -> PullApplyOutput<B, P> {
+        match self {
+            PullApplyOutput::Nonbranching(neighbor, multiplier) => {
+                PullApplyOutput::Nonbranching(neighbor, P::from_complex(multiplier))
+            }
+            PullApplyOutput::Branching((neighbor1, multiplier1), (neighbor2, multiplier2)) => {
+                PullApplyOutput::Branching(
+                    (neighbor1, P::from_complex(multiplier1)),
+                    (neighbor2, P::from_complex(multiplier2)),
+                )
+            }
+            PullApplyOutput::Multibranching(neighbors) => PullApplyOutput::Multibranching(
+                neighbors
+                    .into_iter()
+                    .map(|(neighbor, multiplier)| (neighbor, P::from_complex(multiplier)))
+                    .collect(),
+            ),
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -24,6 +74,16 @@ enum BranchingType {
     MaybeBranching,
 }
 
+/// The basis in which a gate is diagonal on one of the qubits it touches.
+/// Two gates that are diagonal in the same basis on every qubit they share
+/// commute.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum QubitBasis {
+    Z,
+    X,
+    General,
+}
+
 #[derive(Debug, Clone)]
 #[allow(clippy::upper_case_acronyms, dead_code)]
 pub enum GateDefn {
@@ -32,24 +92,62 @@ pub enum GateDefn {
         control2: QubitIndex,
         target: QubitIndex,
     },
+    CH {
+        control: QubitIndex,
+        target: QubitIndex,
+    },
     CPhase {
         control: QubitIndex,
         target: QubitIndex,
         rot: Real,
     },
+    CRX {
+        control: QubitIndex,
+        target: QubitIndex,
+        rot: Real,
+    },
+    CRY {
+        control: QubitIndex,
+        target: QubitIndex,
+        rot: Real,
+    },
+    CRZ {
+        control: QubitIndex,
+        target: QubitIndex,
+        rot: Real,
+    },
     CSwap {
         control: QubitIndex,
         target1: QubitIndex,
         target2: QubitIndex,
     },
+    /// Controlled `e^{i gamma} U(theta, phi, lambda)`
+    CU {
+        control: QubitIndex,
+        target: QubitIndex,
+        theta: Real,
+        phi: Real,
+        lambda: Real,
+        gamma: Real,
+    },
     CX {
         control: QubitIndex,
         target: QubitIndex,
     },
+    CY {
+        control: QubitIndex,
+        target: QubitIndex,
+    },
     CZ {
         control: QubitIndex,
         target: QubitIndex,
     },
+    /// Echoed cross-resonance gate, with `target1` as the least significant
+    /// qubit of its matrix as in Qiskit
+    ECR {
+        target1: QubitIndex,
+        target2: QubitIndex,
+    },
     FSim {
         left: QubitIndex,
         right: QubitIndex,
@@ -57,6 +155,10 @@ pub enum GateDefn {
         phi: Real,
     },
     Hadamard(QubitIndex),
+    ISwap {
+        target1: QubitIndex,
+        target2: QubitIndex,
+    },
     PauliY(QubitIndex),
     PauliZ(QubitIndex),
     Phase {
@@ -67,14 +169,29 @@ pub enum GateDefn {
         rot: Real,
         target: QubitIndex,
     },
+    RXX {
+        rot: Real,
+        target1: QubitIndex,
+        target2: QubitIndex,
+    },
     RY {
         rot: Real,
         target: QubitIndex,
     },
+    RYY {
+        rot: Real,
+        target1: QubitIndex,
+        target2: QubitIndex,
+    },
     RZ {
         rot: Real,
         target: QubitIndex,
     },
+    RZZ {
+        rot: Real,
+        target1: QubitIndex,
+        target2: QubitIndex,
+    },
     S(QubitIndex),
     Sdg(QubitIndex),
     SqrtX(QubitIndex),
@@ -92,15 +209,51 @@ pub enum GateDefn {
         lambda: Real,
     },
     X(QubitIndex),
-    Other {
+    /// A gate given only by its matrix, which acts on
+    /// `matrix.qubit_indices`
+    Unitary {
+        name: String,
+        matrix: UnitaryMatrix,
+    },
+    /// A call of a native gate whose parameters refer to symbols, which are
+    /// bound by `Circuit::bind` before simulation
+    Parameterized {
         name: String,
-        params: Vec<Real>,
+        params: Vec<Expression>,
         args: Vec<QubitIndex>,
     },
+    /// Applies `base` only on the basis indices in which all `controls` are
+    /// set and all `neg_controls` are unset. Use `GateDefn::controlled` to
+    /// build it, which merges nested controls.
+    Controlled {
+        controls: Vec<QubitIndex>,
+        neg_controls: Vec<QubitIndex>,
+        base: Box<GateDefn>,
+    },
+    Measure {
+        target: QubitIndex,
+        clbit: Option<usize>,
+    },
+    Reset(QubitIndex),
+    /// Applies `defn` only if the classical bits `clbits` (least significant
+    /// first) hold `value`
+    Conditional {
+        clbits: Vec<usize>,
+        value: u64,
+        defn: Box<GateDefn>,
+    },
+    /// Keeps the amplitudes in which `target` is `outcome`, scaled by `scale`,
+    /// and drops the others. Simulators use this to collapse the state after
+    /// a measurement.
+    Project {
+        target: QubitIndex,
+        outcome: bool,
+        scale: Real,
+    },
 }
 
 pub trait PushApplicable<B: BasisIdx> {
-    fn push_apply(&self, bidx: B, weight: Complex) -> PushApplyOutput<B>;
+    fn push_apply<P: Precision>(&self, bidx: B, weight: Weight<P>) -> PushApplyOutput<B, P>;
 }
 
 type PullAction<B> = Box<dyn Fn(B) -> PullApplyOutput<B> + Send + Sync>;
@@ -112,6 +265,8 @@ pub struct Gate<B: BasisIdx> {
     pub touches: Vec<QubitIndex>,
     #[derivative(Debug = "ignore")]
     pub pull_action: Option<PullAction<B>>,
+    /// Where the statement the gate was built from starts
+    pub location: Option<Location>,
 }
 
 impl<B: BasisIdx> Gate<B> {
@@ -122,9 +277,15 @@ impl<B: BasisIdx> Gate<B> {
             defn,
             touches,
             pull_action,
+            location: None,
         }
     }
 
+    pub fn at(mut self, location: Option<Location>) -> Self {
+        self.location = location;
+        self
+    }
+
     pub fn is_branching(&self) -> bool {
         self.defn.branching_type() != BranchingType::Nonbranching
         // NOTE: We assume MaybeBranching as Branching
@@ -134,6 +295,10 @@ impl<B: BasisIdx> Gate<B> {
     pub fn is_pullable(&self) -> bool {
         self.pull_action.is_some()
     }
+
+    pub fn pull_apply<P: Precision>(&self, bidx: B) -> PullApplyOutput<B, P> {
+        self.pull_action.as_ref().unwrap()(bidx).cast()
+    }
 }
 
 fn create_touches(defn: &GateDefn) -> Vec<QubitIndex> {
@@ -152,8 +317,32 @@ fn create_touches(defn: &GateDefn) -> Vec<QubitIndex> {
         GateDefn::CPhase {
             control, target, ..
         }
+        | GateDefn::CH { control, target }
+        | GateDefn::CRX {
+            control, target, ..
+        }
+        | GateDefn::CRY {
+            control, target, ..
+        }
+        | GateDefn::CRZ {
+            control, target, ..
+        }
+        | GateDefn::CU {
+            control, target, ..
+        }
+        | GateDefn::CY { control, target }
         | GateDefn::CZ { control, target }
         | GateDefn::CX { control, target } => vec![control, target],
+        GateDefn::Controlled {
+            ref controls,
+            ref neg_controls,
+            ref base,
+        } => {
+            let mut touches = controls.clone();
+            touches.extend(neg_controls);
+            touches.extend(create_touches(base));
+            touches
+        }
         GateDefn::CCX {
             control1,
             control2,
@@ -168,9 +357,25 @@ fn create_touches(defn: &GateDefn) -> Vec<QubitIndex> {
             target1,
             target2,
         } => vec![control, target1, target2],
-        GateDefn::Swap { target1, target2 } => vec![target1, target2],
+        GateDefn::Swap { target1, target2 }
+        | GateDefn::ISwap { target1, target2 }
+        | GateDefn::ECR { target1, target2 }
+        | GateDefn::RXX {
+            target1, target2, ..
+        }
+        | GateDefn::RYY {
+            target1, target2, ..
+        }
+        | GateDefn::RZZ {
+            target1, target2, ..
+        } => vec![target1, target2],
         GateDefn::U { target, .. } => vec![target],
-        GateDefn::Other { .. } => vec![],
+        GateDefn::Unitary { ref matrix, .. } => matrix.qubit_indices.clone(),
+        GateDefn::Parameterized { ref args, .. } => args.clone(),
+        GateDefn::Measure { target, .. }
+        | GateDefn::Reset(target)
+        | GateDefn::Project { target, .. } => vec![target],
+        GateDefn::Conditional { ref defn, .. } => create_touches(defn),
     }
 }
 
@@ -190,7 +395,75 @@ fn create_pull_action<B: BasisIdx>(
         | GateDefn::Sdg(_)
         | GateDefn::T(_)
         | GateDefn::Tdg(_)
-        | GateDefn::X(_) => push_to_pull(defn, touches),
+        | GateDefn::X(_)
+        | GateDefn::ISwap { .. }
+        | GateDefn::RZZ { .. }
+        | GateDefn::Project { .. } => push_to_pull(defn, touches),
+        GateDefn::CH { .. }
+        | GateDefn::CRX { .. }
+        | GateDefn::CRY { .. }
+        | GateDefn::CRZ { .. }
+        | GateDefn::CU { .. }
+        | GateDefn::CY { .. } => {
+            let (control, target, [a, b, c, d]) = defn.controlled_matrix();
+            Some(Box::new(move |bidx| {
+                if bidx.get(control) {
+                    single_qubit_unitary_pull(bidx, target, a, b, c, d)
+                } else {
+                    PullApplyOutput::Nonbranching(bidx, Complex::new(1.0, 0.0))
+                }
+            }))
+        }
+        GateDefn::ECR { target1, target2 } => {
+            let m = Complex::new(constants::RECP_SQRT_2, 0.0);
+            let m_xy = Complex::new(0.0, constants::RECP_SQRT_2);
+            Some(Box::new(move |bidx| {
+                let neighbor_x = bidx.flip(target1);
+                let neighbor_xy = neighbor_x.flip(target2);
+                let m_xy = if bidx.get(target1) { -m_xy } else { m_xy };
+                PullApplyOutput::Branching((neighbor_x, m), (neighbor_xy, m_xy))
+            }))
+        }
+        GateDefn::Controlled {
+            ref controls,
+            ref neg_controls,
+            ref base,
+        } => {
+            // the base gate never touches the controls, so its neighbors
+            // share them with `bidx`
+            let base_pull = create_pull_action::<B>(base, &create_touches(base))?;
+            let controls = controls.clone();
+            let neg_controls = neg_controls.clone();
+            Some(Box::new(move |bidx| {
+                if is_active(&bidx, &controls, &neg_controls) {
+                    base_pull(bidx)
+                } else {
+                    PullApplyOutput::Nonbranching(bidx, Complex::new(1.0, 0.0))
+                }
+            }))
+        }
+        GateDefn::RXX { .. } | GateDefn::RYY { .. } => {
+            let defn = defn.clone();
+            Some(Box::new(move |bidx| {
+                // both gates are symmetric, so the multipliers are the weights
+                // pushed from the neighbors
+                match defn.push_apply(bidx, Complex::new(1.0, 0.0)) {
+                    PushApplyOutput::Nonbranching(neighbor, multiplier) => {
+                        PullApplyOutput::Nonbranching(neighbor, multiplier)
+                    }
+                    PushApplyOutput::Branching(
+                        (neighbor1, multiplier1),
+                        (neighbor2, multiplier2),
+                    ) => PullApplyOutput::Branching(
+                        (neighbor1, multiplier1),
+                        (neighbor2, multiplier2),
+                    ),
+                    PushApplyOutput::Multibranching(neighbors) => {
+                        PullApplyOutput::Multibranching(neighbors)
+                    }
+                }
+            }))
+        }
         GateDefn::CX { control, target } => Some(Box::new(move |bidx| {
             if bidx.get(control) {
                 PullApplyOutput::Nonbranching(bidx.flip(target), Complex::new(1.0, 0.0))
@@ -330,9 +603,31 @@ fn create_pull_action<B: BasisIdx>(
                 single_qubit_unitary_pull(bidx, target, a, b, c, d)
             }))
         }
-        GateDefn::Other { .. } => {
-            unimplemented!()
+        GateDefn::Unitary { ref matrix, .. } => {
+            let qubits = matrix.qubit_indices.clone();
+            let rows: Vec<Vec<(usize, Complex)>> = matrix
+                .mat
+                .row_iter()
+                .map(|row| {
+                    row.iter()
+                        .enumerate()
+                        .filter(|(_, &entry)| utility::is_nonzero(entry))
+                        .map(|(col, &entry)| (col, entry))
+                        .collect()
+                })
+                .collect();
+            Some(Box::new(move |bidx| {
+                let neighbors = rows[local_index(&bidx, &qubits)]
+                    .iter()
+                    .map(|&(col, multiplier)| (with_local_index(&bidx, &qubits, col), multiplier))
+                    .collect();
+                pull_output(neighbors)
+            }))
         }
+        GateDefn::Parameterized { .. }
+        | GateDefn::Measure { .. }
+        | GateDefn::Reset(_)
+        | GateDefn::Conditional { .. } => None,
     }
 }
 
@@ -391,6 +686,85 @@ impl GateDefn {
                 };
                 PushApplyOutput::Nonbranching(bidx, new_weight)
             }
+            GateDefn::CH { .. }
+            | GateDefn::CRX { .. }
+            | GateDefn::CRY { .. }
+            | GateDefn::CRZ { .. }
+            | GateDefn::CU { .. }
+            | GateDefn::CY { .. } => {
+                let (control, target, [a, b, c, d]) = self.controlled_matrix();
+                if bidx.get(control) {
+                    single_qubit_unitary_push(bidx, weight, target, a, b, c, d)
+                } else {
+                    PushApplyOutput::Nonbranching(bidx, weight)
+                }
+            }
+            GateDefn::ECR { target1, target2 } => {
+                // (IX - XY) / sqrt(2)
+                let bidx_x = bidx.flip(target1);
+                let bidx_xy = bidx_x.flip(target2);
+                let new_weight = weight * constants::RECP_SQRT_2;
+                let new_weight_xy = if bidx.get(target1) {
+                    new_weight * Complex::new(0.0, 1.0)
+                } else {
+                    new_weight * Complex::new(0.0, -1.0)
+                };
+                PushApplyOutput::Branching((bidx_x, new_weight), (bidx_xy, new_weight_xy))
+            }
+            GateDefn::ISwap { target1, target2 } => {
+                if bidx.get(target1) == bidx.get(target2) {
+                    PushApplyOutput::Nonbranching(bidx, weight)
+                } else {
+                    let new_bidx = bidx.swap(target1, target2);
+                    PushApplyOutput::Nonbranching(new_bidx, weight * Complex::new(0.0, 1.0))
+                }
+            }
+            GateDefn::Controlled {
+                ref controls,
+                ref neg_controls,
+                ref base,
+            } => {
+                if is_active(&bidx, controls, neg_controls) {
+                    base.push_apply(bidx, weight)
+                } else {
+                    PushApplyOutput::Nonbranching(bidx, weight)
+                }
+            }
+            GateDefn::RXX {
+                rot,
+                target1,
+                target2,
+            } => {
+                let cos = Complex::new((rot / 2.0).cos(), 0.0);
+                let sin = Complex::new(0.0, -(rot / 2.0).sin());
+                two_qubit_rotation_push(bidx, weight, target1, target2, cos, sin)
+            }
+            GateDefn::RYY {
+                rot,
+                target1,
+                target2,
+            } => {
+                // YY picks up a sign when flipping equal bits
+                let cos = Complex::new((rot / 2.0).cos(), 0.0);
+                let sin = if bidx.get(target1) == bidx.get(target2) {
+                    Complex::new(0.0, (rot / 2.0).sin())
+                } else {
+                    Complex::new(0.0, -(rot / 2.0).sin())
+                };
+                two_qubit_rotation_push(bidx, weight, target1, target2, cos, sin)
+            }
+            GateDefn::RZZ {
+                rot,
+                target1,
+                target2,
+            } => {
+                let new_weight = if bidx.get(target1) == bidx.get(target2) {
+                    weight * Complex::new((rot / 2.0).cos(), -(rot / 2.0).sin())
+                } else {
+                    weight * Complex::new((rot / 2.0).cos(), (rot / 2.0).sin())
+                };
+                PushApplyOutput::Nonbranching(bidx, new_weight)
+            }
             GateDefn::FSim {
                 left,
                 right,
@@ -566,7 +940,36 @@ impl GateDefn {
                 let new_bidx = bidx.flip(qi);
                 PushApplyOutput::Nonbranching(new_bidx, weight)
             }
-            GateDefn::Other { .. } => unimplemented!(),
+            GateDefn::Project {
+                target,
+                outcome,
+                scale,
+            } => {
+                let new_weight = if bidx.get(target) == outcome {
+                    weight * scale
+                } else {
+                    Complex::new(0.0, 0.0)
+                };
+                PushApplyOutput::Nonbranching(bidx, new_weight)
+            }
+            GateDefn::Unitary { ref matrix, .. } => {
+                let qubits = &matrix.qubit_indices;
+                let successors = matrix
+                    .mat
+                    .column(local_index(&bidx, qubits))
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &entry)| utility::is_nonzero(entry))
+                    .map(|(row, &entry)| (with_local_index(&bidx, qubits, row), entry * weight))
+                    .collect();
+                push_output(successors)
+            }
+            GateDefn::Parameterized { .. } => {
+                unreachable!("{:?} must be bound before simulation", self)
+            }
+            GateDefn::Measure { .. } | GateDefn::Reset(_) | GateDefn::Conditional { .. } => {
+                unreachable!("{:?} must be resolved by the simulator", self)
+            }
         }
     }
 
@@ -586,18 +989,260 @@ impl GateDefn {
             | GateDefn::Swap { .. }
             | GateDefn::T(_)
             | GateDefn::Tdg(_)
-            | GateDefn::X(_) => BranchingType::Nonbranching,
+            | GateDefn::X(_)
+            | GateDefn::CRZ { .. }
+            | GateDefn::CY { .. }
+            | GateDefn::ISwap { .. }
+            | GateDefn::RZZ { .. }
+            | GateDefn::Measure { .. }
+            | GateDefn::Reset(_)
+            | GateDefn::Project { .. } => BranchingType::Nonbranching,
             GateDefn::Hadamard(_)
             | GateDefn::RY { .. }
             | GateDefn::SqrtX(_)
-            | GateDefn::SqrtXdg(_) => BranchingType::Branching,
-            GateDefn::FSim { .. } | GateDefn::RX { .. } | GateDefn::U { .. } => {
-                BranchingType::MaybeBranching
+            | GateDefn::SqrtXdg(_)
+            | GateDefn::ECR { .. } => BranchingType::Branching,
+            GateDefn::FSim { .. }
+            | GateDefn::RX { .. }
+            | GateDefn::U { .. }
+            | GateDefn::CH { .. }
+            | GateDefn::CRX { .. }
+            | GateDefn::CRY { .. }
+            | GateDefn::CU { .. }
+            | GateDefn::RXX { .. }
+            | GateDefn::RYY { .. } => BranchingType::MaybeBranching,
+            GateDefn::Conditional { defn, .. } => defn.branching_type(),
+            // indices whose controls don't match never branch
+            GateDefn::Controlled { base, .. } => match base.branching_type() {
+                BranchingType::Nonbranching => BranchingType::Nonbranching,
+                _ => BranchingType::MaybeBranching,
+            },
+            GateDefn::Unitary { matrix, .. } => {
+                // permutations with phases, such as compiled oracles, don't
+                // branch
+                let is_monomial = matrix.mat.column_iter().all(|col| {
+                    col.iter()
+                        .filter(|&&entry| utility::is_nonzero(entry))
+                        .count()
+                        == 1
+                });
+                if is_monomial {
+                    BranchingType::Nonbranching
+                } else {
+                    BranchingType::MaybeBranching
+                }
+            }
+            GateDefn::Parameterized { .. } => BranchingType::MaybeBranching,
+        }
+    }
+
+    /// The basis in which the gate is diagonal on `qi`, one of the qubits it
+    /// touches. Controls are diagonal in the Z basis.
+    pub fn qubit_basis(&self, qi: QubitIndex) -> QubitBasis {
+        match self {
+            GateDefn::PauliZ(_)
+            | GateDefn::Phase { .. }
+            | GateDefn::RZ { .. }
+            | GateDefn::S(_)
+            | GateDefn::Sdg(_)
+            | GateDefn::T(_)
+            | GateDefn::Tdg(_)
+            | GateDefn::CPhase { .. }
+            | GateDefn::CRZ { .. }
+            | GateDefn::CZ { .. }
+            | GateDefn::RZZ { .. }
+            | GateDefn::Project { .. } => QubitBasis::Z,
+            GateDefn::X(_)
+            | GateDefn::RX { .. }
+            | GateDefn::SqrtX(_)
+            | GateDefn::SqrtXdg(_)
+            | GateDefn::RXX { .. } => QubitBasis::X,
+            GateDefn::CX { target, .. }
+            | GateDefn::CRX { target, .. }
+            | GateDefn::CCX { target, .. } => {
+                if qi == *target {
+                    QubitBasis::X
+                } else {
+                    QubitBasis::Z
+                }
+            }
+            GateDefn::CH { control, .. }
+            | GateDefn::CRY { control, .. }
+            | GateDefn::CU { control, .. }
+            | GateDefn::CY { control, .. }
+            | GateDefn::CSwap { control, .. } => {
+                if qi == *control {
+                    QubitBasis::Z
+                } else {
+                    QubitBasis::General
+                }
+            }
+            GateDefn::Controlled {
+                controls,
+                neg_controls,
+                base,
+            } => {
+                if controls.contains(&qi) || neg_controls.contains(&qi) {
+                    QubitBasis::Z
+                } else {
+                    base.qubit_basis(qi)
+                }
+            }
+            GateDefn::Unitary { matrix, .. } => {
+                let is_diagonal = matrix.mat.iter().enumerate().all(|(i, entry)| {
+                    i % matrix.mat.nrows() == i / matrix.mat.nrows() || utility::is_zero(*entry)
+                });
+                if is_diagonal {
+                    QubitBasis::Z
+                } else {
+                    QubitBasis::General
+                }
+            }
+            GateDefn::ECR { .. }
+            | GateDefn::FSim { .. }
+            | GateDefn::Hadamard(_)
+            | GateDefn::ISwap { .. }
+            | GateDefn::PauliY(_)
+            | GateDefn::RY { .. }
+            | GateDefn::RYY { .. }
+            | GateDefn::Swap { .. }
+            | GateDefn::U { .. }
+            | GateDefn::Parameterized { .. }
+            | GateDefn::Measure { .. }
+            | GateDefn::Reset(_)
+            | GateDefn::Conditional { .. } => QubitBasis::General,
+        }
+    }
+
+    /// The control, the target and the matrix `[[a, b], [c, d]]` applied to
+    /// the target of a controlled single-qubit gate
+    fn controlled_matrix(&self) -> (QubitIndex, QubitIndex, [Complex; 4]) {
+        let zero = Complex::new(0.0, 0.0);
+        match *self {
+            GateDefn::CH { control, target } => {
+                let h = Complex::new(constants::RECP_SQRT_2, 0.0);
+                (control, target, [h, h, h, -h])
+            }
+            GateDefn::CRX {
+                control,
+                target,
+                rot,
+            } => {
+                let cos = Complex::new((rot / 2.0).cos(), 0.0);
+                let sin = Complex::new(0.0, -(rot / 2.0).sin());
+                (control, target, [cos, sin, sin, cos])
+            }
+            GateDefn::CRY {
+                control,
+                target,
+                rot,
+            } => {
+                let cos = Complex::new((rot / 2.0).cos(), 0.0);
+                let sin = Complex::new((rot / 2.0).sin(), 0.0);
+                (control, target, [cos, -sin, sin, cos])
+            }
+            GateDefn::CRZ {
+                control,
+                target,
+                rot,
+            } => {
+                let a = Complex::new((rot / 2.0).cos(), -(rot / 2.0).sin());
+                let d = Complex::new((rot / 2.0).cos(), (rot / 2.0).sin());
+                (control, target, [a, zero, zero, d])
+            }
+            GateDefn::CU {
+                control,
+                target,
+                theta,
+                phi,
+                lambda,
+                gamma,
+            } => {
+                let global = Complex::new(gamma.cos(), gamma.sin());
+                let cos = Complex::new((theta / 2.0).cos(), 0.0);
+                let sin = Complex::new((theta / 2.0).sin(), 0.0);
+
+                let a = global * cos;
+                let b = -global * sin * Complex::new(lambda.cos(), lambda.sin());
+                let c = global * sin * Complex::new(phi.cos(), phi.sin());
+                let d = global * cos * Complex::new((phi + lambda).cos(), (phi + lambda).sin());
+                (control, target, [a, b, c, d])
+            }
+            GateDefn::CY { control, target } => (
+                control,
+                target,
+                [zero, Complex::new(0.0, -1.0), Complex::new(0.0, 1.0), zero],
+            ),
+            _ => unreachable!("{:?} is not a controlled single-qubit gate", self),
+        }
+    }
+
+    /// Whether the gate depends on or produces measurement outcomes, in which
+    /// case the simulator has to resolve it between unitary segments
+    pub fn is_dynamic(&self) -> bool {
+        matches!(
+            self,
+            GateDefn::Measure { .. } | GateDefn::Reset(_) | GateDefn::Conditional { .. }
+        )
+    }
+
+    /// `base` controlled by `controls` and anti-controlled by `neg_controls`.
+    /// A `base` that is itself `Controlled` is merged into a single gate.
+    pub fn controlled(
+        mut controls: Vec<QubitIndex>,
+        mut neg_controls: Vec<QubitIndex>,
+        base: GateDefn,
+    ) -> GateDefn {
+        match base {
+            GateDefn::Controlled {
+                controls: base_controls,
+                neg_controls: base_neg_controls,
+                base,
+            } => {
+                controls.extend(base_controls);
+                neg_controls.extend(base_neg_controls);
+                GateDefn::Controlled {
+                    controls,
+                    neg_controls,
+                    base,
+                }
             }
-            GateDefn::Other { .. } => unimplemented!(),
+            base => GateDefn::Controlled {
+                controls,
+                neg_controls,
+                base: Box::new(base),
+            },
         }
     }
 
+    /// The matrix of the gate restricted to `qubits`, the first of which
+    /// is the least significant bit, derived by pushing each basis state of
+    /// the qubits through the gate in `B`
+    pub(super) fn local_matrix<B: BasisIdx>(&self, qubits: &[QubitIndex]) -> DMatrix<Complex> {
+        let dim = 1 << qubits.len();
+        let to_bidx = |local: usize| with_local_index(&B::zeros(), qubits, local);
+        let to_local = |bidx: &B| local_index(bidx, qubits);
+
+        let mut mat = DMatrix::from_element(dim, dim, Complex::new(0.0, 0.0));
+        for col in 0..dim {
+            match self.push_apply(to_bidx(col), Complex::new(1.0, 0.0)) {
+                PushApplyOutput::Nonbranching(bidx, weight) => {
+                    mat[(to_local(&bidx), col)] += weight
+                }
+                PushApplyOutput::Branching((bidx0, weight0), (bidx1, weight1)) => {
+                    mat[(to_local(&bidx0), col)] += weight0;
+                    mat[(to_local(&bidx1), col)] += weight1;
+                }
+                PushApplyOutput::Multibranching(successors) => {
+                    for (bidx, weight) in successors {
+                        mat[(to_local(&bidx), col)] += weight;
+                    }
+                }
+            }
+        }
+        mat
+    }
+
     // pub fn gate_to_matrix(&self) -> Option<Array2<Complex>> {
     //     match *self {
     //         GateDefn::X(_) => Some(
@@ -1065,6 +1710,101 @@ fn single_qubit_unitary_push<B: BasisIdx>(
     }
 }
 
+/// The bits of `bidx` at `qubits`, the first of which is the least
+/// significant
+fn local_index<B: BasisIdx>(bidx: &B, qubits: &[QubitIndex]) -> usize {
+    qubits
+        .iter()
+        .enumerate()
+        .filter(|(_, &qi)| bidx.get(qi))
+        .map(|(bit, _)| 1 << bit)
+        .sum()
+}
+
+/// `bidx` with the bits at `qubits` replaced by `local`
+fn with_local_index<B: BasisIdx>(bidx: &B, qubits: &[QubitIndex], local: usize) -> B {
+    qubits
+        .iter()
+        .enumerate()
+        .fold(bidx.clone(), |bidx, (bit, &qi)| {
+            if local >> bit & 1 == 1 {
+                bidx.set(qi)
+            } else {
+                bidx.unset(qi)
+            }
+        })
+}
+
+fn push_output<B: BasisIdx>(mut successors: Vec<(B, Complex)>) -> PushApplyOutput<B> {
+    match successors.len() {
+        1 => {
+            let (bidx, weight) = successors.pop().unwrap();
+            PushApplyOutput::Nonbranching(bidx, weight)
+        }
+        2 => {
+            let second = successors.pop().unwrap();
+            let first = successors.pop().unwrap();
+            PushApplyOutput::Branching(first, second)
+        }
+        _ => PushApplyOutput::Multibranching(successors),
+    }
+}
+
+fn pull_output<B: BasisIdx>(mut neighbors: Vec<(B, Complex)>) -> PullApplyOutput<B> {
+    match neighbors.len() {
+        1 => {
+            let (neighbor, multiplier) = neighbors.pop().unwrap();
+            PullApplyOutput::Nonbranching(neighbor, multiplier)
+        }
+        2 => {
+            let second = neighbors.pop().unwrap();
+            let first = neighbors.pop().unwrap();
+            PullApplyOutput::Branching(first, second)
+        }
+        _ => PullApplyOutput::Multibranching(neighbors),
+    }
+}
+
+fn is_active<B: BasisIdx>(bidx: &B, controls: &[QubitIndex], neg_controls: &[QubitIndex]) -> bool {
+    controls.iter().all(|&qi| bidx.get(qi)) && neg_controls.iter().all(|&qi| !bidx.get(qi))
+}
+
+/// Angles `(gamma, alpha, theta, beta)` such that the matrix `[[a, b], [c, d]]`
+/// is `exp(i gamma) RZ(alpha) RY(theta) RZ(beta)`
+pub(super) fn zyz_angles([a, b, c, d]: [Complex; 4]) -> (Real, Real, Real, Real) {
+    // dividing by a square root of the determinant leaves a special unitary
+    // [[x, -y*], [y, x*]] with x = e^(-i (alpha + beta) / 2) cos(theta / 2)
+    // and y = e^(i (alpha - beta) / 2) sin(theta / 2)
+    let gamma = (a * d - b * c).arg() / 2.0;
+    let phase = Complex::new(gamma.cos(), -gamma.sin());
+    let (x, y) = (a * phase, c * phase);
+
+    let theta = 2.0 * y.norm().atan2(x.norm());
+    let alpha = y.arg() - x.arg();
+    let beta = -y.arg() - x.arg();
+    (gamma, alpha, theta, beta)
+}
+
+/// Pushes through `exp(-i rot/2 P⊗P)` for `P` = X or Y, which maps `bidx`
+/// to `cos * bidx + sin * bidx'` where `bidx'` has both targets flipped
+fn two_qubit_rotation_push<B: BasisIdx>(
+    bidx: B,
+    weight: Complex,
+    target1: QubitIndex,
+    target2: QubitIndex,
+    cos: Complex,
+    sin: Complex,
+) -> PushApplyOutput<B> {
+    let flipped = bidx.flip(target1).flip(target2);
+    if utility::is_zero(sin) {
+        PushApplyOutput::Nonbranching(bidx, cos * weight)
+    } else if utility::is_zero(cos) {
+        PushApplyOutput::Nonbranching(flipped, sin * weight)
+    } else {
+        PushApplyOutput::Branching((bidx, cos * weight), (flipped, sin * weight))
+    }
+}
+
 fn single_qubit_unitary_pull<B: BasisIdx>(
     bidx: B,
     target: QubitIndex,
@@ -1248,8 +1988,10 @@ fn push_to_pull<B: BasisIdx>(defn: &GateDefn, touches: &[QubitIndex]) -> Option<
 }
 
 impl<B: BasisIdx> PushApplicable<B> for Gate<B> {
-    fn push_apply(&self, bidx: B, weight: Complex) -> PushApplyOutput<B> {
-        self.defn.push_apply(bidx, weight)
+    fn push_apply<P: Precision>(&self, bidx: B, weight: Weight<P>) -> PushApplyOutput<B, P> {
+        // gates are applied in `Real` and rounded once to the precision of
+        // the state
+        self.defn.push_apply(bidx, P::to_complex(weight)).cast()
     }
 }
 
@@ -1323,11 +2065,721 @@ impl GateDefn {
         }
     }
 
+    /// Decomposes the phase `exp(i rot x_1 ... x_m)` on `qubits` into CX and
+    /// phase gates, through
+    ///
+    /// `exp(i rot x_1 ... x_m) = prod_S exp(i rot (-1)^(|S|-1) parity(S) / 2^(m-1))`
+    ///
+    /// over the nonempty subsets `S` of the `m` qubits. Each factor computes
+    /// the parity of `S` into its last qubit with CXs and applies a phase
+    /// there. The gate count grows as `2^m`, which is fine for the handful of
+    /// controls seen in practice.
+    fn decompose_mcphase(qubits: &[QubitIndex], rot: Real) -> Vec<GateDefn> {
+        match *qubits {
+            // a global phase
+            [] => vec![],
+            [target] => vec![GateDefn::Phase { rot, target }],
+            [control, target] => vec![GateDefn::CPhase {
+                control,
+                target,
+                rot,
+            }],
+            _ => {
+                let num_qubits = qubits.len();
+                let rot = rot / (1 << (num_qubits - 1)) as Real;
+
+                let mut decomp = vec![];
+                for subset in 1..(1usize << num_qubits) {
+                    let members: Vec<QubitIndex> = (0..num_qubits)
+                        .filter(|i| subset >> i & 1 == 1)
+                        .map(|i| qubits[i])
+                        .collect();
+                    let (&last, rest) = members.split_last().unwrap();
+                    let parity = rest.iter().map(|&control| GateDefn::CX {
+                        control,
+                        target: last,
+                    });
+
+                    decomp.extend(parity.clone());
+                    decomp.push(GateDefn::Phase {
+                        target: last,
+                        rot: if members.len() % 2 == 1 { rot } else { -rot },
+                    });
+                    decomp.extend(parity.rev());
+                }
+                decomp
+            }
+        }
+    }
+
+    /// Decomposes X on `target` controlled by all of `controls`
+    fn decompose_mcx(controls: &[QubitIndex], target: QubitIndex) -> Vec<GateDefn> {
+        match *controls {
+            [] => vec![GateDefn::X(target)],
+            [control] => vec![GateDefn::CX { control, target }],
+            _ => {
+                let mut qubits = controls.to_vec();
+                qubits.push(target);
+
+                let mut decomp = vec![GateDefn::Hadamard(target)];
+                decomp.append(&mut GateDefn::decompose_mcphase(
+                    &qubits,
+                    std::f64::consts::PI,
+                ));
+                decomp.push(GateDefn::Hadamard(target));
+                decomp
+            }
+        }
+    }
+
+    /// Decomposes the matrix `[[a, b], [c, d]]` on `target`, controlled by
+    /// `controls` and anti-controlled by `neg_controls`. Writing the matrix as
+    /// `exp(i gamma) RZ(alpha) RY(theta) RZ(beta)`, it is `A X B X C` with
+    /// `ABC = I` (Barenco et al., 1995), so only the Xs and the phase need
+    /// the controls.
+    fn decompose_controlled_unitary(
+        controls: &[QubitIndex],
+        neg_controls: &[QubitIndex],
+        target: QubitIndex,
+        [a, b, c, d]: [Complex; 4],
+    ) -> Vec<GateDefn> {
+        let flips: Vec<GateDefn> = neg_controls.iter().map(|&qi| GateDefn::X(qi)).collect();
+        let controls: Vec<QubitIndex> = controls.iter().chain(neg_controls).copied().collect();
+        let one = Complex::new(1.0, 0.0);
+
+        let body = if utility::is_zero(a)
+            && utility::is_zero(d)
+            && utility::is_zero(b - one)
+            && utility::is_zero(c - one)
+        {
+            GateDefn::decompose_mcx(&controls, target)
+        } else if utility::is_zero(b) && utility::is_zero(c) && utility::is_zero(a - one) {
+            let mut qubits = controls.clone();
+            qubits.push(target);
+            GateDefn::decompose_mcphase(&qubits, d.arg())
+        } else {
+            let (gamma, alpha, theta, beta) = zyz_angles([a, b, c, d]);
+            match *controls {
+                // the global phase is unobservable without controls
+                [] => vec![GateDefn::U {
+                    target,
+                    theta,
+                    phi: alpha,
+                    lambda: beta,
+                }],
+                [control] => vec![GateDefn::CU {
+                    control,
+                    target,
+                    theta,
+                    phi: alpha,
+                    lambda: beta,
+                    gamma: gamma - (alpha + beta) / 2.0,
+                }],
+                _ => {
+                    let mut decomp = vec![GateDefn::RZ {
+                        rot: (beta - alpha) / 2.0,
+                        target,
+                    }];
+                    decomp.append(&mut GateDefn::decompose_mcx(&controls, target));
+                    decomp.push(GateDefn::RZ {
+                        rot: -(alpha + beta) / 2.0,
+                        target,
+                    });
+                    decomp.push(GateDefn::RY {
+                        rot: -theta / 2.0,
+                        target,
+                    });
+                    decomp.append(&mut GateDefn::decompose_mcx(&controls, target));
+                    decomp.push(GateDefn::RY {
+                        rot: theta / 2.0,
+                        target,
+                    });
+                    decomp.push(GateDefn::RZ { rot: alpha, target });
+                    decomp.append(&mut GateDefn::decompose_mcphase(&controls, gamma));
+                    decomp
+                }
+            }
+        };
+
+        flips
+            .iter()
+            .cloned()
+            .chain(body)
+            .chain(flips.iter().cloned())
+            .collect()
+    }
+
+    /// Decomposes `base` controlled by `controls` and anti-controlled by
+    /// `neg_controls` into gates on at most two qubits.
+    ///
+    /// Controlled single-qubit gates fold into the controls. Other bases are
+    /// split into two-level rotations between basis states that are adjacent
+    /// in Gray code order, which differ in a single qubit, so that each one is
+    /// a single-qubit matrix controlled by the remaining qubits.
+    fn decompose_controlled(
+        controls: &[QubitIndex],
+        neg_controls: &[QubitIndex],
+        base: &GateDefn,
+    ) -> Vec<GateDefn> {
+        let with_controls = |extra: &[QubitIndex]| -> Vec<QubitIndex> {
+            controls.iter().chain(extra).copied().collect()
+        };
+        let zero = Complex::new(0.0, 0.0);
+        let one = Complex::new(1.0, 0.0);
+
+        match *base {
+            GateDefn::Controlled {
+                controls: ref base_controls,
+                neg_controls: ref base_neg_controls,
+                ref base,
+            } => {
+                let neg_controls: Vec<QubitIndex> = neg_controls
+                    .iter()
+                    .chain(base_neg_controls)
+                    .copied()
+                    .collect();
+                return GateDefn::decompose_controlled(
+                    &with_controls(base_controls),
+                    &neg_controls,
+                    base,
+                );
+            }
+            GateDefn::CCX {
+                control1,
+                control2,
+                target,
+            } => {
+                return GateDefn::decompose_controlled(
+                    &with_controls(&[control1, control2]),
+                    neg_controls,
+                    &GateDefn::X(target),
+                )
+            }
+            GateDefn::CPhase {
+                control,
+                target,
+                rot,
+            } => {
+                return GateDefn::decompose_controlled(
+                    &with_controls(&[control]),
+                    neg_controls,
+                    &GateDefn::Phase { rot, target },
+                )
+            }
+            GateDefn::CSwap {
+                control,
+                target1,
+                target2,
+            } => {
+                return GateDefn::decompose_controlled(
+                    &with_controls(&[control]),
+                    neg_controls,
+                    &GateDefn::Swap { target1, target2 },
+                )
+            }
+            GateDefn::CX { control, target } => {
+                return GateDefn::decompose_controlled(
+                    &with_controls(&[control]),
+                    neg_controls,
+                    &GateDefn::X(target),
+                )
+            }
+            GateDefn::CZ { control, target } => {
+                return GateDefn::decompose_controlled(
+                    &with_controls(&[control]),
+                    neg_controls,
+                    &GateDefn::PauliZ(target),
+                )
+            }
+            GateDefn::CH { .. }
+            | GateDefn::CRX { .. }
+            | GateDefn::CRY { .. }
+            | GateDefn::CRZ { .. }
+            | GateDefn::CU { .. }
+            | GateDefn::CY { .. } => {
+                let (control, target, matrix) = base.controlled_matrix();
+                return GateDefn::decompose_controlled_unitary(
+                    &with_controls(&[control]),
+                    neg_controls,
+                    target,
+                    matrix,
+                );
+            }
+            _ => (),
+        }
+
+        // the qubits keep their indices in the circuit, which may be past 64
+        let qubits = create_touches(base);
+        let mat = base.local_matrix::<BasisIdxUnlimited>(&qubits);
+        if let [target] = *qubits {
+            let matrix = [mat[(0, 0)], mat[(0, 1)], mat[(1, 0)], mat[(1, 1)]];
+            return GateDefn::decompose_controlled_unitary(controls, neg_controls, target, matrix);
+        }
+
+        let dim = mat.nrows();
+        let gray = |i: usize| i ^ (i >> 1);
+        let mut reordered: Vec<Vec<Complex>> = (0..dim)
+            .map(|i| (0..dim).map(|j| mat[(gray(i), gray(j))]).collect())
+            .collect();
+
+        // rotations G_1, G_2, ... of adjacent rows such that ... G_2 G_1 M is
+        // upper triangular, hence diagonal as it is unitary
+        let mut rotations = vec![];
+        for col in 0..dim {
+            for row in (col + 1..dim).rev() {
+                let (x, y) = (reordered[row - 1][col], reordered[row][col]);
+                if utility::is_zero(y) {
+                    continue;
+                }
+                let norm = (x.norm_sqr() + y.norm_sqr()).sqrt();
+                let g = [x.conj() / norm, y.conj() / norm, -y / norm, x / norm];
+                let (upper, lower) = reordered.split_at_mut(row);
+                for (u, v) in upper[row - 1].iter_mut().zip(lower[0].iter_mut()) {
+                    (*u, *v) = (g[0] * *u + g[1] * *v, g[2] * *u + g[3] * *v);
+                }
+                rotations.push((row, g));
+            }
+        }
+
+        // M = G_1^dag G_2^dag ... D, so the diagonal D is applied first. Each
+        // step is a matrix on the qubit `bit`, conditioned on the other
+        // qubits matching `state`.
+        let mut steps: Vec<(usize, usize, [Complex; 4])> = vec![];
+        for (i, row) in reordered.iter().enumerate() {
+            let phase = row[i];
+            if !utility::is_zero(phase - one) {
+                let state = gray(i);
+                let matrix = if state & 1 == 1 {
+                    [one, zero, zero, phase]
+                } else {
+                    [phase, zero, zero, one]
+                };
+                steps.push((state, 0, matrix));
+            }
+        }
+        for (row, [g00, g01, g10, g11]) in rotations.into_iter().rev() {
+            let (state, other) = (gray(row - 1), gray(row));
+            let bit = (state ^ other).trailing_zeros() as usize;
+            let dagger = [g00.conj(), g10.conj(), g01.conj(), g11.conj()];
+            let matrix = if state >> bit & 1 == 0 {
+                dagger
+            } else {
+                let [m00, m01, m10, m11] = dagger;
+                [m11, m10, m01, m00]
+            };
+            steps.push((state, bit, matrix));
+        }
+
+        steps
+            .into_iter()
+            .flat_map(|(state, bit, matrix)| {
+                let (set, unset): (Vec<usize>, Vec<usize>) = (0..qubits.len())
+                    .filter(|&i| i != bit)
+                    .partition(|&i| state >> i & 1 == 1);
+                let controls = with_controls(&set.iter().map(|&i| qubits[i]).collect::<Vec<_>>());
+                let neg_controls: Vec<QubitIndex> = neg_controls
+                    .iter()
+                    .copied()
+                    .chain(unset.iter().map(|&i| qubits[i]))
+                    .collect();
+                GateDefn::decompose_controlled_unitary(
+                    &controls,
+                    &neg_controls,
+                    qubits[bit],
+                    matrix,
+                )
+            })
+            .collect()
+    }
+
     pub fn decompose_gate(&self) -> Vec<GateDefn> {
         match self {
             GateDefn::CCX { .. } => GateDefn::decompose_ccx(self),
             GateDefn::CSwap { .. } => GateDefn::decompose_cswap(self),
+            GateDefn::Controlled {
+                controls,
+                neg_controls,
+                base,
+            } => GateDefn::decompose_controlled(controls, neg_controls, base),
+            GateDefn::Unitary { matrix, .. } if matrix.qubit_indices.len() > 2 => {
+                GateDefn::decompose_controlled(&[], &[], self)
+            }
+            GateDefn::Conditional {
+                clbits,
+                value,
+                defn,
+            } => defn
+                .decompose_gate()
+                .into_iter()
+                .map(|defn| GateDefn::Conditional {
+                    clbits: clbits.clone(),
+                    value: *value,
+                    defn: Box::new(defn),
+                })
+                .collect(),
             _ => vec![self.clone()],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::BasisIdx64;
+
+    const NUM_QUBITS: usize = 4;
+
+    /// `mat[row][col]` of a sequence of gates on `NUM_QUBITS` qubits, through
+    /// their push actions
+    fn push_matrix(defns: &[GateDefn]) -> Vec<Vec<Complex>> {
+        let dim = 1 << NUM_QUBITS;
+        let columns: Vec<Vec<Complex>> = (0..dim)
+            .map(|col| {
+                let mut column = vec![Complex::new(0.0, 0.0); dim];
+                column[col] = Complex::new(1.0, 0.0);
+                for defn in defns {
+                    let mut next = vec![Complex::new(0.0, 0.0); dim];
+                    for (idx, &weight) in column.iter().enumerate() {
+                        match defn.push_apply(BasisIdx64::from_idx(idx), weight) {
+                            PushApplyOutput::Nonbranching(bidx, weight) => {
+                                next[bidx.as_idx()] += weight
+                            }
+                            PushApplyOutput::Branching((bidx0, weight0), (bidx1, weight1)) => {
+                                next[bidx0.as_idx()] += weight0;
+                                next[bidx1.as_idx()] += weight1;
+                            }
+                            PushApplyOutput::Multibranching(successors) => {
+                                for (bidx, weight) in successors {
+                                    next[bidx.as_idx()] += weight;
+                                }
+                            }
+                        }
+                    }
+                    column = next;
+                }
+                column
+            })
+            .collect();
+        (0..dim)
+            .map(|row| columns.iter().map(|column| column[row]).collect())
+            .collect()
+    }
+
+    fn pull_matrix(gate: &Gate<BasisIdx64>) -> Vec<Vec<Complex>> {
+        let dim = 1 << NUM_QUBITS;
+        let pull_action = gate.pull_action.as_ref().unwrap();
+        let mut mat = vec![vec![Complex::new(0.0, 0.0); dim]; dim];
+        for (row, entries) in mat.iter_mut().enumerate() {
+            match pull_action(BasisIdx64::from_idx(row)) {
+                PullApplyOutput::Nonbranching(neighbor, multiplier) => {
+                    entries[neighbor.as_idx()] += multiplier
+                }
+                PullApplyOutput::Branching((neighbor1, m1), (neighbor2, m2)) => {
+                    entries[neighbor1.as_idx()] += m1;
+                    entries[neighbor2.as_idx()] += m2;
+                }
+                PullApplyOutput::Multibranching(neighbors) => {
+                    for (neighbor, m) in neighbors {
+                        entries[neighbor.as_idx()] += m;
+                    }
+                }
+            }
+        }
+        mat
+    }
+
+    fn assert_close(expected: &[Vec<Complex>], actual: &[Vec<Complex>], defn: &GateDefn) {
+        for (expected_row, actual_row) in expected.iter().zip(actual) {
+            for (expected, actual) in expected_row.iter().zip(actual_row) {
+                assert!(
+                    (expected - actual).norm() < 1e-5,
+                    "{:?}: expected {}, actual {}",
+                    defn,
+                    expected,
+                    actual
+                );
+            }
+        }
+    }
+
+    fn extended_gates() -> Vec<GateDefn> {
+        vec![
+            GateDefn::CH {
+                control: 2,
+                target: 0,
+            },
+            GateDefn::CRX {
+                control: 0,
+                target: 3,
+                rot: 0.7,
+            },
+            GateDefn::CRY {
+                control: 1,
+                target: 2,
+                rot: -1.3,
+            },
+            GateDefn::CRZ {
+                control: 3,
+                target: 1,
+                rot: 2.1,
+            },
+            GateDefn::CU {
+                control: 0,
+                target: 1,
+                theta: 0.4,
+                phi: 1.1,
+                lambda: -0.6,
+                gamma: 0.3,
+            },
+            GateDefn::CY {
+                control: 1,
+                target: 3,
+            },
+            GateDefn::ECR {
+                target1: 2,
+                target2: 0,
+            },
+            GateDefn::ISwap {
+                target1: 0,
+                target2: 2,
+            },
+            GateDefn::controlled(vec![0, 1, 3], vec![], GateDefn::X(2)),
+            GateDefn::RXX {
+                rot: 0.9,
+                target1: 1,
+                target2: 2,
+            },
+            GateDefn::RYY {
+                rot: -0.5,
+                target1: 3,
+                target2: 0,
+            },
+            GateDefn::RZZ {
+                rot: 1.7,
+                target1: 0,
+                target2: 1,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_extended_gates_pull_matches_push() {
+        for defn in extended_gates() {
+            let gate = Gate::<BasisIdx64>::new(defn.clone());
+            let mat = push_matrix(std::slice::from_ref(&defn));
+
+            // unitary
+            let dim = 1 << NUM_QUBITS;
+            for i in 0..dim {
+                for j in 0..dim {
+                    let dot: Complex = (0..dim).map(|k| mat[k][i].conj() * mat[k][j]).sum();
+                    let expected = if i == j { 1.0 } else { 0.0 };
+                    assert!((dot - expected).norm() < 1e-5, "{:?} is not unitary", defn);
+                }
+            }
+
+            assert_close(&mat, &pull_matrix(&gate), &defn);
+        }
+    }
+
+    #[test]
+    fn test_extended_gates_decompose() {
+        for defn in extended_gates() {
+            let decomp = defn.decompose_gate();
+            assert!(decomp.iter().all(|defn| create_touches(defn).len() <= 2));
+            assert_close(
+                &push_matrix(std::slice::from_ref(&defn)),
+                &push_matrix(&decomp),
+                &defn,
+            );
+        }
+    }
+
+    fn controlled_gates() -> Vec<GateDefn> {
+        vec![
+            GateDefn::controlled(vec![0], vec![2], GateDefn::Hadamard(1)),
+            GateDefn::controlled(vec![], vec![0, 1, 2], GateDefn::PauliY(3)),
+            GateDefn::controlled(
+                vec![0, 1],
+                vec![2],
+                GateDefn::Phase {
+                    rot: 0.8,
+                    target: 3,
+                },
+            ),
+            GateDefn::controlled(
+                vec![3],
+                vec![],
+                GateDefn::U {
+                    target: 1,
+                    theta: 1.2,
+                    phi: -0.4,
+                    lambda: 2.5,
+                },
+            ),
+            GateDefn::controlled(
+                vec![1, 3],
+                vec![],
+                GateDefn::CU {
+                    control: 0,
+                    target: 2,
+                    theta: 0.4,
+                    phi: 1.1,
+                    lambda: -0.6,
+                    gamma: 0.3,
+                },
+            ),
+            GateDefn::controlled(
+                vec![2],
+                vec![0],
+                GateDefn::Swap {
+                    target1: 1,
+                    target2: 3,
+                },
+            ),
+            GateDefn::controlled(
+                vec![0],
+                vec![],
+                GateDefn::ECR {
+                    target1: 1,
+                    target2: 3,
+                },
+            ),
+            GateDefn::controlled(
+                vec![3],
+                vec![],
+                GateDefn::controlled(
+                    vec![],
+                    vec![2],
+                    GateDefn::FSim {
+                        left: 0,
+                        right: 1,
+                        theta: 0.3,
+                        phi: 1.4,
+                    },
+                ),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_controlled_gates() {
+        let merged = GateDefn::controlled(
+            vec![0],
+            vec![1],
+            GateDefn::controlled(vec![2], vec![], GateDefn::X(3)),
+        );
+        assert_eq!(
+            create_touches(&merged),
+            vec![0, 2, 1, 3],
+            "nested controls are merged"
+        );
+        assert_eq!(merged.branching_type(), BranchingType::Nonbranching);
+
+        for defn in controlled_gates() {
+            let gate = Gate::<BasisIdx64>::new(defn.clone());
+            let mat = push_matrix(std::slice::from_ref(&defn));
+            // pullable exactly when the base gate is
+            if gate.is_pullable() {
+                assert_close(&mat, &pull_matrix(&gate), &defn);
+            }
+
+            let decomp = defn.decompose_gate();
+            assert!(decomp.iter().all(|defn| create_touches(defn).len() <= 2));
+            assert_close(&mat, &push_matrix(&decomp), &defn);
+        }
+    }
+    #[test]
+    fn test_controlled_gates_past_64_qubits() {
+        // 1 and 65 are the same bit of a 64-bit basis index
+        let qubits = [1, 65, 70];
+        let defn = GateDefn::controlled(
+            vec![70],
+            vec![],
+            GateDefn::Swap {
+                target1: 1,
+                target2: 65,
+            },
+        );
+        let decomp = defn.decompose_gate();
+        assert!(decomp.iter().all(|defn| create_touches(defn).len() <= 2));
+
+        let actual = decomp.iter().fold(DMatrix::identity(8, 8), |mat, defn| {
+            defn.local_matrix::<BasisIdxUnlimited>(&qubits) * mat
+        });
+        let expected = defn.local_matrix::<BasisIdxUnlimited>(&qubits);
+        assert!((actual - expected).norm() < 1e-5, "{:?}", defn);
+    }
+
+    #[test]
+    fn test_unitary_gates() {
+        let dense = DMatrix::from_fn(8, 8, |row, col| {
+            Complex::new(
+                ((3 * row + col) as Real).sin(),
+                ((row + 2 * col) as Real).cos(),
+            )
+        })
+        .qr()
+        .q();
+        let permutation = DMatrix::from_row_slice(
+            2,
+            2,
+            &[
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 1.0),
+                Complex::new(1.0, 0.0),
+                Complex::new(0.0, 0.0),
+            ],
+        );
+
+        for (mat, qubit_indices) in [(dense, vec![3, 0, 2]), (permutation, vec![1])] {
+            let dim = 1 << NUM_QUBITS;
+            let local = |idx: usize| {
+                qubit_indices
+                    .iter()
+                    .enumerate()
+                    .map(|(bit, &qubit)| ((idx >> qubit) & 1) << bit)
+                    .sum::<usize>()
+            };
+            let mask: usize = qubit_indices.iter().map(|&qubit| 1 << qubit).sum();
+            let expected: Vec<Vec<Complex>> = (0..dim)
+                .map(|row| {
+                    (0..dim)
+                        .map(|col| {
+                            if row & !mask == col & !mask {
+                                mat[(local(row), local(col))]
+                            } else {
+                                Complex::new(0.0, 0.0)
+                            }
+                        })
+                        .collect()
+                })
+                .collect();
+
+            let defn = GateDefn::Unitary {
+                name: "u".to_string(),
+                matrix: UnitaryMatrix {
+                    mat,
+                    qubit_indices: qubit_indices.clone(),
+                },
+            };
+            assert_eq!(create_touches(&defn), qubit_indices);
+            let gate = Gate::<BasisIdx64>::new(defn.clone());
+            assert_close(&expected, &push_matrix(std::slice::from_ref(&defn)), &defn);
+            assert_close(&expected, &pull_matrix(&gate), &defn);
+
+            let decomp = defn.decompose_gate();
+            assert!(decomp.iter().all(|defn| create_touches(defn).len() <= 2));
+            assert_close(&expected, &push_matrix(&decomp), &defn);
+        }
+
+        let single = GateDefn::Unitary {
+            name: "u".to_string(),
+            matrix: UnitaryMatrix {
+                mat: DMatrix::identity(2, 2),
+                qubit_indices: vec![0],
+            },
+        };
+        assert_eq!(single.branching_type(), BranchingType::Nonbranching);
+    }
+}
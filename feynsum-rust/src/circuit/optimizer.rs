@@ -0,0 +1,572 @@
+//! A peephole optimizer for bound circuits.
+//!
+//! Two gates are adjacent if no gate in between touches any of their qubits.
+//! Adjacent inverse pairs are cancelled and adjacent rotations about the same
+//! axis are merged, then runs of single-qubit gates that contain a branching
+//! gate are folded into at most two gates. Folding drops the global phase of
+//! the run, so the optimized circuit agrees with the original up to a global
+//! phase.
+
+use std::collections::HashMap;
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+use std::fmt::{self, Display, Formatter};
+
+use super::gate::zyz_angles;
+use super::{Gate, GateDefn, Unitary};
+use crate::error::Location;
+use crate::types::{constants, BasisIdx, Complex, QubitIndex, Real};
+use crate::utility;
+
+/// The number of gates removed, by how
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OptimizationReport {
+    /// Adjacent pairs of mutually inverse gates
+    pub cancelled: usize,
+    /// Adjacent rotations about the same axis merged into one
+    pub merged: usize,
+    /// Rotations by a multiple of their period
+    pub identities: usize,
+    /// Runs of single-qubit gates folded into at most two gates
+    pub folded: usize,
+}
+
+impl OptimizationReport {
+    pub fn num_removed(&self) -> usize {
+        self.cancelled + self.merged + self.identities + self.folded
+    }
+}
+
+impl Display for OptimizationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "removed {} gates ({} cancelled, {} merged, {} identities, {} folded)",
+            self.num_removed(),
+            self.cancelled,
+            self.merged,
+            self.identities,
+            self.folded
+        )
+    }
+}
+
+type Located = (GateDefn, Option<Location>);
+
+pub fn optimize<B: BasisIdx>(
+    num_qubits: usize,
+    gates: &[Gate<B>],
+) -> (Vec<Gate<B>>, OptimizationReport) {
+    let mut report = OptimizationReport::default();
+
+    let gates = cancel_and_merge(num_qubits, gates, &mut report);
+    let gates = fold_single_qubit_runs::<B>(num_qubits, gates, &mut report);
+
+    let gates = gates
+        .into_iter()
+        .map(|(defn, location)| Gate::new(defn).at(location))
+        .collect();
+    (gates, report)
+}
+
+fn cancel_and_merge<B: BasisIdx>(
+    num_qubits: usize,
+    gates: &[Gate<B>],
+    report: &mut OptimizationReport,
+) -> Vec<Located> {
+    let mut optimized: Vec<Option<(Located, &[QubitIndex])>> = Vec::with_capacity(gates.len());
+    // the gates in `optimized` that touch each qubit, the last one on top
+    let mut stacks: Vec<Vec<usize>> = vec![vec![]; num_qubits];
+
+    for gate in gates {
+        if is_identity(&gate.defn) {
+            report.identities += 1;
+            continue;
+        }
+
+        // the gate this one is adjacent to on all its qubits, if it touches
+        // the same ones
+        let top = stacks[gate.touches[0]].last().copied();
+        let combined = top
+            .filter(|&gi| {
+                gate.touches
+                    .iter()
+                    .all(|&qi| stacks[qi].last() == Some(&gi))
+                    && optimized[gi].as_ref().unwrap().1.len() == gate.touches.len()
+            })
+            .and_then(|gi| {
+                let ((prev, _), _) = optimized[gi].as_ref().unwrap();
+                combine(prev, &gate.defn).map(|defn| (gi, defn))
+            });
+
+        match combined {
+            Some((gi, None)) => {
+                report.cancelled += 2;
+                optimized[gi] = None;
+                for &qi in &gate.touches {
+                    stacks[qi].pop();
+                }
+            }
+            Some((gi, Some(defn))) => {
+                report.merged += 1;
+                optimized[gi].as_mut().unwrap().0 .0 = defn;
+            }
+            None => {
+                for &qi in &gate.touches {
+                    stacks[qi].push(optimized.len());
+                }
+                optimized.push(Some((
+                    (gate.defn.clone(), gate.location),
+                    gate.touches.as_slice(),
+                )));
+            }
+        }
+    }
+
+    optimized
+        .into_iter()
+        .flatten()
+        .map(|(located, _)| located)
+        .collect()
+}
+
+/// The gate equivalent to `first` followed by `second`, which touch the
+/// same qubits: `Some(None)` if they cancel out, `None` if they do not
+/// combine
+fn combine(first: &GateDefn, second: &GateDefn) -> Option<Option<GateDefn>> {
+    if let (Some((target1, rot1)), Some((target2, rot2))) =
+        (phase_rotation(first), phase_rotation(second))
+    {
+        assert_eq!(target1, target2);
+        return Some(phase_gate(target1, rot1 + rot2));
+    }
+
+    let rotation = |defn: GateDefn| Some(Some(defn).filter(|defn| !is_identity(defn)));
+    match (first, second) {
+        (GateDefn::Hadamard(_), GateDefn::Hadamard(_))
+        | (GateDefn::X(_), GateDefn::X(_))
+        | (GateDefn::PauliY(_), GateDefn::PauliY(_))
+        | (GateDefn::SqrtX(_), GateDefn::SqrtXdg(_))
+        | (GateDefn::SqrtXdg(_), GateDefn::SqrtX(_))
+        | (GateDefn::CZ { .. }, GateDefn::CZ { .. })
+        | (GateDefn::Swap { .. }, GateDefn::Swap { .. }) => Some(None),
+        (
+            GateDefn::CX { control, target },
+            GateDefn::CX {
+                control: control2,
+                target: target2,
+            },
+        )
+        | (
+            GateDefn::CY { control, target },
+            GateDefn::CY {
+                control: control2,
+                target: target2,
+            },
+        )
+        | (
+            GateDefn::CH { control, target },
+            GateDefn::CH {
+                control: control2,
+                target: target2,
+            },
+        ) if control == control2 && target == target2 => Some(None),
+        (&GateDefn::RX { rot, target }, &GateDefn::RX { rot: rot2, .. }) => {
+            rotation(GateDefn::RX {
+                rot: rot + rot2,
+                target,
+            })
+        }
+        (&GateDefn::RY { rot, target }, &GateDefn::RY { rot: rot2, .. }) => {
+            rotation(GateDefn::RY {
+                rot: rot + rot2,
+                target,
+            })
+        }
+        (&GateDefn::RZ { rot, target }, &GateDefn::RZ { rot: rot2, .. }) => {
+            rotation(GateDefn::RZ {
+                rot: rot + rot2,
+                target,
+            })
+        }
+        (
+            &GateDefn::RXX {
+                rot,
+                target1,
+                target2,
+            },
+            &GateDefn::RXX { rot: rot2, .. },
+        ) => rotation(GateDefn::RXX {
+            rot: rot + rot2,
+            target1,
+            target2,
+        }),
+        (
+            &GateDefn::RYY {
+                rot,
+                target1,
+                target2,
+            },
+            &GateDefn::RYY { rot: rot2, .. },
+        ) => rotation(GateDefn::RYY {
+            rot: rot + rot2,
+            target1,
+            target2,
+        }),
+        (
+            &GateDefn::RZZ {
+                rot,
+                target1,
+                target2,
+            },
+            &GateDefn::RZZ { rot: rot2, .. },
+        ) => rotation(GateDefn::RZZ {
+            rot: rot + rot2,
+            target1,
+            target2,
+        }),
+        (
+            &GateDefn::CPhase {
+                control,
+                target,
+                rot,
+            },
+            &GateDefn::CPhase { rot: rot2, .. },
+        ) => rotation(GateDefn::CPhase {
+            control,
+            target,
+            rot: rot + rot2,
+        }),
+        (
+            &GateDefn::CRX {
+                control,
+                target,
+                rot,
+            },
+            &GateDefn::CRX {
+                control: control2,
+                rot: rot2,
+                ..
+            },
+        ) if control == control2 => rotation(GateDefn::CRX {
+            control,
+            target,
+            rot: rot + rot2,
+        }),
+        (
+            &GateDefn::CRY {
+                control,
+                target,
+                rot,
+            },
+            &GateDefn::CRY {
+                control: control2,
+                rot: rot2,
+                ..
+            },
+        ) if control == control2 => rotation(GateDefn::CRY {
+            control,
+            target,
+            rot: rot + rot2,
+        }),
+        (
+            &GateDefn::CRZ {
+                control,
+                target,
+                rot,
+            },
+            &GateDefn::CRZ {
+                control: control2,
+                rot: rot2,
+                ..
+            },
+        ) if control == control2 => rotation(GateDefn::CRZ {
+            control,
+            target,
+            rot: rot + rot2,
+        }),
+        _ => None,
+    }
+}
+
+/// The target and angle of the gates that are `diag(1, e^(i rot))`
+fn phase_rotation(defn: &GateDefn) -> Option<(QubitIndex, Real)> {
+    match *defn {
+        GateDefn::PauliZ(target) => Some((target, PI)),
+        GateDefn::S(target) => Some((target, FRAC_PI_2)),
+        GateDefn::Sdg(target) => Some((target, -FRAC_PI_2)),
+        GateDefn::T(target) => Some((target, FRAC_PI_4)),
+        GateDefn::Tdg(target) => Some((target, -FRAC_PI_4)),
+        GateDefn::Phase { rot, target } => Some((target, rot)),
+        _ => None,
+    }
+}
+
+/// `diag(1, e^(i rot))` by its name if it has one, or `None` if it is the
+/// identity
+fn phase_gate(target: QubitIndex, rot: Real) -> Option<GateDefn> {
+    let rot = rot.rem_euclid(2.0 * PI);
+    let is = |angle: Real| is_multiple_of(rot - angle, 2.0 * PI);
+    if is(0.0) {
+        None
+    } else if is(PI) {
+        Some(GateDefn::PauliZ(target))
+    } else if is(FRAC_PI_2) {
+        Some(GateDefn::S(target))
+    } else if is(-FRAC_PI_2) {
+        Some(GateDefn::Sdg(target))
+    } else if is(FRAC_PI_4) {
+        Some(GateDefn::T(target))
+    } else if is(-FRAC_PI_4) {
+        Some(GateDefn::Tdg(target))
+    } else {
+        Some(GateDefn::Phase { rot, target })
+    }
+}
+
+/// Rotations are `exp(-i rot / 2 P)`, which is only the identity for
+/// multiples of `4 pi`
+fn is_identity(defn: &GateDefn) -> bool {
+    match *defn {
+        GateDefn::RX { rot, .. }
+        | GateDefn::RY { rot, .. }
+        | GateDefn::RZ { rot, .. }
+        | GateDefn::RXX { rot, .. }
+        | GateDefn::RYY { rot, .. }
+        | GateDefn::RZZ { rot, .. }
+        | GateDefn::CRX { rot, .. }
+        | GateDefn::CRY { rot, .. }
+        | GateDefn::CRZ { rot, .. } => is_multiple_of(rot, 4.0 * PI),
+        GateDefn::Phase { rot, .. } | GateDefn::CPhase { rot, .. } => is_multiple_of(rot, 2.0 * PI),
+        _ => false,
+    }
+}
+
+fn is_multiple_of(angle: Real, period: Real) -> bool {
+    let rem = angle.rem_euclid(period);
+    rem < constants::ZERO_THRESHOLD || period - rem < constants::ZERO_THRESHOLD
+}
+
+fn fold_single_qubit_runs<B: BasisIdx>(
+    num_qubits: usize,
+    gates: Vec<Located>,
+    report: &mut OptimizationReport,
+) -> Vec<Located> {
+    let gates = gates
+        .into_iter()
+        .map(|(defn, location)| Gate::<B>::new(defn).at(location))
+        .collect::<Vec<_>>();
+
+    // the single-qubit gates since the last other gate on each qubit
+    let mut runs: Vec<Vec<usize>> = vec![vec![]; num_qubits];
+    // runs to be replaced, by the index of their last gate
+    let mut folds: HashMap<usize, Vec<GateDefn>> = HashMap::new();
+    let mut folded = vec![false; gates.len()];
+
+    let mut fold = |run: &mut Vec<usize>| {
+        let run = std::mem::take(run);
+        if run.len() < 2 || !run.iter().any(|&gi| gates[gi].is_branching()) {
+            return;
+        }
+
+        let mat = run
+            .iter()
+            .fold(nalgebra::DMatrix::identity(2, 2), |mat, &gi| {
+                gates[gi].unitary().mat * mat
+            });
+        let replacement = single_qubit_gates(
+            gates[run[0]].touches[0],
+            [mat[(0, 0)], mat[(0, 1)], mat[(1, 0)], mat[(1, 1)]],
+        );
+
+        report.folded += run.len() - replacement.len();
+        for &gi in &run {
+            folded[gi] = true;
+        }
+        folds.insert(*run.last().unwrap(), replacement);
+    };
+
+    for (gi, gate) in gates.iter().enumerate() {
+        if gate.touches.len() == 1 && !gate.defn.is_dynamic() {
+            runs[gate.touches[0]].push(gi);
+        } else {
+            for &qi in &gate.touches {
+                fold(&mut runs[qi]);
+            }
+        }
+    }
+    for run in &mut runs {
+        fold(run);
+    }
+
+    gates
+        .into_iter()
+        .enumerate()
+        .flat_map(|(gi, gate)| {
+            let location = gate.location;
+            let defns = match folds.remove(&gi) {
+                Some(defns) => defns,
+                None if folded[gi] => vec![],
+                None => vec![gate.defn],
+            };
+            defns.into_iter().map(move |defn| (defn, location))
+        })
+        .collect()
+}
+
+/// Gates that apply `[[a, b], [c, d]]` to `target` up to a global phase,
+/// which are nonbranching unless the matrix branches
+fn single_qubit_gates(target: QubitIndex, [a, b, c, d]: [Complex; 4]) -> Vec<GateDefn> {
+    if utility::is_zero(b) && utility::is_zero(c) {
+        phase_gate(target, d.arg() - a.arg()).into_iter().collect()
+    } else if utility::is_zero(a) && utility::is_zero(d) {
+        // X diag(c, b)
+        phase_gate(target, b.arg() - c.arg())
+            .into_iter()
+            .chain([GateDefn::X(target)])
+            .collect()
+    } else {
+        let (_, alpha, theta, beta) = zyz_angles([a, b, c, d]);
+        vec![GateDefn::U {
+            target,
+            theta,
+            phi: alpha,
+            lambda: beta,
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Circuit;
+    use crate::config::Config;
+    use crate::gate_scheduler::ScheduleCursor;
+    use crate::parser;
+    use crate::simulator::{sequential_simulator, Compactifiable};
+    use crate::types::BasisIdx64;
+
+    fn circuit(source: &str) -> Circuit<BasisIdx64> {
+        Circuit::new(parser::parse_program(source).unwrap()).unwrap()
+    }
+
+    fn state(circuit: Circuit<BasisIdx64>) -> HashMap<BasisIdx64, Complex> {
+        let (state, _) = sequential_simulator::run::<_, f64>(
+            &Config::default(),
+            circuit,
+            &ScheduleCursor::default(),
+        )
+        .unwrap();
+        state.compactify().collect()
+    }
+
+    /// Asserts that the circuits prepare the same state up to a global phase
+    fn assert_same_state(original: Circuit<BasisIdx64>, optimized: Circuit<BasisIdx64>) {
+        let expected = state(original);
+        let actual = state(optimized);
+        let weight = |state: &HashMap<BasisIdx64, Complex>, bidx| {
+            state.get(bidx).copied().unwrap_or_default()
+        };
+
+        // the global phase is the overlap of the states if they are equal up
+        // to it
+        let phase: Complex = expected
+            .iter()
+            .map(|(bidx, w)| w.conj() * weight(&actual, bidx))
+            .sum();
+        assert!((phase.norm() - 1.0).abs() < 1e-10, "overlap: {}", phase);
+
+        for bidx in expected.keys().chain(actual.keys()) {
+            let (expected, actual) = (weight(&expected, bidx), weight(&actual, bidx));
+            assert!(
+                (actual - expected * phase).norm() < 1e-10,
+                "{}: expected {}, actual {}",
+                bidx,
+                expected * phase,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn test_cancel_and_merge() {
+        let source = r#"
+        OPENQASM 2.0;
+        include "qelib1.inc";
+        qreg q[3];
+        h q[0];
+        x q[1];
+        x q[0];
+        cx q[1],q[2];
+        x q[0];
+        cx q[1],q[2];
+        h q[0];
+        s q[1];
+        sdg q[1];
+        t q[2];
+        t q[2];
+        rz(0.25) q[1];
+        rz(0.5) q[1];
+        rx(0) q[2];
+        cx q[1],q[2];
+        "#;
+        let original = circuit(source);
+        let (optimized, report) = original.optimize();
+
+        assert_eq!(
+            report,
+            OptimizationReport {
+                cancelled: 8,
+                merged: 2,
+                identities: 1,
+                folded: 0,
+            }
+        );
+        assert_eq!(
+            optimized
+                .gates
+                .iter()
+                .map(|gate| format!("{:?}", gate.defn))
+                .collect::<Vec<_>>(),
+            vec![
+                "X(1)",
+                "S(2)",
+                "RZ { rot: 0.75, target: 1 }",
+                "CX { control: 1, target: 2 }"
+            ]
+        );
+        assert_same_state(circuit(source), optimized);
+    }
+
+    #[test]
+    fn test_fold() {
+        let source = r#"
+        OPENQASM 2.0;
+        include "qelib1.inc";
+        qreg q[2];
+        h q[0];
+        t q[0];
+        h q[0];
+        ry(0.3) q[1];
+        cx q[0],q[1];
+        h q[1];
+        z q[1];
+        h q[1];
+        sx q[0];
+        rz(0.2) q[0];
+        "#;
+        let (optimized, report) = circuit(source).optimize();
+
+        // h t h and sx rz fold into a U each, and h z h into an X
+        assert_eq!(report.folded, 5);
+        assert_eq!(
+            optimized
+                .gates
+                .iter()
+                .filter(|gate| gate.is_branching())
+                .count(),
+            3
+        );
+        assert!(matches!(optimized.gates[3].defn, GateDefn::X(1)));
+        assert_same_state(circuit(source), optimized);
+    }
+}
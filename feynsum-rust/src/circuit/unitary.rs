@@ -1,10 +1,16 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
 use crate::circuit::{Gate, GateDefn};
-use crate::types::{constants, BasisIdx, Complex, QubitIndex};
+use crate::types::{constants, BasisIdx, Complex, QubitIndex, Real};
 use nalgebra::{
     base::{Matrix, VecStorage},
-    dmatrix, Dyn,
+    dmatrix, DMatrix, Dyn,
 };
 
+#[derive(Debug, Clone)]
 pub struct UnitaryMatrix {
     pub mat: Matrix<Complex, Dyn, Dyn, VecStorage<Complex, Dyn, Dyn>>,
     pub qubit_indices: Vec<QubitIndex>,
@@ -187,8 +193,8 @@ impl<B: BasisIdx> Unitary for Gate<B> {
                 }
             }
             GateDefn::RZ { rot, target } => {
-                let a = Complex::new((rot / 2.0).cos(), (rot / 2.0).sin());
-                let b = Complex::new((rot / 2.0).cos(), -(rot / 2.0).sin());
+                let a = Complex::new((rot / 2.0).cos(), -(rot / 2.0).sin());
+                let b = Complex::new((rot / 2.0).cos(), (rot / 2.0).sin());
                 let mat = dmatrix![
                     a, Complex::new(0.0, 0.0);
                     Complex::new(0.0, 0.0), b
@@ -303,7 +309,45 @@ impl<B: BasisIdx> Unitary for Gate<B> {
                     qubit_indices: vec![*qi],
                 }
             }
-            GateDefn::Other { .. } => panic!("unsupported gate {:?}", self),
+            GateDefn::Project {
+                target,
+                outcome,
+                scale,
+            } => {
+                let (m0, m1) = if *outcome {
+                    (0.0, *scale)
+                } else {
+                    (*scale, 0.0)
+                };
+                let mat = dmatrix![
+                    Complex::new(m0, 0.0), Complex::new(0.0, 0.0);
+                    Complex::new(0.0, 0.0), Complex::new(m1, 0.0)
+                ];
+                UnitaryMatrix {
+                    mat,
+                    qubit_indices: vec![*target],
+                }
+            }
+            GateDefn::CH { .. }
+            | GateDefn::CRX { .. }
+            | GateDefn::CRY { .. }
+            | GateDefn::CRZ { .. }
+            | GateDefn::CU { .. }
+            | GateDefn::CY { .. }
+            | GateDefn::ECR { .. }
+            | GateDefn::ISwap { .. }
+            | GateDefn::RXX { .. }
+            | GateDefn::RYY { .. }
+            | GateDefn::RZZ { .. }
+            | GateDefn::Controlled { .. } => UnitaryMatrix {
+                mat: self.defn.local_matrix::<B>(&self.touches),
+                qubit_indices: self.touches.clone(),
+            },
+            GateDefn::Unitary { matrix, .. } => matrix.clone(),
+            GateDefn::Parameterized { .. }
+            | GateDefn::Measure { .. }
+            | GateDefn::Reset(_)
+            | GateDefn::Conditional { .. } => panic!("unsupported gate {:?}", self),
         }
     }
 
@@ -326,3 +370,81 @@ impl<B: BasisIdx> Unitary for Gate<B> {
         }
     }
 }
+
+/// Loads named gate matrices from a JSON object mapping each name to its rows,
+/// whose entries are either real numbers or `[re, im]` pairs. As with
+/// `unitary()`, the first qubit a gate is applied to is the least significant
+/// bit.
+pub fn load_unitaries(path: &Path) -> io::Result<HashMap<String, DMatrix<Complex>>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let value: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let entries = match value {
+        serde_json::Value::Object(entries) => entries,
+        value => {
+            return Err(invalid(format!(
+                "expected an object of gate matrices, found {}",
+                value
+            )))
+        }
+    };
+
+    entries
+        .into_iter()
+        .map(|(name, value)| {
+            let mat = parse_matrix(&value)
+                .map_err(|message| invalid(format!("gate {}: {}", name, message)))?;
+            Ok((name, mat))
+        })
+        .collect()
+}
+
+fn parse_matrix(value: &serde_json::Value) -> Result<DMatrix<Complex>, String> {
+    let rows = value
+        .as_array()
+        .ok_or_else(|| format!("expected an array of rows, found {}", value))?;
+    let dim = rows.len();
+    if dim < 2 || !dim.is_power_of_two() {
+        return Err(format!("dimension {} is not a power of two", dim));
+    }
+
+    let mut entries = Vec::with_capacity(dim * dim);
+    for row in rows {
+        let row = match row.as_array() {
+            Some(row) if row.len() == dim => row,
+            _ => return Err(format!("expected a row of {} entries, found {}", dim, row)),
+        };
+        for entry in row {
+            entries.push(parse_entry(entry)?);
+        }
+    }
+    let mat = DMatrix::from_row_slice(dim, dim, &entries);
+
+    let error = (mat.adjoint() * &mat - DMatrix::identity(dim, dim))
+        .iter()
+        .map(|entry| entry.norm())
+        .fold(0.0, Real::max);
+    if error > UNITARY_TOLERANCE {
+        return Err(format!("matrix is not unitary (error {})", error));
+    }
+
+    Ok(mat)
+}
+
+fn parse_entry(value: &serde_json::Value) -> Result<Complex, String> {
+    let invalid = || format!("expected a number or [re, im], found {}", value);
+    match value {
+        serde_json::Value::Number(re) => Ok(Complex::new(re.as_f64().unwrap() as Real, 0.0)),
+        serde_json::Value::Array(parts) => match parts.as_slice() {
+            [re, im] => Ok(Complex::new(
+                re.as_f64().ok_or_else(invalid)? as Real,
+                im.as_f64().ok_or_else(invalid)? as Real,
+            )),
+            _ => Err(invalid()),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+/// Matrices are typically written out with a handful of digits
+const UNITARY_TOLERANCE: Real = 1e-4;
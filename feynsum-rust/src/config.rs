@@ -1,43 +1,55 @@
-use crate::gate_scheduler::GateSchedulingPolicy;
-use crate::options::Options;
+use crate::gate_scheduler::{GateSchedulingPolicy, Schedule};
 use crate::types::Real;
 
+/// How to simulate a circuit, see `run`
+#[derive(Clone)]
 pub struct Config {
+    /// The name of the backend to simulate with, see `Registry`
+    pub simulator: String,
     pub block_size: usize,
     #[allow(dead_code)]
     pub maxload: Real,
     pub gate_scheduling_policy: GateSchedulingPolicy, // TODO: Add pullThreshold
+    pub lookahead: usize,
+    pub max_branching_stride: usize,
     pub disable_gate_fusion: bool,
+    pub fusion_max_qubits: usize,
     pub dense_threshold: Real,
     pub pull_threshold: Real,
+    /// The density below which the hybrid simulator returns a dense state to
+    /// a sparse table, clamped to `dense_threshold`
+    pub sparse_threshold: Real,
     pub bond_dimension_threshold: usize,
+    pub seed: u64,
+    /// How many basis states to draw from the final state, into
+    /// `Outcome::counts`, or 0 to draw none
+    pub shots: usize,
+    /// Whether to record the picks of the schedulers, into
+    /// `Outcome::schedule`
+    pub record_schedule: bool,
+    /// The picks to replay instead of running the scheduling policy
+    pub replayed_schedule: Option<Schedule>,
 }
 
-impl Config {
-    pub fn new(options: &Options) -> Self {
-        Self {
-            block_size: options.block_size,
-            maxload: 0.75, // FIXME
-            gate_scheduling_policy: options.gate_schduling_policy,
-            disable_gate_fusion: options.disable_gate_fusion,
-            dense_threshold: options.dense_threshold,
-            pull_threshold: options.pull_threshold,
-            bond_dimension_threshold: options.bond_dimension_threshold,
-        }
-    }
-}
-
-// used for testing
 impl Default for Config {
     fn default() -> Self {
         Self {
+            simulator: "parallel".to_string(),
             block_size: 10_000,
             maxload: 0.75, // FIXME
             gate_scheduling_policy: GateSchedulingPolicy::GreedyNonbranching,
+            lookahead: 5,
+            max_branching_stride: 2,
             disable_gate_fusion: false,
+            fusion_max_qubits: 4,
             dense_threshold: 0.25,
             pull_threshold: 0.8,
+            sparse_threshold: 0.05,
             bond_dimension_threshold: 100,
+            seed: 0,
+            shots: 0,
+            record_schedule: false,
+            replayed_schedule: None,
         }
     }
 }
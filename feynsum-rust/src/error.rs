@@ -0,0 +1,165 @@
+//! The error type returned by parsing, circuit construction and simulation.
+//!
+//! Failures are classified by `ErrorKind`, which also determines the exit
+//! code of the process, so that batch runs can tell them apart without
+//! scraping the log.
+
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+use crate::circuit::CircuitBuildError;
+
+/// A position in the input program, both 1-based
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    /// The location of the byte `offset` of `source`
+    pub fn from_offset(source: &str, offset: usize) -> Self {
+        let prefix = &source[..offset.min(source.len())];
+        let line = prefix.matches('\n').count() + 1;
+        let column = prefix.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        Location { line, column }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Parse,
+    CircuitBuild(CircuitBuildError),
+    /// A gate the chosen simulator cannot apply
+    UnsupportedGate,
+    /// A simulator name no backend is registered under
+    UnknownSimulator,
+    /// A replayed schedule that does not fit the circuit
+    Schedule,
+    Io(io::Error),
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Parse => "parse",
+            ErrorKind::CircuitBuild(_) => "circuit",
+            ErrorKind::UnsupportedGate => "unsupported",
+            ErrorKind::UnknownSimulator => "simulator",
+            ErrorKind::Schedule => "schedule",
+            ErrorKind::Io(_) => "io",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    /// Where the offending statement starts, if known
+    pub location: Option<Location>,
+    /// The gate called by the offending statement, if any
+    pub gate: Option<String>,
+    pub message: String,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Error {
+            kind,
+            location: None,
+            gate: None,
+            message: message.into(),
+        }
+    }
+
+    /// Sets the location, unless a more precise one is known already
+    pub fn at(mut self, location: Option<Location>) -> Self {
+        self.location = self.location.or(location);
+        self
+    }
+
+    /// Sets the gate, unless a more precise one is known already
+    pub fn in_gate(mut self, gate: Option<&str>) -> Self {
+        self.gate = self.gate.or_else(|| gate.map(str::to_string));
+        self
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.kind {
+            ErrorKind::Parse => 2,
+            ErrorKind::CircuitBuild(_) => 3,
+            ErrorKind::UnsupportedGate => 4,
+            ErrorKind::Io(_) => 5,
+            ErrorKind::UnknownSimulator => 6,
+            ErrorKind::Schedule => 7,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(location) = self.location {
+            write!(f, "{}: ", location)?;
+        }
+        if let Some(gate) = &self.gate {
+            write!(f, "gate {}: ", gate)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::CircuitBuild(err) => Some(err),
+            ErrorKind::Io(err) => Some(err),
+            ErrorKind::Parse
+            | ErrorKind::UnsupportedGate
+            | ErrorKind::UnknownSimulator
+            | ErrorKind::Schedule => None,
+        }
+    }
+}
+
+impl From<CircuitBuildError> for Error {
+    fn from(err: CircuitBuildError) -> Self {
+        let message = err.to_string();
+        Error::new(ErrorKind::CircuitBuild(err), message)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        let message = err.to_string();
+        Error::new(ErrorKind::Io(err), message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let source = "OPENQASM 2.0;\nqreg q[2];\n  foo q[0];\n";
+        let err = Error::from(CircuitBuildError::UnknownGate)
+            .at(Some(Location::from_offset(
+                source,
+                source.find("foo").unwrap(),
+            )))
+            .in_gate(Some("foo"))
+            .at(None);
+        assert_eq!(err.kind.to_string(), "circuit");
+        assert_eq!(err.to_string(), "3:3: gate foo: unknown gate");
+        assert_eq!(err.exit_code(), 3);
+    }
+}
@@ -1,19 +1,19 @@
 use std::cmp::Ordering;
 use std::collections::BTreeSet;
 
-use crate::types::{BasisIdx, Complex};
+use crate::types::{BasisIdx, Precision, Weight};
 
 #[derive(PartialEq)]
-struct Entry<B: BasisIdx>(B, Complex);
+struct Entry<B: BasisIdx, P: Precision>(B, Weight<P>);
 
-impl<B: BasisIdx> Eq for Entry<B> {}
+impl<B: BasisIdx, P: Precision> Eq for Entry<B, P> {}
 
-impl<B: BasisIdx> PartialOrd for Entry<B> {
+impl<B: BasisIdx, P: Precision> PartialOrd for Entry<B, P> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         // FIXME: bucket magnitude?
-        if self.1.norm() < other.1.norm() {
+        if self.1.norm_sqr() < other.1.norm_sqr() {
             Some(Ordering::Less)
-        } else if self.1.norm() > other.1.norm() {
+        } else if self.1.norm_sqr() > other.1.norm_sqr() {
             Some(Ordering::Greater)
         } else {
             self.0.as_bytes().partial_cmp(&other.0.as_bytes())
@@ -21,11 +21,11 @@ impl<B: BasisIdx> PartialOrd for Entry<B> {
     }
 }
 
-impl<B: BasisIdx> Ord for Entry<B> {
+impl<B: BasisIdx, P: Precision> Ord for Entry<B, P> {
     fn cmp(&self, other: &Self) -> Ordering {
-        if self.1.norm() < other.1.norm() {
+        if self.1.norm_sqr() < other.1.norm_sqr() {
             Ordering::Less
-        } else if self.1.norm() > other.1.norm() {
+        } else if self.1.norm_sqr() > other.1.norm_sqr() {
             Ordering::Greater
         } else {
             other.0.as_bytes().cmp(&self.0.as_bytes())
@@ -33,12 +33,12 @@ impl<B: BasisIdx> Ord for Entry<B> {
     }
 }
 
-pub struct Fingerprint<B: BasisIdx> {
+pub struct Fingerprint<B: BasisIdx, P: Precision> {
     num_entries: usize,
-    entries: BTreeSet<Entry<B>>,
+    entries: BTreeSet<Entry<B, P>>,
 }
 
-impl<B: BasisIdx> Fingerprint<B> {
+impl<B: BasisIdx, P: Precision> Fingerprint<B, P> {
     pub fn new(num_entries: usize) -> Self {
         Self {
             num_entries,
@@ -46,14 +46,14 @@ impl<B: BasisIdx> Fingerprint<B> {
         }
     }
 
-    pub fn insert(&mut self, bidx: B, weight: Complex) {
+    pub fn insert(&mut self, bidx: B, weight: Weight<P>) {
         self.entries.insert(Entry(bidx, weight));
         if self.entries.len() > self.num_entries {
             self.entries.pop_first();
         }
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (B, Complex)> + '_ {
+    pub fn iter(&self) -> impl Iterator<Item = (B, Weight<P>)> + '_ {
         // reverse the comparison here so that the output fingerprint is sorted
         // like normal binary numbers, for readability
         self.entries
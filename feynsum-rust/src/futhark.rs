@@ -1,40 +1,50 @@
-// futhark_lib.rs file is dirty so we define a wrapper here
+// futhark_lib.rs file is dirty so we define a wrapper here. The kernels are
+// single precision, so weights are converted on the way in and out.
 mod internal {
     #![allow(warnings)]
     include!(concat!(env!("OUT_DIR"), "/futhark_lib.rs"));
+    use std::marker::PhantomData;
+
     use crate::circuit::UnitaryMatrix;
-    use crate::types::Complex;
+    use crate::types::{constants, Complex, Precision, Weight};
 
     pub fn create_context() -> Context {
         Context::new().unwrap()
     }
 
-    pub struct FutharkVector<'a> {
+    /// Stored in single precision, whatever the precision `P` of the weights
+    pub struct FutharkVector<'a, P: Precision> {
         vec: ArrayF32D2<'a>,
+        precision: PhantomData<P>,
     }
 
-    impl<'a> FutharkVector<'a> {
-        pub fn new(ctx: &'a Context, vec: Vec<Complex>) -> Self {
+    impl<'a, P: Precision> FutharkVector<'a, P> {
+        pub fn new(ctx: &'a Context, vec: Vec<Weight<P>>) -> Self {
             let vec = ArrayF32D2::new(
                 ctx,
                 [vec.len() as i64, 2],
-                vec.iter().flat_map(|c| [c.re, c.im]).collect::<Vec<f32>>(),
+                vec.iter()
+                    .flat_map(|c| [c.re.into_real() as f32, c.im.into_real() as f32])
+                    .collect::<Vec<f32>>(),
             )
             .unwrap();
-            FutharkVector { vec }
+            FutharkVector {
+                vec,
+                precision: PhantomData,
+            }
         }
 
         pub fn dim(&self) -> usize {
             self.vec.shape[0] as usize
         }
 
-        pub fn into_vec(self) -> Vec<Complex> {
+        pub fn into_vec(self) -> Vec<Weight<P>> {
             let linearized = self.vec.get().unwrap();
             assert!(linearized.len() == 2 * self.dim());
 
             linearized
                 .chunks(2)
-                .map(|c| Complex::new(c[0], c[1]))
+                .map(|c| Weight::new(P::convert(c[0] as f64), P::convert(c[1] as f64)))
                 .collect()
         }
     }
@@ -52,7 +62,11 @@ mod internal {
                 ctx,
                 [dim as i64, dim as i64, 2],
                 mat.iter()
-                    .flat_map(|row| row.iter().flat_map(|c| [c.re, c.im]).collect::<Vec<_>>())
+                    .flat_map(|row| {
+                        row.iter()
+                            .flat_map(|c| [c.re as f32, c.im as f32])
+                            .collect::<Vec<_>>()
+                    })
                     .collect::<Vec<_>>(),
             )
             .unwrap();
@@ -64,11 +78,11 @@ mod internal {
         }
     }
 
-    pub fn apply_vec<'a>(
+    pub fn apply_vec<'a, P: Precision>(
         ctx: &'a Context,
-        state: FutharkVector,
+        state: FutharkVector<P>,
         unitary: UnitaryMatrix,
-    ) -> FutharkVector<'a> {
+    ) -> FutharkVector<'a, P> {
         let n = state.dim();
         let q = unitary.qubit_indices.len();
         assert!(unitary.mat.nrows() == 1 << q && unitary.mat.ncols() == 1 << q);
@@ -88,16 +102,29 @@ mod internal {
 
         FutharkVector {
             vec: ctx.apply_vec(&state.vec, &mat, &qubit_indices).unwrap(),
+            precision: PhantomData,
         }
     }
 
+    /// Counts the nonzeros on the device, so that the state stays there
+    pub fn num_nonzeros<P: Precision>(ctx: &Context, state: &FutharkVector<P>) -> usize {
+        ctx.count_nonzeros(&state.vec, constants::ZERO_THRESHOLD as f32)
+            .unwrap() as usize
+    }
+
     fn flatten(unitary: &UnitaryMatrix) -> Vec<f32> {
         unitary
             .mat
             .row_iter()
-            .flat_map(|row| row.iter().flat_map(|c| [c.re, c.im]).collect::<Vec<_>>())
+            .flat_map(|row| {
+                row.iter()
+                    .flat_map(|c| [c.re as f32, c.im as f32])
+                    .collect::<Vec<_>>()
+            })
             .collect()
     }
 }
 
-pub use internal::{apply_vec, create_context, Context, FutharkVector};
+pub use internal::{
+    apply_vec, create_context, num_nonzeros, Context, FutharkVector as DenseVector,
+};
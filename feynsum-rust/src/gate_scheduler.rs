@@ -1,25 +1,46 @@
 use std::fmt::Display;
 use std::str::FromStr;
+use std::time::Duration;
 
 use crate::circuit::Circuit;
 use crate::config::Config;
+use crate::error;
 use crate::types::BasisIdx;
 
+mod a_star_gate_scheduler;
+mod adaptive_gate_scheduler;
+mod dependency_graph;
+mod fusing_gate_scheduler;
+mod greedy_branching_gate_scheduler;
 mod greedy_finish_qubit_gate_scheduler;
 mod greedy_nonbranching_gate_scheduler;
 mod naive_gate_scheduler;
-mod utility;
+mod recording_gate_scheduler;
+mod replay_gate_scheduler;
+mod schedule;
+mod support_model;
 
+pub use a_star_gate_scheduler::AStarGateScheduler;
+pub use adaptive_gate_scheduler::AdaptiveGateScheduler;
+pub use dependency_graph::DependencyGraph;
+pub use fusing_gate_scheduler::FusingGateScheduler;
+pub use greedy_branching_gate_scheduler::GreedyBranchingGateScheduler;
 pub use greedy_finish_qubit_gate_scheduler::GreedyFinishQubitGateScheduler;
 pub use greedy_nonbranching_gate_scheduler::GreedyNonbranchingGateScheduler;
 pub use naive_gate_scheduler::NaiveGateScheduler;
-pub use dag_a_star_informed::DAGScheduler;
+pub use recording_gate_scheduler::RecordingGateScheduler;
+pub use replay_gate_scheduler::ReplayGateScheduler;
+pub use schedule::{Schedule, ScheduleCursor};
+pub use support_model::SupportModel;
+
 #[derive(Debug, Copy, Clone)]
 pub enum GateSchedulingPolicy {
     Naive,
+    GreedyBranching,
     GreedyNonbranching,
     GreedyFinishQubit,
-    DAG,
+    AStar,
+    Adaptive,
 }
 
 impl FromStr for GateSchedulingPolicy {
@@ -28,11 +49,13 @@ impl FromStr for GateSchedulingPolicy {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "naive" => Ok(GateSchedulingPolicy::Naive),
+            "greedy-branching" | "gb" => Ok(GateSchedulingPolicy::GreedyBranching),
             "greedy-nonbranching" | "gnb" => Ok(GateSchedulingPolicy::GreedyNonbranching),
             "greedy-finish-qubit" | "gfq" => Ok(GateSchedulingPolicy::GreedyFinishQubit),
-            "dag-a-star"|"das" => Ok(GateSchedulingPolicy::DAG),
+            "dag-a-star" | "das" => Ok(GateSchedulingPolicy::AStar),
+            "adaptive" => Ok(GateSchedulingPolicy::Adaptive),
             _ => Err(format!(
-                "unknown gate scheduling policy: {}; valid values are: naive, gnb, fgq",
+                "unknown gate scheduling policy: {}; valid values are: naive, gb, gnb, gfq, das, adaptive",
                 s
             )),
         }
@@ -43,20 +66,70 @@ impl Display for GateSchedulingPolicy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             GateSchedulingPolicy::Naive => write!(f, "naive"),
+            GateSchedulingPolicy::GreedyBranching => write!(f, "greedy-branching"),
             GateSchedulingPolicy::GreedyNonbranching => write!(f, "greedy-nonbranching"),
             GateSchedulingPolicy::GreedyFinishQubit => write!(f, "greedy-finish-qubit"),
-            GateSchedulingPolicy::DAG=>write!(f,"dag-a-star"),
+            GateSchedulingPolicy::AStar => write!(f, "dag-a-star"),
+            GateSchedulingPolicy::Adaptive => write!(f, "adaptive"),
         }
     }
 }
 
+/// How a simulator applied the gates of a pick
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Expansion {
+    Sparse,
+    Dense,
+    Mps,
+}
+
+/// What a simulator observed after applying the gates of a pick
+#[derive(Debug, Copy, Clone)]
+pub struct Feedback {
+    pub num_nonzeros: usize,
+    pub expansion: Expansion,
+    pub duration: Duration,
+}
+
 pub trait GateScheduler {
     fn pick_next_gates(&mut self) -> Vec<usize>;
+
+    /// Called by the simulator after it applies the gates of each pick, for
+    /// schedulers that adapt to how the state evolves
+    fn observe(&mut self, _feedback: &Feedback) {}
 }
 
+/// Replays or records the picks at `cursor`, if it does. Fails if the
+/// replayed schedule does not fit the circuit
 pub fn create_gate_scheduler<'a, B: BasisIdx>(
     config: &Config,
     circuit: &'a Circuit<B>,
+    cursor: &'a ScheduleCursor,
+) -> error::Result<Box<dyn GateScheduler + 'a>> {
+    let scheduler: Box<dyn GateScheduler + 'a> = if cursor.is_replaying() {
+        log::info!("replaying a recorded schedule");
+        Box::new(ReplayGateScheduler::new(
+            circuit.num_qubits,
+            &circuit.gates,
+            DependencyGraph::new(circuit.num_qubits, &circuit.gates),
+            cursor,
+        )?)
+    } else {
+        create_policy_gate_scheduler(config, circuit)
+    };
+
+    Ok(if cursor.is_recording() {
+        Box::new(RecordingGateScheduler::new(scheduler, cursor))
+    } else {
+        scheduler
+    })
+}
+
+/// The scheduler of `config.gate_scheduling_policy`, which neither records
+/// nor replays a schedule
+pub(crate) fn create_policy_gate_scheduler<'a, B: BasisIdx>(
+    config: &Config,
+    circuit: &'a Circuit<B>,
 ) -> Box<dyn GateScheduler + 'a> {
     let gate_scheduling_policy = config.gate_scheduling_policy;
     let disable_gate_fusion = config.disable_gate_fusion;
@@ -79,33 +152,82 @@ pub fn create_gate_scheduler<'a, B: BasisIdx>(
             log::info!("using naive gate scheduler");
             Box::new(NaiveGateScheduler::new(num_gates))
         }
+        GateSchedulingPolicy::GreedyBranching => {
+            log::info!("using greedy branching gate scheduler");
+            Box::new(GreedyBranchingGateScheduler::new(
+                num_qubits,
+                DependencyGraph::new(num_qubits, &circuit.gates),
+                gate_touches,
+                gate_is_branching,
+                config.max_branching_stride,
+                disable_gate_fusion,
+            ))
+        }
         GateSchedulingPolicy::GreedyNonbranching => {
             log::info!("using greedy nonbranching gate scheduler");
             Box::new(GreedyNonbranchingGateScheduler::new(
-                num_gates,
                 num_qubits,
+                DependencyGraph::new(num_qubits, &circuit.gates),
                 gate_touches,
                 gate_is_branching,
+                config.max_branching_stride,
                 disable_gate_fusion,
             ))
         }
         GateSchedulingPolicy::GreedyFinishQubit => {
-            log::info!("using greedy nonbranching gate scheduler");
+            log::info!("using greedy finish qubit gate scheduler");
             Box::new(GreedyFinishQubitGateScheduler::new(
-                num_gates,
                 num_qubits,
+                DependencyGraph::new(num_qubits, &circuit.gates),
                 gate_touches,
             ))
         }
-        GateSchedulingPolicy::DAG =>{
+        GateSchedulingPolicy::AStar => {
             log::info!("using dag a* gate scheduler");
-            Box::new(DAGScheduler::new(
-                num_gates,
+            Box::new(AStarGateScheduler::new(
+                num_qubits,
+                &circuit.gates,
+                DependencyGraph::new(num_qubits, &circuit.gates),
+                config.lookahead,
+            ))
+        }
+        GateSchedulingPolicy::Adaptive => {
+            log::info!("using adaptive gate scheduler");
+            Box::new(AdaptiveGateScheduler::new(
                 num_qubits,
+                DependencyGraph::new(num_qubits, &circuit.gates),
                 gate_touches,
                 gate_is_branching,
-                informed,
+                config.max_branching_stride,
+                disable_gate_fusion,
             ))
         }
     }
 }
+
+/// Like `create_gate_scheduler`, but each pick is a run of gates to be fused
+/// into one unitary, or a single gate if gate fusion is disabled
+pub fn create_fusing_gate_scheduler<'a, B: BasisIdx>(
+    config: &Config,
+    circuit: &'a Circuit<B>,
+    cursor: &'a ScheduleCursor,
+) -> error::Result<Box<dyn GateScheduler + 'a>> {
+    let max_qubits = if config.disable_gate_fusion {
+        0
+    } else {
+        config.fusion_max_qubits
+    };
+    log::info!("fusing gates on up to {} qubits", max_qubits);
+
+    let gate_touches = circuit
+        .gates
+        .iter()
+        .map(|gate| gate.touches.as_slice())
+        .collect();
+
+    Ok(Box::new(FusingGateScheduler::new(
+        create_gate_scheduler(config, circuit, cursor)?,
+        gate_touches,
+        max_qubits,
+    )))
+}
@@ -0,0 +1,430 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
+use std::rc::Rc;
+
+use super::{DependencyGraph, GateScheduler, SupportModel};
+use crate::circuit::Gate;
+use crate::types::{BasisIdx, GateIndex, QubitIndex, Real};
+
+/// Paths expanded by one search before settling for the most promising one
+const MAX_EXPANSIONS: usize = 1024;
+
+/// Picks the next gates by an A* search for the cheapest schedule of the next
+/// `lookahead` branching gates. Each step of a schedule visits a branching
+/// gate after the nonbranching gates it waits for, and costs one gate
+/// application per nonzero per gate, with the nonzeros estimated by a
+/// `SupportModel`. If the support shrinks back within the lookahead, as when a
+/// later gate undoes a branching gate, the steps up to there are picked at
+/// once, and the nonbranching gates that need not wait for them are left for
+/// after. Otherwise the ready nonbranching gates are picked along with the
+/// first branching gate of the schedule.
+pub struct AStarGateScheduler<'a, B: BasisIdx> {
+    gates: &'a [Gate<B>],
+    dependencies: DependencyGraph,
+    model: SupportModel,
+    lookahead: usize,
+}
+
+/// A schedule of branching gates, with the gates it visits as an overlay on
+/// the scheduler's and the estimated support after them
+#[derive(Clone)]
+struct Path {
+    visited: BTreeSet<GateIndex>,
+    ready: BTreeSet<GateIndex>,
+    /// Shared with the path it extends until `step` is applied to it
+    model: Rc<SupportModel>,
+    /// The last step, which `model` does not include until the path is the
+    /// most promising one
+    step: Vec<GateIndex>,
+    /// The gates of the steps so far, each branching gate after the gates it
+    /// waits for
+    gates: Vec<GateIndex>,
+    /// Whether the support is back to at most what it was at the start of
+    /// the path, which ends the path
+    is_back: bool,
+    /// Ties between paths go to the one whose first branching gate is on the
+    /// lowest qubit, as in `GreedyNonbranchingGateScheduler`
+    first_order: Option<(QubitIndex, GateIndex)>,
+    num_branching: usize,
+    start_num_nonzeros: Real,
+    num_nonzeros: Real,
+    cost: Real,
+    estimate: Real,
+}
+
+impl<'a, B: BasisIdx> GateScheduler for AStarGateScheduler<'a, B> {
+    fn pick_next_gates(&mut self) -> Vec<GateIndex> {
+        let root = Path::root(self);
+
+        let next_gates = match self.search(root.clone()) {
+            // the steps up to where the support shrinks back go in one pick,
+            // so that the paths they split into are summed up at its end, and
+            // the other nonbranching gates are cheaper after them
+            Some(path) if path.is_back => {
+                self.model = Rc::unwrap_or_clone(path.model);
+                path.gates
+            }
+            path => {
+                let (mut next_gates, _) = self.nonbranching_closure(&root);
+                if let Some(Path {
+                    first_order: Some((_, first)),
+                    ..
+                }) = path
+                {
+                    next_gates.push(first);
+                }
+                for gi in &next_gates {
+                    self.model.apply(&self.gates[*gi]);
+                }
+                next_gates
+            }
+        };
+
+        for gi in &next_gates {
+            self.dependencies.visit(*gi);
+        }
+
+        log::debug!(
+            "next gates: {:?}, expected nonzeros: {}",
+            next_gates,
+            self.model.num_nonzeros()
+        );
+
+        next_gates
+    }
+}
+
+impl<'a, B: BasisIdx> AStarGateScheduler<'a, B> {
+    pub fn new(
+        num_qubits: usize,
+        gates: &'a [Gate<B>],
+        dependencies: DependencyGraph,
+        lookahead: usize,
+    ) -> Self {
+        log::debug!(
+            "initializing a* gate scheduler with {} gates, {} qubits and lookahead {}",
+            gates.len(),
+            num_qubits,
+            lookahead
+        );
+        assert_eq!(gates.len(), dependencies.num_gates());
+        assert!(lookahead > 0);
+
+        Self {
+            gates,
+            dependencies,
+            model: SupportModel::new(num_qubits),
+            lookahead,
+        }
+    }
+
+    /// The cheapest schedule from `root`, which ends early where the support
+    /// shrinks back, if any branching gate is left
+    fn search(&self, root: Path) -> Option<Path> {
+        // gates visited in another order lead to the same support, so only
+        // the cheapest order is worth expanding
+        let mut min_costs = HashMap::from([(root.visited.clone(), root.cost)]);
+        let mut paths = BinaryHeap::from([root]);
+        let mut num_expansions = 0;
+
+        while let Some(mut path) = paths.pop() {
+            if min_costs[&path.visited] < path.cost {
+                continue;
+            }
+            if !path.step.is_empty() {
+                self.apply_step(&mut path);
+                paths.push(path);
+                continue;
+            }
+            let (_, branching) = self.nonbranching_closure(&path);
+            if path.num_branching == self.lookahead
+                || path.is_back
+                || branching.is_empty()
+                || num_expansions == MAX_EXPANSIONS
+            {
+                log::debug!(
+                    "a* search expanded {} paths, expected cost: {}",
+                    num_expansions,
+                    path.estimate
+                );
+                return if path.num_branching > 0 {
+                    Some(path)
+                } else {
+                    None
+                };
+            }
+            num_expansions += 1;
+
+            for gi in branching {
+                let mut step = self.pending_ancestors(&path, gi);
+                step.push(gi);
+                let cost = path.cost + path.num_nonzeros * step.len() as Real;
+
+                let mut visited = path.visited.clone();
+                visited.extend(&step);
+                if min_costs.get(&visited).is_some_and(|min| *min <= cost) {
+                    continue;
+                }
+                min_costs.insert(visited, cost);
+                paths.push(self.extend(&path, step, cost));
+            }
+        }
+        unreachable!("the search ends at a complete path")
+    }
+
+    /// `path` followed by `step`, the gates that its last gate, a branching
+    /// gate, waits for and that gate. The support after it is left to
+    /// `apply_step`, so until then the estimate takes it to be unchanged.
+    fn extend(&self, path: &Path, step: Vec<GateIndex>, cost: Real) -> Path {
+        let mut next = path.clone();
+        for gi in &step {
+            self.mark_visited(&mut next.visited, &mut next.ready, *gi);
+        }
+
+        next.cost = cost;
+        next.num_branching += 1;
+        if next.first_order.is_none() {
+            next.first_order = Some(self.order(*step.last().unwrap()));
+        }
+        next.gates.extend(&step);
+        next.step = step;
+        next.estimate = next.cost + next.num_nonzeros;
+        next
+    }
+
+    /// Applies the last step of `path` to its support
+    fn apply_step(&self, path: &mut Path) {
+        let model = Rc::make_mut(&mut path.model);
+        for gi in path.step.drain(..) {
+            model.apply(&self.gates[gi]);
+        }
+
+        path.num_nonzeros = path.model.num_nonzeros();
+        path.is_back = path.num_nonzeros <= path.start_num_nonzeros;
+        // the next step, or whatever follows the last one, applies at least
+        // one gate to each nonzero
+        path.estimate = path.cost + path.num_nonzeros;
+    }
+
+    /// The nonbranching gates that can be visited after `path`, in an order
+    /// they can be visited in, and the branching gates ready after them
+    fn nonbranching_closure(&self, path: &Path) -> (Vec<GateIndex>, Vec<GateIndex>) {
+        let mut visited = path.visited.clone();
+        let mut ready = path.ready.clone();
+        let mut nonbranching = Vec::new();
+        while let Some(gi) = ready.iter().copied().find(|gi| !self.is_branching(*gi)) {
+            self.mark_visited(&mut visited, &mut ready, gi);
+            nonbranching.push(gi);
+        }
+        (nonbranching, ready.into_iter().collect())
+    }
+
+    /// Visits `gi` in the overlay `visited` on the scheduler's visited gates,
+    /// and updates the gates `ready` after them
+    fn mark_visited(
+        &self,
+        visited: &mut BTreeSet<GateIndex>,
+        ready: &mut BTreeSet<GateIndex>,
+        gi: GateIndex,
+    ) {
+        assert!(ready.remove(&gi), "gate {} is not ready", gi);
+        visited.insert(gi);
+
+        for succ in self.dependencies.successors(gi) {
+            if self
+                .dependencies
+                .predecessors(*succ)
+                .iter()
+                .all(|pred| self.is_visited(visited, *pred))
+            {
+                ready.insert(*succ);
+            }
+        }
+    }
+
+    fn is_visited(&self, visited: &BTreeSet<GateIndex>, gi: GateIndex) -> bool {
+        self.dependencies.is_visited(gi) || visited.contains(&gi)
+    }
+
+    /// The unvisited gates that `gi` waits for, in circuit order
+    fn pending_ancestors(&self, path: &Path, gi: GateIndex) -> Vec<GateIndex> {
+        let mut ancestors = BTreeSet::new();
+        let mut stack = vec![gi];
+        while let Some(gi) = stack.pop() {
+            for pred in self.dependencies.predecessors(gi) {
+                if !self.is_visited(&path.visited, *pred) && ancestors.insert(*pred) {
+                    stack.push(*pred);
+                }
+            }
+        }
+        ancestors.into_iter().collect()
+    }
+
+    fn is_branching(&self, gi: GateIndex) -> bool {
+        self.gates[gi].is_branching()
+    }
+
+    fn order(&self, gi: GateIndex) -> (QubitIndex, GateIndex) {
+        (
+            self.gates[gi].touches.iter().min().copied().unwrap_or(0),
+            gi,
+        )
+    }
+}
+
+impl Path {
+    fn root<B: BasisIdx>(scheduler: &AStarGateScheduler<B>) -> Self {
+        let num_nonzeros = scheduler.model.num_nonzeros();
+        Self {
+            visited: BTreeSet::new(),
+            ready: scheduler.dependencies.ready().collect(),
+            model: Rc::new(scheduler.model.clone()),
+            step: Vec::new(),
+            gates: Vec::new(),
+            is_back: false,
+            first_order: None,
+            num_branching: 0,
+            start_num_nonzeros: num_nonzeros,
+            num_nonzeros,
+            cost: 0.0,
+            estimate: 0.0,
+        }
+    }
+}
+
+impl PartialEq for Path {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Path {}
+
+impl PartialOrd for Path {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The most promising path is the greatest, so that `BinaryHeap` pops it
+/// first: the lowest estimate, then the longest, then the lowest order
+impl Ord for Path {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .total_cmp(&self.estimate)
+            .then(self.num_branching.cmp(&other.num_branching))
+            .then(other.first_order.cmp(&self.first_order))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Circuit, GateDefn};
+    use crate::gate_scheduler::GreedyNonbranchingGateScheduler;
+    use crate::parser;
+    use crate::test_case;
+    use crate::types::BasisIdx64;
+    use std::fs;
+
+    #[test]
+    fn test_pick_next_gates() {
+        let gates = vec![
+            GateDefn::Hadamard(0),
+            GateDefn::X(1),
+            GateDefn::Hadamard(1),
+            GateDefn::Hadamard(0),
+        ]
+        .into_iter()
+        .map(Gate::<BasisIdx64>::new)
+        .collect::<Vec<_>>();
+        let mut scheduler = AStarGateScheduler::new(2, &gates, DependencyGraph::new(2, &gates), 3);
+
+        // the hadamards on qubit 0 undo each other before qubit 1 branches
+        assert_eq!(scheduler.pick_next_gates(), vec![0, 3]);
+        assert_eq!(scheduler.pick_next_gates(), vec![1, 2]);
+        assert!(scheduler.pick_next_gates().is_empty());
+    }
+
+    #[test]
+    fn test_visits_all_gates() {
+        let circuit = adder();
+        let mut visited = picks(AStarGateScheduler::new(
+            circuit.num_qubits,
+            &circuit.gates,
+            DependencyGraph::new(circuit.num_qubits, &circuit.gates),
+            5,
+        ))
+        .concat();
+        visited.sort_unstable();
+        assert_eq!(visited, (0..circuit.gates.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_cheaper_than_greedy_nonbranching() {
+        let circuit = adder();
+        let a_star = picks(AStarGateScheduler::new(
+            circuit.num_qubits,
+            &circuit.gates,
+            DependencyGraph::new(circuit.num_qubits, &circuit.gates),
+            5,
+        ));
+        let greedy = picks(GreedyNonbranchingGateScheduler::new(
+            circuit.num_qubits,
+            DependencyGraph::new(circuit.num_qubits, &circuit.gates),
+            circuit
+                .gates
+                .iter()
+                .map(|gate| gate.touches.as_slice())
+                .collect(),
+            circuit
+                .gates
+                .iter()
+                .map(|gate| gate.is_branching())
+                .collect(),
+            2,
+            false,
+        ));
+
+        assert_eq!(num_gate_apps(&circuit, &a_star), 266.0);
+        assert_eq!(num_gate_apps(&circuit, &greedy), 274.0);
+    }
+
+    fn adder() -> Circuit<BasisIdx64> {
+        let source = fs::read_to_string(test_case!("adder_n10.qasm")).unwrap();
+        let program = parser::parse_program(&source).unwrap();
+        Circuit::new(program).unwrap()
+    }
+
+    fn picks(mut scheduler: impl GateScheduler) -> Vec<Vec<GateIndex>> {
+        let mut picks = Vec::new();
+        loop {
+            let next_gates = scheduler.pick_next_gates();
+            if next_gates.is_empty() {
+                return picks;
+            }
+            picks.push(next_gates);
+        }
+    }
+
+    /// The gate applications of pushing the nonzeros through each of `picks`
+    /// as the sequential simulator does, where the paths a pick splits into
+    /// are only summed up at its end. The support model is exact for the
+    /// adder, which only branches inside its Toffolis.
+    fn num_gate_apps(circuit: &Circuit<BasisIdx64>, picks: &[Vec<GateIndex>]) -> Real {
+        let mut model = SupportModel::new(circuit.num_qubits);
+        let mut num_gate_apps = 0.0;
+        for pick in picks {
+            let mut num_paths = model.num_nonzeros();
+            for gi in pick {
+                num_gate_apps += num_paths;
+                if circuit.gates[*gi].is_branching() {
+                    num_paths *= 2.0;
+                }
+                model.apply(&circuit.gates[*gi]);
+            }
+        }
+        num_gate_apps
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::mem;
+use std::time::Duration;
+
+use super::{DependencyGraph, Expansion, Feedback, GateScheduler};
+use crate::types::{GateIndex, QubitIndex, Real};
+
+/// The largest number of branching gates picked at once
+const MAX_BRANCHING_STRIDE: usize = 8;
+/// Observations are pooled until they add up to this much time, so that the
+/// stride does not follow the noise of short steps
+const MIN_WINDOW: Duration = Duration::from_millis(10);
+
+/// Like `GreedyNonbranchingGateScheduler`, but tunes the branching stride to
+/// the throughput that the simulator observes. Picking more branching gates
+/// at once saves passes over the state but pushes each nonzero down more
+/// paths at once, and which wins depends on the circuit and the simulator,
+/// so the stride keeps moving in whichever direction last raised the
+/// throughput. Sparse and dense expansions are tuned separately, and each
+/// pick uses the stride of the expansion method last observed.
+pub struct AdaptiveGateScheduler<'a> {
+    dependencies: DependencyGraph,
+    gate_touches: Vec<&'a [QubitIndex]>,
+    gate_is_branching: Vec<bool>,
+    /// The stride that the tuning of each expansion method starts from
+    initial_branching_stride: usize,
+    disable_gate_fusion: bool,
+    tunings: HashMap<Expansion, Tuning>,
+    expansion: Expansion,
+    /// Gates picked since the last observation
+    num_unobserved_gates: usize,
+    num_nonzeros: usize,
+}
+
+struct Tuning {
+    branching_stride: usize,
+    is_growing: bool,
+    /// The observations pooled since the stride last changed
+    num_gate_apps: Real,
+    duration: Duration,
+    last_throughput: Option<Real>,
+}
+
+impl<'a> GateScheduler for AdaptiveGateScheduler<'a> {
+    fn pick_next_gates(&mut self) -> Vec<GateIndex> {
+        let next_gates = if self.disable_gate_fusion {
+            match self.visit_first(false).or_else(|| self.visit_first(true)) {
+                Some(gi) => vec![gi],
+                None => vec![],
+            }
+        } else {
+            let mut next_gates = Vec::new();
+            for _ in 0..self.branching_stride() {
+                while let Some(gi) = self.visit_first(false) {
+                    next_gates.push(gi);
+                }
+                match self.visit_first(true) {
+                    Some(gi) => next_gates.push(gi),
+                    None => break,
+                }
+            }
+            next_gates
+        };
+
+        log::debug!("next gates: {:?}", next_gates);
+
+        self.num_unobserved_gates += next_gates.len();
+        next_gates
+    }
+
+    fn observe(&mut self, feedback: &Feedback) {
+        // each gate applies to about as many nonzeros as the larger of the
+        // states before and after it
+        let num_gate_apps = mem::take(&mut self.num_unobserved_gates) as Real
+            * self.num_nonzeros.max(feedback.num_nonzeros) as Real;
+        self.num_nonzeros = feedback.num_nonzeros;
+        self.expansion = feedback.expansion;
+        if self.disable_gate_fusion {
+            return;
+        }
+
+        let initial_branching_stride = self.initial_branching_stride;
+        let tuning = self
+            .tunings
+            .entry(feedback.expansion)
+            .or_insert_with(|| Tuning::new(initial_branching_stride));
+        tuning.num_gate_apps += num_gate_apps;
+        tuning.duration += feedback.duration;
+        if tuning.duration < MIN_WINDOW {
+            return;
+        }
+
+        let throughput = tuning.num_gate_apps / tuning.duration.as_secs_f64();
+        if tuning.last_throughput.is_some_and(|last| throughput < last) {
+            tuning.is_growing = !tuning.is_growing;
+        }
+        tuning.branching_stride = if tuning.is_growing {
+            (tuning.branching_stride + 1).min(MAX_BRANCHING_STRIDE)
+        } else {
+            (tuning.branching_stride - 1).max(1)
+        };
+        log::debug!(
+            "{:?} throughput: {:.2}M gates/s, next branching stride: {}",
+            feedback.expansion,
+            throughput / 1e6,
+            tuning.branching_stride
+        );
+
+        tuning.num_gate_apps = 0.0;
+        tuning.duration = Duration::ZERO;
+        tuning.last_throughput = Some(throughput);
+    }
+}
+
+impl<'a> AdaptiveGateScheduler<'a> {
+    pub fn new(
+        num_qubits: usize,
+        dependencies: DependencyGraph,
+        gate_touches: Vec<&'a [QubitIndex]>,
+        gate_is_branching: Vec<bool>,
+        max_branching_stride: usize,
+        disable_gate_fusion: bool,
+    ) -> Self {
+        log::debug!(
+            "initializing adaptive gate scheduler with {} gates and {} qubits",
+            dependencies.num_gates(),
+            num_qubits
+        );
+        assert_eq!(gate_touches.len(), dependencies.num_gates());
+        assert_eq!(gate_is_branching.len(), dependencies.num_gates());
+        assert!(max_branching_stride > 0);
+
+        Self {
+            dependencies,
+            gate_touches,
+            gate_is_branching,
+            initial_branching_stride: max_branching_stride.min(MAX_BRANCHING_STRIDE),
+            disable_gate_fusion,
+            tunings: HashMap::new(),
+            expansion: Expansion::Sparse,
+            num_unobserved_gates: 0,
+            num_nonzeros: 1,
+        }
+    }
+
+    fn branching_stride(&self) -> usize {
+        self.tunings
+            .get(&self.expansion)
+            .map_or(self.initial_branching_stride, |tuning| {
+                tuning.branching_stride
+            })
+    }
+
+    fn visit_first(&mut self, branching: bool) -> Option<GateIndex> {
+        self.dependencies
+            .visit_first(&self.gate_touches, &self.gate_is_branching, branching)
+    }
+}
+
+impl Tuning {
+    fn new(branching_stride: usize) -> Self {
+        Self {
+            branching_stride,
+            is_growing: true,
+            num_gate_apps: 0.0,
+            duration: Duration::ZERO,
+            last_throughput: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Gate, GateDefn};
+    use crate::types::BasisIdx64;
+
+    fn feedback(num_nonzeros: usize, expansion: Expansion, millis: u64) -> Feedback {
+        Feedback {
+            num_nonzeros,
+            expansion,
+            duration: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn test_observe() {
+        let gates = (0..8)
+            .map(|qi| Gate::<BasisIdx64>::new(GateDefn::Hadamard(qi)))
+            .collect::<Vec<_>>();
+        let mut scheduler = AdaptiveGateScheduler::new(
+            8,
+            DependencyGraph::new(8, &gates),
+            gates.iter().map(|gate| gate.touches.as_slice()).collect(),
+            gates.iter().map(|gate| gate.is_branching()).collect(),
+            2,
+            false,
+        );
+
+        assert_eq!(scheduler.pick_next_gates(), vec![0, 1]);
+        // too short to tell
+        scheduler.observe(&feedback(4, Expansion::Sparse, 1));
+        assert_eq!(scheduler.pick_next_gates(), vec![2, 3]);
+        scheduler.observe(&feedback(16, Expansion::Sparse, 19));
+        assert_eq!(scheduler.pick_next_gates(), vec![4, 5, 6]);
+        // slower than before, so the stride shrinks again
+        scheduler.observe(&feedback(128, Expansion::Sparse, 1000));
+        assert_eq!(scheduler.pick_next_gates(), vec![7]);
+        // dense expansions are tuned from the initial stride again
+        scheduler.observe(&feedback(256, Expansion::Dense, 10));
+        assert_eq!(scheduler.branching_stride(), 3);
+        assert!(scheduler.pick_next_gates().is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::BTreeSet;
+use std::mem;
+
+use crate::circuit::{Gate, QubitBasis};
+use crate::types::{BasisIdx, GateIndex, QubitIndex};
+
+/// The order that gates must be visited in, up to commutation: a gate depends
+/// on an earlier gate only if they share a qubit on which they are not
+/// diagonal in the same basis. For example, a T gate on the control of a CX
+/// can be visited before or after it.
+pub struct DependencyGraph {
+    predecessors: Vec<Vec<GateIndex>>,
+    successors: Vec<Vec<GateIndex>>,
+    num_pending_predecessors: Vec<usize>,
+    visited: Vec<bool>,
+    ready: BTreeSet<GateIndex>,
+}
+
+/// The gates since the last change of basis on a qubit, which commute with
+/// each other, and the gates of the run before them
+#[derive(Default)]
+struct QubitRuns {
+    basis: Option<QubitBasis>,
+    current: Vec<GateIndex>,
+    previous: Vec<GateIndex>,
+}
+
+impl DependencyGraph {
+    pub fn new<B: BasisIdx>(num_qubits: usize, gates: &[Gate<B>]) -> Self {
+        let mut runs = (0..num_qubits)
+            .map(|_| QubitRuns::default())
+            .collect::<Vec<_>>();
+
+        let predecessors = gates
+            .iter()
+            .enumerate()
+            .map(|(gi, gate)| {
+                let mut predecessors = gate
+                    .touches
+                    .iter()
+                    .flat_map(|qi| runs[*qi].push(gi, gate.defn.qubit_basis(*qi)))
+                    .collect::<Vec<_>>();
+                predecessors.sort_unstable();
+                predecessors.dedup();
+                predecessors
+            })
+            .collect::<Vec<_>>();
+
+        let mut successors = vec![Vec::new(); gates.len()];
+        for (gi, predecessors) in predecessors.iter().enumerate() {
+            for pred in predecessors {
+                successors[*pred].push(gi);
+            }
+        }
+
+        let num_pending_predecessors = predecessors.iter().map(Vec::len).collect::<Vec<_>>();
+        let ready = (0..gates.len())
+            .filter(|gi| num_pending_predecessors[*gi] == 0)
+            .collect();
+
+        Self {
+            predecessors,
+            successors,
+            num_pending_predecessors,
+            visited: vec![false; gates.len()],
+            ready,
+        }
+    }
+
+    pub fn num_gates(&self) -> usize {
+        self.visited.len()
+    }
+
+    pub fn is_visited(&self, gi: GateIndex) -> bool {
+        self.visited[gi]
+    }
+
+    pub fn is_ready(&self, gi: GateIndex) -> bool {
+        self.ready.contains(&gi)
+    }
+
+    /// The gates that can be visited next, in circuit order
+    pub fn ready(&self) -> impl Iterator<Item = GateIndex> + '_ {
+        self.ready.iter().copied()
+    }
+
+    /// The gates that `gi` must be visited after
+    pub fn predecessors(&self, gi: GateIndex) -> &[GateIndex] {
+        &self.predecessors[gi]
+    }
+
+    /// The gates that must be visited after `gi`
+    pub fn successors(&self, gi: GateIndex) -> &[GateIndex] {
+        &self.successors[gi]
+    }
+
+    /// The first gate that `gi` waits for
+    pub fn pending_predecessor(&self, gi: GateIndex) -> Option<GateIndex> {
+        self.predecessors[gi]
+            .iter()
+            .copied()
+            .find(|pred| !self.visited[*pred])
+    }
+
+    /// Visits a ready gate that branches as `branching`, preferring gates on
+    /// lower qubits so that qubits are finished one after another
+    pub fn visit_first(
+        &mut self,
+        gate_touches: &[&[QubitIndex]],
+        gate_is_branching: &[bool],
+        branching: bool,
+    ) -> Option<GateIndex> {
+        let candidate = self
+            .ready()
+            .filter(|gi| gate_is_branching[*gi] == branching)
+            .min_by_key(|gi| (gate_touches[*gi].iter().min().copied(), *gi));
+
+        if let Some(gi) = candidate {
+            self.visit(gi);
+        }
+        candidate
+    }
+
+    pub fn visit(&mut self, gi: GateIndex) {
+        log::debug!("visiting gate: {}", gi);
+        assert!(self.ready.remove(&gi), "gate {} is not ready", gi);
+
+        self.visited[gi] = true;
+        for succ in &self.successors[gi] {
+            self.num_pending_predecessors[*succ] -= 1;
+            if self.num_pending_predecessors[*succ] == 0 {
+                self.ready.insert(*succ);
+            }
+        }
+    }
+}
+
+impl QubitRuns {
+    /// Adds gate `gi`, which is diagonal in `basis` on the qubit, and returns
+    /// the gates on the qubit that it depends on
+    fn push(&mut self, gi: GateIndex, basis: QubitBasis) -> Vec<GateIndex> {
+        if basis == QubitBasis::General || self.basis != Some(basis) {
+            self.previous = mem::take(&mut self.current);
+            self.basis = Some(basis);
+        }
+        self.current.push(gi);
+        self.previous.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::GateDefn;
+    use crate::types::BasisIdx64;
+
+    #[test]
+    fn test_commuting_gates() {
+        let gates = vec![
+            GateDefn::Hadamard(0),
+            GateDefn::CX {
+                control: 0,
+                target: 1,
+            },
+            GateDefn::T(0),
+            GateDefn::X(1),
+            GateDefn::Hadamard(1),
+            GateDefn::CZ {
+                control: 0,
+                target: 2,
+            },
+            GateDefn::Hadamard(0),
+        ]
+        .into_iter()
+        .map(Gate::<BasisIdx64>::new)
+        .collect::<Vec<_>>();
+        let mut graph = DependencyGraph::new(3, &gates);
+
+        assert_eq!(graph.ready().collect::<Vec<_>>(), vec![0, 3]);
+        assert_eq!(graph.pending_predecessor(4), Some(1));
+
+        graph.visit(0);
+        // t and cz commute with the control of cx, and x with its target
+        assert_eq!(graph.ready().collect::<Vec<_>>(), vec![1, 2, 3, 5]);
+
+        graph.visit(5);
+        graph.visit(2);
+        assert!(!graph.is_ready(6));
+        graph.visit(1);
+        assert_eq!(graph.ready().collect::<Vec<_>>(), vec![3, 6]);
+        graph.visit(3);
+        graph.visit(4);
+        graph.visit(6);
+        assert!(graph.ready().next().is_none());
+        assert!((0..graph.num_gates()).all(|gi| graph.is_visited(gi)));
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use super::{Feedback, GateScheduler};
+use crate::types::{GateIndex, QubitIndex};
+
+/// Regroups the gates picked by another scheduler into runs of consecutive
+/// gates that touch at most `max_qubits` qubits together, for simulators
+/// that apply each pick as a single dense unitary. A gate wider than
+/// `max_qubits` is picked on its own.
+pub struct FusingGateScheduler<'a> {
+    scheduler: Box<dyn GateScheduler + 'a>,
+    gate_touches: Vec<&'a [QubitIndex]>,
+    max_qubits: usize,
+    pending: VecDeque<GateIndex>,
+    /// Whether the other scheduler has returned its empty pick, after which
+    /// it is not asked again
+    exhausted: bool,
+    /// The time spent on the runs of the other scheduler's pending pick
+    duration: Duration,
+}
+
+impl<'a> GateScheduler for FusingGateScheduler<'a> {
+    fn pick_next_gates(&mut self) -> Vec<GateIndex> {
+        let mut run = Vec::new();
+        let mut qubits = Vec::<QubitIndex>::new();
+
+        loop {
+            if self.pending.is_empty() && !self.exhausted {
+                let next_gates = self.scheduler.pick_next_gates();
+                self.exhausted = next_gates.is_empty();
+                self.pending.extend(next_gates);
+            }
+            let gi = match self.pending.front() {
+                Some(&gi) => gi,
+                None => break,
+            };
+
+            let mut touches = qubits.clone();
+            touches.extend(self.gate_touches[gi]);
+            touches.sort_unstable();
+            touches.dedup();
+            if !run.is_empty() && touches.len() > self.max_qubits {
+                break;
+            }
+
+            self.pending.pop_front();
+            run.push(gi);
+            qubits = touches;
+        }
+
+        log::debug!("fused gates: {:?} on qubits {:?}", run, qubits);
+
+        run
+    }
+
+    /// Tells the other scheduler once all gates of its pick are applied
+    fn observe(&mut self, feedback: &Feedback) {
+        self.duration += feedback.duration;
+        if self.pending.is_empty() {
+            self.scheduler.observe(&Feedback {
+                duration: self.duration,
+                ..*feedback
+            });
+            self.duration = Duration::ZERO;
+        }
+    }
+}
+
+impl<'a> FusingGateScheduler<'a> {
+    pub fn new(
+        scheduler: Box<dyn GateScheduler + 'a>,
+        gate_touches: Vec<&'a [QubitIndex]>,
+        max_qubits: usize,
+    ) -> Self {
+        Self {
+            scheduler,
+            gate_touches,
+            max_qubits,
+            pending: VecDeque::new(),
+            exhausted: false,
+            duration: Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::NaiveGateScheduler;
+    use super::*;
+
+    /// Picks each gate on its own, and panics if asked again after its
+    /// empty pick
+    struct OnceGateScheduler {
+        next: GateIndex,
+        num_gates: usize,
+    }
+
+    impl GateScheduler for OnceGateScheduler {
+        fn pick_next_gates(&mut self) -> Vec<GateIndex> {
+            assert!(self.next <= self.num_gates, "asked after the empty pick");
+            self.next += 1;
+            if self.next > self.num_gates {
+                vec![]
+            } else {
+                vec![self.next - 1]
+            }
+        }
+    }
+
+    #[test]
+    fn test_pick_next_gates() {
+        let gate_touches: Vec<&[QubitIndex]> = vec![&[0], &[0, 1], &[1], &[2, 3, 4], &[0, 4], &[3]];
+        let mut scheduler = FusingGateScheduler::new(
+            Box::new(NaiveGateScheduler::new(gate_touches.len())),
+            gate_touches,
+            2,
+        );
+
+        assert_eq!(scheduler.pick_next_gates(), vec![0, 1, 2]);
+        assert_eq!(scheduler.pick_next_gates(), vec![3]);
+        assert_eq!(scheduler.pick_next_gates(), vec![4]);
+        assert_eq!(scheduler.pick_next_gates(), vec![5]);
+        assert!(scheduler.pick_next_gates().is_empty());
+    }
+
+    #[test]
+    fn test_stops_after_empty_pick() {
+        let gate_touches: Vec<&[QubitIndex]> = vec![&[0], &[1]];
+        let mut scheduler = FusingGateScheduler::new(
+            Box::new(OnceGateScheduler {
+                next: 0,
+                num_gates: 2,
+            }),
+            gate_touches,
+            2,
+        );
+
+        assert_eq!(scheduler.pick_next_gates(), vec![0, 1]);
+        assert!(scheduler.pick_next_gates().is_empty());
+        assert!(scheduler.pick_next_gates().is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use super::{DependencyGraph, GateScheduler};
+use crate::types::{GateIndex, QubitIndex};
+
+/// Visits branching gates as early as possible, visiting a nonbranching gate
+/// only when no branching gate is ready. This is the opposite of
+/// `GreedyNonbranchingGateScheduler`, and mostly a baseline to compare it
+/// against. Unless gate fusion is disabled, each pick goes on until it has
+/// visited `max_branching_stride` branching gates.
+pub struct GreedyBranchingGateScheduler<'a> {
+    dependencies: DependencyGraph,
+    gate_touches: Vec<&'a [QubitIndex]>,
+    gate_is_branching: Vec<bool>,
+    max_branching_stride: usize,
+    disable_gate_fusion: bool,
+}
+
+impl<'a> GateScheduler for GreedyBranchingGateScheduler<'a> {
+    fn pick_next_gates(&mut self) -> Vec<GateIndex> {
+        let max_branching_stride = if self.disable_gate_fusion {
+            1
+        } else {
+            self.max_branching_stride
+        };
+
+        let mut num_branching_so_far = 0;
+        let mut next_gates = Vec::new();
+        while num_branching_so_far < max_branching_stride {
+            if let Some(gi) = self.visit_first(true) {
+                num_branching_so_far += 1;
+                next_gates.push(gi);
+            } else if let Some(gi) = self.visit_first(false) {
+                next_gates.push(gi);
+                if self.disable_gate_fusion {
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+
+        log::debug!("next gates: {:?}", next_gates);
+
+        next_gates
+    }
+}
+
+impl<'a> GreedyBranchingGateScheduler<'a> {
+    pub fn new(
+        num_qubits: usize,
+        dependencies: DependencyGraph,
+        gate_touches: Vec<&'a [QubitIndex]>,
+        gate_is_branching: Vec<bool>,
+        max_branching_stride: usize,
+        disable_gate_fusion: bool,
+    ) -> Self {
+        log::debug!(
+            "initializing greedy branching gate scheduler with {} gates and {} qubits",
+            dependencies.num_gates(),
+            num_qubits
+        );
+        assert_eq!(gate_touches.len(), dependencies.num_gates());
+        assert_eq!(gate_is_branching.len(), dependencies.num_gates());
+        assert!(max_branching_stride > 0);
+
+        Self {
+            dependencies,
+            gate_touches,
+            gate_is_branching,
+            max_branching_stride,
+            disable_gate_fusion,
+        }
+    }
+
+    fn visit_first(&mut self, branching: bool) -> Option<GateIndex> {
+        self.dependencies
+            .visit_first(&self.gate_touches, &self.gate_is_branching, branching)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Gate, GateDefn};
+    use crate::types::BasisIdx64;
+
+    fn picks(max_branching_stride: usize, disable_gate_fusion: bool) -> Vec<Vec<GateIndex>> {
+        let gates = vec![
+            GateDefn::X(0),
+            GateDefn::Hadamard(0),
+            GateDefn::PauliZ(1),
+            GateDefn::Hadamard(2),
+            GateDefn::CX {
+                control: 0,
+                target: 1,
+            },
+        ]
+        .into_iter()
+        .map(Gate::<BasisIdx64>::new)
+        .collect::<Vec<_>>();
+        let mut scheduler = GreedyBranchingGateScheduler::new(
+            3,
+            DependencyGraph::new(3, &gates),
+            gates.iter().map(|gate| gate.touches.as_slice()).collect(),
+            gates.iter().map(|gate| gate.is_branching()).collect(),
+            max_branching_stride,
+            disable_gate_fusion,
+        );
+
+        let mut picks = Vec::new();
+        loop {
+            let next_gates = scheduler.pick_next_gates();
+            if next_gates.is_empty() {
+                return picks;
+            }
+            picks.push(next_gates);
+        }
+    }
+
+    #[test]
+    fn test_pick_next_gates() {
+        assert_eq!(
+            picks(2, true),
+            vec![vec![3], vec![0], vec![1], vec![2], vec![4]]
+        );
+        assert_eq!(picks(1, false), vec![vec![3], vec![0, 1], vec![2, 4]]);
+        assert_eq!(picks(2, false), vec![vec![3, 0, 1], vec![2, 4]]);
+    }
+}
@@ -1,65 +1,74 @@
-use super::{utility, GateScheduler};
+use super::{DependencyGraph, GateScheduler};
 use crate::types::{GateIndex, QubitIndex};
 
-pub struct GreedyFinishQubitGateScheduler<'a> {
-    frontier: Vec<GateIndex>,
-    num_gates: usize,
-    num_qubits: usize,
-    gate_touches: Vec<&'a [QubitIndex]>,
+pub struct GreedyFinishQubitGateScheduler {
+    dependencies: DependencyGraph,
+    /// The gates that touch each qubit, in circuit order
+    qubit_gates: Vec<Vec<GateIndex>>,
+    /// Where the unvisited gates in `qubit_gates` start; gates commuting with
+    /// earlier ones may have been visited after it
+    next_on_qubit: Vec<usize>,
 }
 
-impl<'a> GateScheduler for GreedyFinishQubitGateScheduler<'a> {
+impl GateScheduler for GreedyFinishQubitGateScheduler {
     fn pick_next_gates(&mut self) -> Vec<GateIndex> {
-        let unfinished_qubit = (0..self.num_qubits).find(|qi| self.frontier[*qi] < self.num_gates);
+        let unfinished_gate =
+            (0..self.qubit_gates.len()).find_map(|qi| self.next_unvisited_gate(qi));
 
-        match unfinished_qubit {
-            Some(qi) => self.make_progress_on_qubit(qi),
+        match unfinished_gate {
+            Some(gi) => self.make_progress_on_gate(gi),
             None => Vec::new(),
         }
     }
 }
 
-impl<'a> GreedyFinishQubitGateScheduler<'a> {
-    pub fn new(num_gates: usize, num_qubits: usize, gate_touches: Vec<&'a [QubitIndex]>) -> Self {
+impl GreedyFinishQubitGateScheduler {
+    pub fn new(
+        num_qubits: usize,
+        dependencies: DependencyGraph,
+        gate_touches: Vec<&[QubitIndex]>,
+    ) -> Self {
         log::debug!(
             "initializing greedy finish qubit gate scheduler with {} gates and {} qubits",
-            num_gates,
+            dependencies.num_gates(),
             num_qubits
         );
 
+        let mut qubit_gates = vec![Vec::new(); num_qubits];
+        for (gi, touches) in gate_touches.iter().enumerate() {
+            for qi in touches.iter() {
+                qubit_gates[*qi].push(gi);
+            }
+        }
+
         GreedyFinishQubitGateScheduler {
-            frontier: (0..num_qubits)
-                .map(|qi| utility::next_touch(num_gates, &gate_touches, qi, 0))
-                .collect(),
-            num_gates,
-            num_qubits,
-            gate_touches,
+            dependencies,
+            qubit_gates,
+            next_on_qubit: vec![0; num_qubits],
         }
     }
 
-    fn make_progress_on_qubit(&mut self, qi: QubitIndex) -> Vec<GateIndex> {
-        let desired_gate = self.frontier[qi];
+    fn next_unvisited_gate(&mut self, qi: QubitIndex) -> Option<GateIndex> {
+        let gates = &self.qubit_gates[qi];
+        let next = &mut self.next_on_qubit[qi];
+        while *next < gates.len() && self.dependencies.is_visited(gates[*next]) {
+            *next += 1;
+        }
+
+        gates.get(*next).copied()
+    }
 
-        if utility::okay_to_visit(
-            self.num_gates,
-            &self.gate_touches,
-            &self.frontier,
-            desired_gate,
-        ) {
-            utility::mark_as_visit(
-                self.num_gates,
-                &self.gate_touches,
-                &mut self.frontier,
-                desired_gate,
-            );
+    fn make_progress_on_gate(&mut self, desired_gate: GateIndex) -> Vec<GateIndex> {
+        if self.dependencies.is_ready(desired_gate) {
+            self.dependencies.visit(desired_gate);
             vec![desired_gate]
         } else {
-            let dependency = self.gate_touches[desired_gate]
-                .iter()
-                .find(|qj| self.frontier[**qj] < desired_gate)
-                .expect("since desired_gate is not okay to visit, there must be a dependency");
+            let dependency = self
+                .dependencies
+                .pending_predecessor(desired_gate)
+                .expect("since desired_gate is not ready, there must be a dependency");
 
-            self.make_progress_on_qubit(*dependency)
+            self.make_progress_on_gate(dependency)
         }
     }
 }
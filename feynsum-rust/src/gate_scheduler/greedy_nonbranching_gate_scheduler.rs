@@ -1,10 +1,8 @@
-use super::{utility, GateScheduler};
+use super::{DependencyGraph, GateScheduler};
 use crate::types::{GateIndex, QubitIndex};
 
 pub struct GreedyNonbranchingGateScheduler<'a> {
-    frontier: Vec<GateIndex>,
-    num_gates: usize,
-    num_qubits: usize,
+    dependencies: DependencyGraph,
     gate_touches: Vec<&'a [QubitIndex]>,
     gate_is_branching: Vec<bool>,
     max_branching_stride: usize,
@@ -37,12 +35,9 @@ impl<'a> GateScheduler for GreedyNonbranchingGateScheduler<'a> {
             }
 
             // each gate in next_gates should be marked as already visited
-            assert!(next_gates.iter().all(|gi| !utility::okay_to_visit(
-                self.num_gates,
-                &self.gate_touches,
-                &self.frontier,
-                *gi
-            )));
+            assert!(next_gates
+                .iter()
+                .all(|gi| self.dependencies.is_visited(*gi)));
 
             log::debug!("next gates: {:?}", next_gates);
 
@@ -53,143 +48,89 @@ impl<'a> GateScheduler for GreedyNonbranchingGateScheduler<'a> {
 
 impl<'a> GreedyNonbranchingGateScheduler<'a> {
     pub fn new(
-        num_gates: usize,
         num_qubits: usize,
+        dependencies: DependencyGraph,
         gate_touches: Vec<&'a [QubitIndex]>,
         gate_is_branching: Vec<bool>,
+        max_branching_stride: usize,
         disable_gate_fusion: bool,
     ) -> Self {
         log::debug!(
             "initializing greedy nonbranching gate scheduler with {} gates and {} qubits",
-            num_gates,
+            dependencies.num_gates(),
             num_qubits
         );
-        let scheduler = Self {
-            frontier: (0..num_qubits)
-                .map(|qi| next_touch(num_gates, &gate_touches, qi, 0))
-                .collect(),
-            num_gates,
-            num_qubits,
+        assert_eq!(gate_touches.len(), dependencies.num_gates());
+        assert_eq!(gate_is_branching.len(), dependencies.num_gates());
+        assert!(max_branching_stride > 0);
+
+        Self {
+            dependencies,
             gate_touches,
             gate_is_branching,
-            max_branching_stride: 2,
+            max_branching_stride,
             disable_gate_fusion,
-        };
-
-        assert_eq!(scheduler.frontier.len(), num_qubits);
-        assert_eq!(scheduler.gate_touches.len(), num_gates);
-        assert_eq!(scheduler.gate_is_branching.len(), num_gates);
-
-        log::debug!("initial frontier: {:?}", scheduler.frontier);
+        }
+    }
 
-        scheduler
+    fn visit_first(&mut self, branching: bool) -> Option<GateIndex> {
+        self.dependencies
+            .visit_first(&self.gate_touches, &self.gate_is_branching, branching)
     }
 
     fn visit_nonbranching(&mut self) -> Option<GateIndex> {
-        let candidate = self
-            .frontier
-            .iter()
-            .filter(|gi| {
-                *gi < &self.num_gates
-                    && !self.gate_is_branching[**gi]
-                    && utility::okay_to_visit(
-                        self.num_gates,
-                        &self.gate_touches,
-                        &self.frontier,
-                        **gi,
-                    )
-            })
-            .nth(0)
-            .cloned();
-
-        if let Some(gi) = candidate {
-            utility::mark_as_visit(self.num_gates, &self.gate_touches, &mut self.frontier, gi);
-        }
-        candidate
+        self.visit_first(false)
     }
 
+    /// Visits nonbranching gates until only branching ones are ready. These
+    /// include the nonbranching gates after a branching one that commute
+    /// with it.
     fn visit_maximal_nonbranching_run(&mut self) -> Vec<GateIndex> {
         let mut non_branching_gates = Vec::new();
-
-        loop {
-            let mut selection = Vec::<GateIndex>::new();
-
-            for qi in 0..self.num_qubits {
-                loop {
-                    let next_gi = self.frontier[qi];
-                    if next_gi >= self.num_gates
-                        || self.gate_is_branching[next_gi]
-                        || !utility::okay_to_visit(
-                            self.num_gates,
-                            &self.gate_touches,
-                            &self.frontier,
-                            next_gi,
-                        )
-                    {
-                        break;
-                    } else {
-                        assert!(utility::okay_to_visit(
-                            self.num_gates,
-                            &self.gate_touches,
-                            &self.frontier,
-                            next_gi
-                        ));
-                        utility::mark_as_visit(
-                            self.num_gates,
-                            &self.gate_touches,
-                            &mut self.frontier,
-                            next_gi,
-                        );
-                        selection.push(next_gi);
-                    }
-                }
-            }
-
-            if selection.is_empty() {
-                break;
-            } else {
-                non_branching_gates.append(&mut selection);
-            }
+        while let Some(gi) = self.visit_nonbranching() {
+            non_branching_gates.push(gi);
         }
         non_branching_gates
     }
 
     fn visit_branching(&mut self) -> Option<GateIndex> {
-        let result = self
-            .frontier
-            .iter()
-            .filter(|gi| {
-                *gi < &self.num_gates
-                    && self.gate_is_branching[**gi]
-                    && utility::okay_to_visit(
-                        self.num_gates,
-                        &self.gate_touches,
-                        &self.frontier,
-                        **gi,
-                    )
-            })
-            .nth(0)
-            .copied();
-
-        if let Some(gi) = result {
-            utility::mark_as_visit(self.num_gates, &self.gate_touches, &mut self.frontier, gi);
-        }
-
-        result
+        self.visit_first(true)
     }
 }
 
-fn next_touch(
-    num_gates: usize,
-    gate_touches: &[&[QubitIndex]],
-    qi: QubitIndex,
-    gi: GateIndex,
-) -> GateIndex {
-    if gi >= num_gates {
-        num_gates
-    } else if gate_touches[gi].contains(&qi) {
-        gi
-    } else {
-        next_touch(num_gates, gate_touches, qi, gi + 1)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Gate, GateDefn};
+    use crate::types::BasisIdx64;
+
+    fn picks(max_branching_stride: usize) -> Vec<Vec<GateIndex>> {
+        let gates = vec![GateDefn::Hadamard(0), GateDefn::Hadamard(1), GateDefn::X(2)]
+            .into_iter()
+            .map(Gate::<BasisIdx64>::new)
+            .collect::<Vec<_>>();
+        let mut scheduler = GreedyNonbranchingGateScheduler::new(
+            3,
+            DependencyGraph::new(3, &gates),
+            gates.iter().map(|gate| gate.touches.as_slice()).collect(),
+            gates.iter().map(|gate| gate.is_branching()).collect(),
+            max_branching_stride,
+            false,
+        );
+
+        let mut picks = Vec::new();
+        loop {
+            let next_gates = scheduler.pick_next_gates();
+            if next_gates.is_empty() {
+                return picks;
+            }
+            picks.push(next_gates);
+        }
+    }
+
+    #[test]
+    fn test_max_branching_stride() {
+        assert_eq!(picks(1), vec![vec![2, 0], vec![1]]);
+        assert_eq!(picks(2), vec![vec![2, 0, 1]]);
     }
 }
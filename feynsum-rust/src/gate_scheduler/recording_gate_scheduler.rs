@@ -0,0 +1,26 @@
+use super::{Feedback, GateScheduler, ScheduleCursor};
+use crate::types::GateIndex;
+
+/// Passes on the picks of another scheduler, and pushes them to `cursor`
+pub struct RecordingGateScheduler<'a> {
+    scheduler: Box<dyn GateScheduler + 'a>,
+    cursor: &'a ScheduleCursor,
+}
+
+impl<'a> GateScheduler for RecordingGateScheduler<'a> {
+    fn pick_next_gates(&mut self) -> Vec<GateIndex> {
+        let next_gates = self.scheduler.pick_next_gates();
+        self.cursor.push(next_gates.clone());
+        next_gates
+    }
+
+    fn observe(&mut self, feedback: &Feedback) {
+        self.scheduler.observe(feedback);
+    }
+}
+
+impl<'a> RecordingGateScheduler<'a> {
+    pub fn new(scheduler: Box<dyn GateScheduler + 'a>, cursor: &'a ScheduleCursor) -> Self {
+        Self { scheduler, cursor }
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::VecDeque;
+
+use super::{DependencyGraph, GateScheduler, ScheduleCursor};
+use crate::circuit::Gate;
+use crate::error::{self, Error, ErrorKind};
+use crate::types::{BasisIdx, GateIndex};
+
+/// Picks the gates of a recorded or hand-written `Schedule`, up to the empty
+/// pick that ends the segment. The picks may visit gates in any order that
+/// the dependency graph allows, but must visit each gate exactly once, which
+/// is checked when the scheduler is created.
+pub struct ReplayGateScheduler {
+    picks: VecDeque<Vec<GateIndex>>,
+}
+
+impl GateScheduler for ReplayGateScheduler {
+    fn pick_next_gates(&mut self) -> Vec<GateIndex> {
+        let next_gates = self.picks.pop_front().unwrap_or_default();
+
+        log::debug!("next gates: {:?}", next_gates);
+
+        next_gates
+    }
+}
+
+impl ReplayGateScheduler {
+    /// Takes the picks of this segment off `cursor`, and fails if they
+    /// visit a gate out of order or twice, or end before visiting them all
+    pub fn new<B: BasisIdx>(
+        num_qubits: usize,
+        gates: &[Gate<B>],
+        mut dependencies: DependencyGraph,
+        cursor: &ScheduleCursor,
+    ) -> error::Result<Self> {
+        log::debug!(
+            "initializing replay gate scheduler with {} gates and {} qubits",
+            dependencies.num_gates(),
+            num_qubits
+        );
+
+        let mut picks = VecDeque::new();
+        loop {
+            let next_gates = cursor.pop().unwrap_or_default();
+            if next_gates.is_empty() {
+                if let Some(gi) = dependencies.ready().next() {
+                    log::error!("the replayed schedule ends before gate {}", gi);
+                    return Err(Error::new(
+                        ErrorKind::Schedule,
+                        format!(
+                            "the replayed schedule ends a segment before visiting gate {}",
+                            gi
+                        ),
+                    )
+                    .at(gates[gi].location));
+                }
+                return Ok(Self { picks });
+            }
+
+            for &gi in &next_gates {
+                if gi >= gates.len() {
+                    log::error!("the replayed schedule picks gate {}", gi);
+                    return Err(Error::new(
+                        ErrorKind::Schedule,
+                        format!(
+                            "the replayed schedule picks gate {}, but the segment has {} gates",
+                            gi,
+                            gates.len()
+                        ),
+                    ));
+                }
+                if !dependencies.is_ready(gi) {
+                    let message = match dependencies.pending_predecessor(gi) {
+                        Some(pred) => format!(
+                            "the replayed schedule picks gate {} before gate {}, which it depends on",
+                            gi, pred
+                        ),
+                        None => format!("the replayed schedule picks gate {} twice", gi),
+                    };
+                    log::error!("{}", message);
+                    return Err(Error::new(ErrorKind::Schedule, message).at(gates[gi].location));
+                }
+                dependencies.visit(gi);
+            }
+            picks.push_back(next_gates);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::GateDefn;
+    use crate::config::Config;
+    use crate::gate_scheduler::Schedule;
+    use crate::types::BasisIdx64;
+
+    fn replay(json: &str) -> error::Result<Vec<Vec<GateIndex>>> {
+        let gates = vec![
+            GateDefn::Hadamard(0),
+            GateDefn::X(1),
+            GateDefn::CX {
+                control: 0,
+                target: 1,
+            },
+        ]
+        .into_iter()
+        .map(Gate::<BasisIdx64>::new)
+        .collect::<Vec<_>>();
+        let cursor = ScheduleCursor::new(&Config {
+            replayed_schedule: Some(Schedule::from_json(json).unwrap()),
+            ..Config::default()
+        });
+        let mut scheduler =
+            ReplayGateScheduler::new(2, &gates, DependencyGraph::new(2, &gates), &cursor)?;
+
+        let mut picks = Vec::new();
+        loop {
+            let next_gates = scheduler.pick_next_gates();
+            if next_gates.is_empty() {
+                return Ok(picks);
+            }
+            picks.push(next_gates);
+        }
+    }
+
+    #[test]
+    fn test_pick_next_gates() {
+        // x commutes with the target of cx
+        assert_eq!(
+            replay("[[0], [2, 1], []]").unwrap(),
+            vec![vec![0], vec![2, 1]]
+        );
+    }
+
+    #[test]
+    fn test_bad_schedule() {
+        let message = |json| {
+            let err = replay(json).unwrap_err();
+            assert!(matches!(err.kind, ErrorKind::Schedule));
+            err.message
+        };
+        assert!(message("[[2], [0, 1], []]").contains("before gate 0"));
+        assert!(message("[[0, 0], [1, 2], []]").contains("twice"));
+        assert!(message("[[0, 3], [1, 2], []]").contains("has 3 gates"));
+        assert!(message("[[0, 1], []]").contains("before visiting gate 2"));
+        assert!(message("[[0, 1]]").contains("before visiting gate 2"));
+    }
+}
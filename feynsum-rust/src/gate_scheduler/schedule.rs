@@ -0,0 +1,149 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::types::GateIndex;
+
+/// The picks of the schedulers of one simulation, in order. Each scheduler's
+/// picks end with an empty one, so the picks of the segments of a dynamic
+/// circuit follow one another.
+///
+/// On disk it is a JSON array of the picks, one per line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schedule {
+    pub picks: Vec<Vec<GateIndex>>,
+}
+
+impl Schedule {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        Ok(Self {
+            picks: serde_json::from_str(json)?,
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        let lines = self
+            .picks
+            .iter()
+            .map(|pick| format!("  {}", serde_json::to_string(pick).unwrap()))
+            .collect::<Vec<_>>();
+        if lines.is_empty() {
+            "[]\n".to_string()
+        } else {
+            format!("[\n{}\n]\n", lines.join(",\n"))
+        }
+    }
+}
+
+/// Where one simulation is in `config.replayed_schedule`, and the picks it
+/// has recorded if `config.record_schedule` is set. The scheduler of each
+/// segment of a dynamic circuit takes up where the last one left off, so a
+/// simulation creates one cursor and hands it to all of its schedulers.
+#[derive(Default)]
+pub struct ScheduleCursor {
+    replayed: Option<RefCell<VecDeque<Vec<GateIndex>>>>,
+    recorded: Option<RefCell<Vec<Vec<GateIndex>>>>,
+}
+
+impl ScheduleCursor {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            replayed: config
+                .replayed_schedule
+                .as_ref()
+                .map(|schedule| RefCell::new(schedule.picks.iter().cloned().collect())),
+            recorded: config.record_schedule.then(|| RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replayed.is_some()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorded.is_some()
+    }
+
+    /// The next replayed pick, or `None` once they run out
+    pub fn pop(&self) -> Option<Vec<GateIndex>> {
+        self.replayed.as_ref()?.borrow_mut().pop_front()
+    }
+
+    pub fn push(&self, pick: Vec<GateIndex>) {
+        if let Some(recorded) = &self.recorded {
+            recorded.borrow_mut().push(pick);
+        }
+    }
+
+    /// The number of replayed picks that no scheduler has taken
+    pub fn num_left(&self) -> usize {
+        self.replayed
+            .as_ref()
+            .map_or(0, |replayed| replayed.borrow().len())
+    }
+
+    /// The recorded picks, if they were recorded
+    pub fn into_recorded(self) -> Option<Schedule> {
+        self.recorded.map(|recorded| Schedule {
+            picks: recorded.into_inner(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let schedule = Schedule {
+            picks: vec![vec![0, 2], vec![1], vec![]],
+        };
+
+        let json = schedule.to_json();
+        assert_eq!(json, "[\n  [0,2],\n  [1],\n  []\n]\n");
+        assert_eq!(Schedule::from_json(&json).unwrap(), schedule);
+
+        assert!(Schedule::from_json("[[0], 1]").is_err());
+    }
+
+    #[test]
+    fn test_cursor() {
+        let config = Config {
+            record_schedule: true,
+            replayed_schedule: Some(Schedule {
+                picks: vec![vec![0], vec![]],
+            }),
+            ..Config::default()
+        };
+        // each run starts from the first pick
+        for _ in 0..2 {
+            let cursor = ScheduleCursor::new(&config);
+            assert_eq!(cursor.pop(), Some(vec![0]));
+            assert_eq!(cursor.num_left(), 1);
+            cursor.push(vec![0]);
+            assert_eq!(
+                cursor.into_recorded(),
+                Some(Schedule {
+                    picks: vec![vec![0]]
+                })
+            );
+        }
+
+        let cursor = ScheduleCursor::default();
+        assert_eq!(cursor.pop(), None);
+        cursor.push(vec![0]);
+        assert_eq!(cursor.into_recorded(), None);
+    }
+}
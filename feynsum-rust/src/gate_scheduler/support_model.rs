@@ -0,0 +1,566 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::f64::consts::{PI, TAU};
+
+use nalgebra::DMatrix;
+
+use crate::circuit::{Gate, GateDefn, QubitBasis, Unitary};
+use crate::types::{constants, BasisIdx, Complex, QubitIndex, Real};
+use crate::utility;
+
+type Var = usize;
+/// A product of distinct variables, sorted, so that the empty product is 1
+type Monomial = Vec<Var>;
+/// A function of the variables as the sum of monomials times coefficients.
+/// Each boolean function is exactly one such polynomial, whose coefficients
+/// modulo 2 give the function as the exclusive or of monomials.
+type Poly = BTreeMap<Monomial, i64>;
+/// A boolean function as the exclusive or of monomials
+type BoolPoly = BTreeSet<Monomial>;
+
+/// Values with more terms than this are forgotten rather than combined
+const MAX_TERMS: usize = 64;
+/// Constraints with more terms than this are not solved, which takes time
+/// exponential in their number of terms
+const MAX_CONSTRAINT_TERMS: usize = 12;
+
+/// An estimate of the basis indices with nonzero weight, which schedulers use
+/// to compare the cost of gate orders without simulating them.
+///
+/// The state is kept as a sum over paths: each path is an assignment of the
+/// variables, which a branching gate adds one of, and has the basis index
+/// whose qubits are `values` of the assignment and the phase `phase` of it.
+/// Permutations with phases, such as X, CX, CCX, Swap, T and CZ, map the
+/// values and the phase exactly. When a variable no longer sets the value of
+/// any qubit, the paths that differ only in it cancel out unless the phase
+/// says otherwise, so branching gates that undo each other, such as the two
+/// Hadamards of a Toffoli, shrink the estimate `2^rank` again.
+#[derive(Clone)]
+pub struct SupportModel {
+    values: Vec<Poly>,
+    /// In radians, so only coefficients modulo 2π matter
+    phase: BTreeMap<Monomial, Real>,
+    /// The variables whose paths have weights that `phase` does not give,
+    /// which therefore never cancel out
+    opaque: BTreeSet<Var>,
+    num_vars: usize,
+}
+
+impl SupportModel {
+    /// The support of the all-zeros state
+    pub fn new(num_qubits: usize) -> Self {
+        Self {
+            values: vec![Poly::new(); num_qubits],
+            phase: BTreeMap::new(),
+            opaque: BTreeSet::new(),
+            num_vars: 0,
+        }
+    }
+
+    /// The number of distinct values of the qubits over all paths, if the
+    /// values are linear in the variables, and an upper bound otherwise
+    pub fn num_nonzeros(&self) -> Real {
+        let mut rows = BTreeMap::<Monomial, BoolPoly>::new();
+        let mut vars = BTreeSet::new();
+        for value in &self.values {
+            let mut row = parity(value);
+            row.remove(&Monomial::new());
+            vars.extend(row.iter().flatten().copied());
+
+            while let Some(pivot) = row.last().cloned() {
+                match rows.get(&pivot) {
+                    Some(other) => row = row.symmetric_difference(other).cloned().collect(),
+                    None => {
+                        rows.insert(pivot, row);
+                        break;
+                    }
+                }
+            }
+        }
+        (rows.len().min(vars.len()) as Real).exp2()
+    }
+
+    pub fn apply<B: BasisIdx>(&mut self, gate: &Gate<B>) {
+        // only the variables of the values that the gate changes can stop
+        // setting any value
+        let vars = gate
+            .touches
+            .iter()
+            .flat_map(|qi| self.values[*qi].keys().flatten().copied())
+            .collect();
+
+        match gate.defn {
+            GateDefn::Project {
+                target, outcome, ..
+            } => self.project(target, outcome),
+            ref defn if defn.is_dynamic() => self.forget(gate),
+            _ => {
+                let mat = gate.unitary().mat;
+                let is_applied = match (permutation(&mat), gate.touches.as_slice()) {
+                    (Some(perm), _) => self.apply_permutation(&gate.touches, &perm),
+                    (None, [qi]) => match uniform_angles(&mat) {
+                        Some(angles) if self.values[*qi].len() <= MAX_TERMS => {
+                            self.apply_branching(*qi, angles);
+                            true
+                        }
+                        _ => false,
+                    },
+                    _ => false,
+                };
+                if !is_applied {
+                    self.forget(gate);
+                }
+            }
+        }
+        self.simplify(vars);
+    }
+
+    /// Applies a gate that maps each basis index `a` of the qubits `touches`
+    /// to `perm[a].0` times the phase `perm[a].1`. Returns whether it could.
+    fn apply_permutation(&mut self, touches: &[QubitIndex], perm: &[(usize, Real)]) -> bool {
+        // the products of the values of each subset of the qubits, so that
+        // the new values and phase are sums of them
+        let mut products = vec![Poly::from([(Monomial::new(), 1)])];
+        for qi in touches {
+            let value = &self.values[*qi];
+            let more = products
+                .iter()
+                .map(|product| multiply(product, value))
+                .collect::<Vec<_>>();
+            if more.iter().any(|product| product.len() > MAX_TERMS) {
+                return false;
+            }
+            products.extend(more);
+        }
+
+        for (pos, qi) in touches.iter().enumerate() {
+            let bits = perm
+                .iter()
+                .map(|(row, _)| ((row >> pos) & 1) as Real)
+                .collect::<Vec<_>>();
+            let mut value = Poly::new();
+            for (coeff, product) in mobius(&bits).iter().zip(&products) {
+                add(&mut value, product, *coeff as i64);
+            }
+            self.values[*qi] = value;
+        }
+
+        let angles = perm.iter().map(|(_, angle)| *angle).collect::<Vec<_>>();
+        for (coeff, product) in mobius(&angles).iter().zip(&products) {
+            self.add_phase(*coeff, product);
+        }
+        true
+    }
+
+    /// Applies a single-qubit gate that takes `a` to each `b` with the phase
+    /// `angles[b][a]`, by a new variable for `b`
+    fn apply_branching(&mut self, qi: QubitIndex, angles: [[Real; 2]; 2]) {
+        let old = self.values[qi].clone();
+        let new = Poly::from([(vec![self.new_var()], 1)]);
+
+        self.add_phase(angles[0][1] - angles[0][0], &old);
+        self.add_phase(angles[1][0] - angles[0][0], &new);
+        self.add_phase(
+            angles[1][1] - angles[1][0] - angles[0][1] + angles[0][0],
+            &multiply(&old, &new),
+        );
+        self.values[qi] = new;
+    }
+
+    /// Keeps the paths in which `qi` is `outcome`
+    fn project(&mut self, qi: QubitIndex, outcome: bool) {
+        let mut constraint = parity(&self.values[qi]);
+        if outcome {
+            toggle(&mut constraint, Monomial::new());
+        }
+        if !self.solve(&constraint) {
+            self.forget_value(qi);
+            self.values[qi] = lift(&BoolPoly::from_iter(outcome.then(Monomial::new)));
+        }
+    }
+
+    /// Gives up on the gate: the values of the qubits it does not leave in the
+    /// Z basis become new opaque variables
+    fn forget<B: BasisIdx>(&mut self, gate: &Gate<B>) {
+        for qi in &gate.touches {
+            self.forget_value(*qi);
+            if gate.defn.qubit_basis(*qi) != QubitBasis::Z {
+                let var = self.new_var();
+                self.opaque.insert(var);
+                self.values[*qi] = Poly::from([(vec![var], 1)]);
+            }
+        }
+    }
+
+    fn forget_value(&mut self, qi: QubitIndex) {
+        let vars = self.values[qi]
+            .keys()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        self.opaque.extend(vars);
+    }
+
+    fn new_var(&mut self) -> Var {
+        self.num_vars += 1;
+        self.num_vars - 1
+    }
+
+    fn add_phase(&mut self, coeff: Real, poly: &Poly) {
+        for (monomial, times) in poly {
+            if monomial.is_empty() {
+                // a global phase
+                continue;
+            }
+            let angle = self.phase.remove(monomial).unwrap_or(0.0) + coeff * *times as Real;
+            if !is_angle(angle, 0.0) {
+                self.phase.insert(monomial.clone(), angle.rem_euclid(TAU));
+            }
+        }
+    }
+
+    /// Sums out those of `vars` that no value depends on, as long as any can
+    /// be
+    fn simplify(&mut self, mut vars: BTreeSet<Var>) {
+        while let Some(var) = vars.pop_first() {
+            let is_used = self
+                .values
+                .iter()
+                .any(|value| value.keys().any(|monomial| monomial.contains(&var)));
+            if !is_used && self.sum_out(var) {
+                // which may leave any other variable unused
+                vars.extend(self.phase.keys().flatten().chain(&self.opaque));
+            }
+        }
+    }
+
+    /// Sums over `var`, which no value depends on. Returns whether that
+    /// changed the phase.
+    fn sum_out(&mut self, var: Var) -> bool {
+        let terms = self
+            .phase
+            .iter()
+            .filter(|(monomial, _)| monomial.contains(&var))
+            .map(|(monomial, angle)| (monomial.clone(), *angle))
+            .collect::<Vec<_>>();
+        if terms.is_empty() {
+            self.opaque.remove(&var);
+            return false;
+        }
+
+        if self.opaque.remove(&var) {
+            // nothing is known of the weights, so nothing more is known of
+            // the other variables in the phase
+            for (monomial, _) in terms {
+                self.phase.remove(&monomial);
+                self.opaque.extend(without(&monomial, var));
+            }
+            return true;
+        }
+
+        // if the phase is π times `var` times the rest of each term, the sum
+        // is zero unless the rest is even
+        if !terms.iter().all(|(_, angle)| is_angle(*angle, PI)) {
+            return false;
+        }
+        let mut constraint = BoolPoly::new();
+        for (monomial, _) in &terms {
+            toggle(&mut constraint, without(monomial, var));
+        }
+
+        let phase = self.phase.clone();
+        for (monomial, _) in &terms {
+            self.phase.remove(monomial);
+        }
+        if constraint.is_empty() || self.solve(&constraint) {
+            true
+        } else {
+            self.phase = phase;
+            false
+        }
+    }
+
+    /// Keeps the paths in which `constraint` is zero by substituting one of
+    /// its variables, if it is linear in one. Returns whether it could.
+    fn solve(&mut self, constraint: &BoolPoly) -> bool {
+        let var = constraint
+            .iter()
+            .filter(|monomial| monomial.len() == 1 && !self.opaque.contains(&monomial[0]))
+            .map(|monomial| monomial[0])
+            .filter(|var| {
+                constraint
+                    .iter()
+                    .filter(|monomial| monomial.contains(var))
+                    .count()
+                    == 1
+            })
+            .max();
+        let var = match var {
+            Some(var) if constraint.len() <= MAX_CONSTRAINT_TERMS => var,
+            _ => return false,
+        };
+
+        let mut solution = constraint.clone();
+        toggle(&mut solution, vec![var]);
+        let solution = lift(&solution);
+
+        for value in self.values.iter_mut() {
+            if value.keys().any(|monomial| monomial.contains(&var)) {
+                let mut new = Poly::new();
+                for (monomial, coeff) in value.iter() {
+                    let term = Poly::from([(without(monomial, var), *coeff)]);
+                    if monomial.contains(&var) {
+                        add(&mut new, &multiply(&term, &solution), 1);
+                    } else {
+                        add(&mut new, &term, 1);
+                    }
+                }
+                *value = new;
+            }
+        }
+
+        let terms = self
+            .phase
+            .iter()
+            .filter(|(monomial, _)| monomial.contains(&var))
+            .map(|(monomial, angle)| (monomial.clone(), *angle))
+            .collect::<Vec<_>>();
+        for (monomial, angle) in terms {
+            self.phase.remove(&monomial);
+            let rest = Poly::from([(without(&monomial, var), 1)]);
+            self.add_phase(angle, &multiply(&rest, &solution));
+        }
+        true
+    }
+}
+
+/// The row and phase of the nonzero entry of each column of `mat`, if it has
+/// exactly one in each column
+fn permutation(mat: &DMatrix<Complex>) -> Option<Vec<(usize, Real)>> {
+    (0..mat.ncols())
+        .map(|col| {
+            let mut rows = (0..mat.nrows()).filter(|row| utility::is_nonzero(mat[(*row, col)]));
+            match (rows.next(), rows.next()) {
+                (Some(row), None) => Some((row, mat[(row, col)].arg())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The phases of the entries of a 2x2 `mat`, if they all have the same
+/// magnitude
+fn uniform_angles(mat: &DMatrix<Complex>) -> Option<[[Real; 2]; 2]> {
+    let norm = mat[(0, 0)].norm();
+    if mat
+        .iter()
+        .all(|entry| (entry.norm() - norm).abs() < constants::ZERO_THRESHOLD)
+    {
+        Some([
+            [mat[(0, 0)].arg(), mat[(0, 1)].arg()],
+            [mat[(1, 0)].arg(), mat[(1, 1)].arg()],
+        ])
+    } else {
+        None
+    }
+}
+
+/// The coefficients of the polynomial that is `values[a]` at the bits of each
+/// `a`, one for the product of each subset of the bits
+fn mobius(values: &[Real]) -> Vec<Real> {
+    let mut coeffs = values.to_vec();
+    let mut bit = 1;
+    while bit < coeffs.len() {
+        for subset in 0..coeffs.len() {
+            if subset & bit != 0 {
+                coeffs[subset] -= coeffs[subset ^ bit];
+            }
+        }
+        bit <<= 1;
+    }
+    coeffs
+}
+
+fn is_angle(angle: Real, expected: Real) -> bool {
+    let diff = (angle - expected).rem_euclid(TAU);
+    diff < constants::ZERO_THRESHOLD || TAU - diff < constants::ZERO_THRESHOLD
+}
+
+fn without(monomial: &Monomial, var: Var) -> Monomial {
+    monomial.iter().copied().filter(|v| *v != var).collect()
+}
+
+fn toggle(poly: &mut BoolPoly, monomial: Monomial) {
+    if !poly.remove(&monomial) {
+        poly.insert(monomial);
+    }
+}
+
+fn add(poly: &mut Poly, other: &Poly, times: i64) {
+    for (monomial, coeff) in other {
+        match poly.get_mut(monomial) {
+            Some(sum) => {
+                *sum += coeff * times;
+                if *sum == 0 {
+                    poly.remove(monomial);
+                }
+            }
+            None if coeff * times != 0 => {
+                poly.insert(monomial.clone(), coeff * times);
+            }
+            None => {}
+        }
+    }
+}
+
+fn multiply(a: &Poly, b: &Poly) -> Poly {
+    let mut product = Poly::new();
+    for (x, i) in a {
+        for (y, j) in b {
+            let mut monomial = x.iter().chain(y).copied().collect::<Monomial>();
+            monomial.sort_unstable();
+            monomial.dedup();
+            let sum = product.entry(monomial).or_insert(0);
+            *sum += i * j;
+        }
+    }
+    product.retain(|_, coeff| *coeff != 0);
+    product
+}
+
+/// The boolean function `value` as the exclusive or of monomials
+fn parity(value: &Poly) -> BoolPoly {
+    value
+        .iter()
+        .filter(|(_, coeff)| *coeff % 2 != 0)
+        .map(|(monomial, _)| monomial.clone())
+        .collect()
+}
+
+/// The exclusive or of monomials `value` as a sum: x ⊕ y = x + y - 2xy
+fn lift(value: &BoolPoly) -> Poly {
+    value.iter().fold(Poly::new(), |acc, monomial| {
+        let term = Poly::from([(monomial.clone(), 1)]);
+        let mut sum = multiply(&term, &acc);
+        for coeff in sum.values_mut() {
+            *coeff *= -2;
+        }
+        add(&mut sum, &acc, 1);
+        add(&mut sum, &term, 1);
+        sum
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::BasisIdx64;
+
+    fn apply_all(model: &mut SupportModel, defns: Vec<GateDefn>) {
+        for defn in defns {
+            model.apply(&Gate::<BasisIdx64>::new(defn));
+        }
+    }
+
+    #[test]
+    fn test_entangled() {
+        let mut model = SupportModel::new(3);
+        apply_all(
+            &mut model,
+            vec![
+                GateDefn::Hadamard(0),
+                GateDefn::CX {
+                    control: 0,
+                    target: 1,
+                },
+                GateDefn::CX {
+                    control: 1,
+                    target: 2,
+                },
+            ],
+        );
+        assert_eq!(model.num_nonzeros(), 2.0);
+
+        apply_all(&mut model, vec![GateDefn::Hadamard(2)]);
+        assert_eq!(model.num_nonzeros(), 4.0);
+    }
+
+    #[test]
+    fn test_interference() {
+        let mut model = SupportModel::new(1);
+        apply_all(
+            &mut model,
+            vec![
+                GateDefn::Hadamard(0),
+                GateDefn::PauliZ(0),
+                GateDefn::Hadamard(0),
+            ],
+        );
+        assert_eq!(model.num_nonzeros(), 1.0);
+
+        // s is not diagonal in the x basis, so the paths do not cancel out
+        apply_all(
+            &mut model,
+            vec![GateDefn::Hadamard(0), GateDefn::S(0), GateDefn::Hadamard(0)],
+        );
+        assert_eq!(model.num_nonzeros(), 2.0);
+    }
+
+    #[test]
+    fn test_toffoli_decomposition() {
+        let (a, b, c) = (0, 1, 2);
+        let mut model = SupportModel::new(3);
+        apply_all(
+            &mut model,
+            vec![GateDefn::Hadamard(a), GateDefn::Hadamard(b)],
+        );
+        assert_eq!(model.num_nonzeros(), 4.0);
+
+        apply_all(
+            &mut model,
+            vec![
+                GateDefn::Hadamard(c),
+                GateDefn::CX {
+                    control: b,
+                    target: c,
+                },
+                GateDefn::Tdg(c),
+                GateDefn::CX {
+                    control: a,
+                    target: c,
+                },
+                GateDefn::T(c),
+                GateDefn::CX {
+                    control: b,
+                    target: c,
+                },
+                GateDefn::Tdg(c),
+                GateDefn::CX {
+                    control: a,
+                    target: c,
+                },
+                GateDefn::T(b),
+                GateDefn::T(c),
+            ],
+        );
+        assert_eq!(model.num_nonzeros(), 8.0);
+
+        // the second hadamard on the target undoes the first
+        apply_all(
+            &mut model,
+            vec![
+                GateDefn::Hadamard(c),
+                GateDefn::CX {
+                    control: a,
+                    target: b,
+                },
+                GateDefn::T(a),
+                GateDefn::Tdg(b),
+                GateDefn::CX {
+                    control: a,
+                    target: b,
+                },
+            ],
+        );
+        assert_eq!(model.num_nonzeros(), 4.0);
+    }
+}
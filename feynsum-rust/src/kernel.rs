@@ -0,0 +1,153 @@
+// The dense kernels in Rust, for builds without the `futhark` feature. They
+// keep the interface of the futhark kernels, so the dense and hybrid
+// simulators run the same either way, but compute in the precision of the
+// weights rather than in single precision.
+use std::marker::PhantomData;
+
+use rayon::prelude::*;
+
+use crate::circuit::UnitaryMatrix;
+use crate::types::{Precision, QubitIndex, Weight};
+use crate::utility;
+
+/// Outputs computed together, so that the inputs they read stay in cache
+const BLOCK_SIZE: usize = 1 << 12;
+
+/// Nothing to set up, unlike the futhark context
+pub struct Context;
+
+pub fn create_context() -> Context {
+    Context
+}
+
+pub struct DenseVector<'a, P: Precision> {
+    vec: Vec<Weight<P>>,
+    context: PhantomData<&'a Context>,
+}
+
+impl<'a, P: Precision> DenseVector<'a, P> {
+    pub fn new(_ctx: &'a Context, vec: Vec<Weight<P>>) -> Self {
+        DenseVector {
+            vec,
+            context: PhantomData,
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<Weight<P>> {
+        self.vec
+    }
+}
+
+/// Applies `unitary` to `state`. Each output sums the inputs that differ from
+/// it only in the qubits of the gate, weighted by the row of the matrix that
+/// those qubits select, as in the futhark `apply_vec`. Blocks of outputs are
+/// computed in parallel.
+pub fn apply_vec<'a, P: Precision>(
+    _ctx: &'a Context,
+    state: DenseVector<P>,
+    unitary: UnitaryMatrix,
+) -> DenseVector<'a, P> {
+    let q = unitary.qubit_indices.len();
+    let dim = 1 << q;
+    assert!(unitary.mat.nrows() == dim && unitary.mat.ncols() == dim);
+
+    // offsets[k] sets the qubits of the gate to the bits of k
+    let offsets = (0..dim)
+        .map(|k| deposit(k, &unitary.qubit_indices))
+        .collect::<Vec<_>>();
+    let mask = offsets[dim - 1];
+    let mat = unitary
+        .mat
+        .row_iter()
+        .flat_map(|row| row.iter().map(|c| P::from_complex(*c)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let input = &state.vec;
+    let mut vec = vec![Weight::new(P::zero(), P::zero()); input.len()];
+    vec.par_chunks_mut(BLOCK_SIZE)
+        .enumerate()
+        .for_each(|(block, outputs)| {
+            for (j, output) in outputs.iter_mut().enumerate() {
+                let i = block * BLOCK_SIZE + j;
+                let base = i & !mask;
+                let row = extract(i, &unitary.qubit_indices);
+                *output = mat[row * dim..(row + 1) * dim]
+                    .iter()
+                    .zip(&offsets)
+                    .map(|(g, offset)| g * input[base | offset])
+                    .sum();
+            }
+        });
+
+    DenseVector {
+        vec,
+        context: PhantomData,
+    }
+}
+
+pub fn num_nonzeros<P: Precision>(_ctx: &Context, state: &DenseVector<P>) -> usize {
+    state
+        .vec
+        .par_iter()
+        .filter(|c| utility::is_nonzero(**c))
+        .count()
+}
+
+/// Moves bit `j` of `k` to bit `qubit_indices[j]`
+fn deposit(k: usize, qubit_indices: &[QubitIndex]) -> usize {
+    qubit_indices
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (j, qi)| acc | (((k >> j) & 1) << qi))
+}
+
+/// Moves bit `qubit_indices[j]` of `i` to bit `j`
+fn extract(i: usize, qubit_indices: &[QubitIndex]) -> usize {
+    qubit_indices
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (j, qi)| acc | (((i >> qi) & 1) << j))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Gate, GateDefn, Unitary};
+    use crate::types::{constants, BasisIdx64};
+
+    fn apply(vec: Vec<Weight<f64>>, defn: GateDefn) -> Vec<Weight<f64>> {
+        let ctx = create_context();
+        let unitary = Gate::<BasisIdx64>::new(defn).unitary();
+        apply_vec(&ctx, DenseVector::new(&ctx, vec), unitary).into_vec()
+    }
+
+    #[test]
+    fn test_apply_vec() {
+        let zero = Weight::new(0.0, 0.0);
+        let one = Weight::new(1.0, 0.0);
+
+        // |01> to |11>
+        let vec = apply(
+            vec![zero, one, zero, zero],
+            GateDefn::CX {
+                control: 0,
+                target: 1,
+            },
+        );
+        assert_eq!(vec, vec![zero, zero, zero, one]);
+
+        // a state larger than a block, with the gate on its highest qubit
+        let mut vec = vec![zero; 4 * BLOCK_SIZE];
+        vec[1] = one;
+        let vec = apply(vec, GateDefn::Hadamard(13));
+        for (i, weight) in vec.iter().enumerate() {
+            let expected = if i == 1 || i == 1 + 2 * BLOCK_SIZE {
+                constants::RECP_SQRT_2
+            } else {
+                0.0
+            };
+            // in double precision, unlike the futhark kernels
+            assert!((weight.re - expected).abs() < 1e-12 && weight.im == 0.0);
+        }
+    }
+}
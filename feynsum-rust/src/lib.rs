@@ -0,0 +1,84 @@
+//! Feynsum simulates quantum circuits as a sum over paths, keeping only the
+//! basis states with nonzero weight.
+//!
+//! A circuit is built from OpenQASM source or from gate definitions, and
+//! simulated by `run` with the simulator backend and options in a `Config`:
+//!
+//! ```
+//! use std::sync::atomic::AtomicU64;
+//!
+//! use feynsum_rust::types::BasisIdx64;
+//! use feynsum_rust::{Circuit, Config, GateDefn};
+//!
+//! let bell = Circuit::<BasisIdx64>::from_qasm(
+//!     r#"
+//! OPENQASM 2.0;
+//! include "qelib1.inc";
+//! qreg q[2];
+//! h q[0];
+//! cx q[0], q[1];
+//! "#,
+//! )?;
+//! let config = Config {
+//!     simulator: "sequential".to_string(),
+//!     ..Config::default()
+//! };
+//! let outcome = feynsum_rust::run::<BasisIdx64, AtomicU64, f32>(&config, bell)?;
+//! assert_eq!(outcome.densities.count(), 2);
+//!
+//! let ghz = Circuit::<BasisIdx64>::from_gates(
+//!     3,
+//!     0,
+//!     [
+//!         GateDefn::Hadamard(0),
+//!         GateDefn::CX { control: 0, target: 1 },
+//!         GateDefn::CX { control: 1, target: 2 },
+//!     ],
+//! )?;
+//! let outcome = feynsum_rust::run::<BasisIdx64, AtomicU64, f32>(&config, ghz)?;
+//! assert_eq!(outcome.densities.count(), 2);
+//! # Ok::<(), feynsum_rust::Error>(())
+//! ```
+//!
+//! Other backends implement `SimulatorBackend`, and are run by registering
+//! them in a `Registry`.
+//!
+//! The parallel simulators run on the current rayon thread pool, so callers
+//! that want to bound their parallelism run them inside `ThreadPool::install`.
+
+pub mod circuit;
+pub mod config;
+pub mod error;
+pub mod fingerprint;
+#[cfg(feature = "futhark")]
+mod futhark;
+pub mod gate_scheduler;
+#[cfg(not(feature = "futhark"))]
+mod kernel;
+pub mod parser;
+mod simulator;
+pub mod types;
+pub mod utility;
+
+pub use circuit::{Circuit, GateDefn};
+pub use config::Config;
+pub use error::{Error, ErrorKind, Result};
+pub use gate_scheduler::GateSchedulingPolicy;
+pub use simulator::{Counts, Densities, FinalState, Outcome, Registry, SimulatorBackend, Stats};
+
+use types::{AtomicBasisIdx, BasisIdx, Precision};
+
+// the dense and hybrid simulators run on the futhark kernels if they are
+// built, and on those in `kernel` otherwise
+#[cfg(feature = "futhark")]
+use futhark as kernel;
+
+/// Simulates `circuit` with the builtin backend named by `config.simulator`.
+/// Weights are stored in precision `P`, and basis indices as `B`, which must
+/// have room for all qubits of the circuit.
+pub fn run<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
+    config: &Config,
+    circuit: Circuit<B>,
+) -> Result<Outcome<B, P>> {
+    Registry::<B, AB, P>::default().run(config, circuit)
+}
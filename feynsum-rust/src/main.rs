@@ -1,39 +1,45 @@
-mod circuit;
-mod config;
-mod fingerprint;
-mod futhark;
-mod gate_scheduler;
 mod options;
-mod parser;
-mod simulator;
-mod types;
-mod utility;
 
-use parser::QasmStatement;
+use nalgebra::DMatrix;
 use rayon::ThreadPoolBuilder;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::{atomic::AtomicU64, RwLock};
 use structopt::StructOpt;
 
-use circuit::Circuit;
-use config::Config;
-use fingerprint::Fingerprint;
+use feynsum_rust::circuit::{self, Circuit, ClassicalRegister};
+use feynsum_rust::error;
+use feynsum_rust::fingerprint::Fingerprint;
+use feynsum_rust::gate_scheduler::Schedule;
+use feynsum_rust::parser::{self, LocatedStatement, QasmStatement};
+use feynsum_rust::types::{
+    AtomicBasisIdx, AtomicBasisIdxN, BasisIdx, BasisIdx64, BasisIdxN, BasisIdxUnlimited, Complex,
+    Precision, Real,
+};
+use feynsum_rust::utility;
+use feynsum_rust::{Config, Counts, Densities};
 use options::Options;
-use simulator::{Compactifiable, Simulator};
-use types::{AtomicBasisIdx, BasisIdx, BasisIdx64, BasisIdxUnlimited, Complex};
 
 #[global_allocator]
 static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
 const BASIS_IDX_64_OKAY_THRESHOLD: usize = 62;
 
-fn main() -> io::Result<()> {
+fn main() {
     env_logger::init();
 
     let options = Options::from_args();
 
+    if let Err(err) = try_main(options) {
+        eprintln!("error[{}]: {}", err.kind, err);
+        process::exit(err.exit_code());
+    }
+}
+
+fn try_main(options: Options) -> error::Result<()> {
     log::info!("input file: {}", options.input.display());
     if let Some(output) = &options.output {
         log::info!("output file: {}", output.display());
@@ -43,7 +49,9 @@ fn main() -> io::Result<()> {
     log::info!("gate scheduling policy: {}", options.gate_schduling_policy);
     log::info!("dense threshold: {}", options.dense_threshold);
     log::info!("pull threshold: {}", options.pull_threshold);
+    log::info!("sparse threshold: {}", options.sparse_threshold);
     log::info!("parallelism: {} threads", options.parallelism);
+    log::info!("precision: {} bits", options.precision);
     log::info!("block size: {}", options.block_size);
     log::info!(
         "bond dimension threshold: {}",
@@ -52,18 +60,26 @@ fn main() -> io::Result<()> {
 
     let source = fs::read_to_string(&options.input)?;
 
-    let config = Config::new(&options);
+    let mut config = options.config();
+    log::info!("seed: {}", config.seed);
+    if let Some(path) = &options.replay_schedule {
+        log::info!("replayed schedule file: {}", path.display());
+        config.replayed_schedule = Some(Schedule::load(path)?);
+    }
 
-    let program = match parser::parse_program(&source) {
-        Ok(program) => program,
-        Err(err) => {
-            panic!("Failed to parse program: {:?}", err);
-        }
-    };
+    let program = parser::parse_program(&source)?;
     log::info!("parse complete. starting circuit construction.");
 
-    if circuit::num_qubits(&program) <= BASIS_IDX_64_OKAY_THRESHOLD {
+    // use the narrowest basis index the qubits fit in
+    let num_qubits = circuit::num_qubits(&program);
+    if num_qubits <= BASIS_IDX_64_OKAY_THRESHOLD {
         build_circuit_and_run::<BasisIdx64, AtomicU64>(options, config, program)
+    } else if num_qubits <= BasisIdxN::<2>::MAX_QUBITS {
+        build_circuit_and_run::<BasisIdxN<2>, AtomicBasisIdxN<2>>(options, config, program)
+    } else if num_qubits <= BasisIdxN::<3>::MAX_QUBITS {
+        build_circuit_and_run::<BasisIdxN<3>, AtomicBasisIdxN<3>>(options, config, program)
+    } else if num_qubits <= BasisIdxN::<4>::MAX_QUBITS {
+        build_circuit_and_run::<BasisIdxN<4>, AtomicBasisIdxN<4>>(options, config, program)
     } else {
         build_circuit_and_run::<BasisIdxUnlimited, RwLock<BasisIdxUnlimited>>(
             options, config, program,
@@ -74,67 +90,221 @@ fn main() -> io::Result<()> {
 fn build_circuit_and_run<B: BasisIdx, AB: AtomicBasisIdx<B>>(
     options: Options,
     config: Config,
-    program: Vec<QasmStatement>,
-) -> io::Result<()> {
-    let circuit = match Circuit::<B>::new(program) {
-        Ok(circuit) => circuit,
-        Err(err) => {
-            panic!("Failed to construct circuit: {:?}", err);
-        }
+    program: Vec<LocatedStatement>,
+) -> error::Result<()> {
+    let unitaries = unitaries(&options, &program)?;
+    let circuit = Circuit::<B>::with_unitaries(program, unitaries)?;
+
+    let parameters = circuit.parameters();
+    if !parameters.is_empty() {
+        log::info!("circuit parameters: {:?}", parameters);
     }
-    .decompose();
 
     log::info!("circuit construction complete. starting simulation");
 
     let num_qubits = circuit.num_qubits;
+    let cregs = circuit.cregs.clone();
 
     ThreadPoolBuilder::new()
         .num_threads(options.parallelism)
         .build_global()
         .unwrap();
 
-    let result = run::<B, AB>(&options, config, circuit);
+    let parameter_sets = parameter_sets(&options)?;
+
+    for (idx, bindings) in parameter_sets.iter().enumerate() {
+        let mut output = options.output.clone();
+        if parameter_sets.len() > 1 {
+            println!(
+                "parameter set {}: {:?}",
+                idx,
+                bindings.iter().collect::<BTreeMap<_, _>>()
+            );
+            output = output.map(|path| indexed_path(&path, idx));
+        }
+
+        let mut circuit = circuit.bind(bindings)?;
+        if options.optimize {
+            let (optimized, report) = circuit.optimize();
+            log::info!("optimizer {}", report);
+            circuit = optimized;
+        }
+
+        // each simulation replays the schedule from its first pick, and
+        // records a schedule of its own
+        let record_schedule = options.record_schedule.as_ref().map(|path| {
+            if parameter_sets.len() > 1 {
+                indexed_path(path, idx)
+            } else {
+                path.clone()
+            }
+        });
+
+        let (clbits, counts, schedule) = match options.precision {
+            64 => simulate::<B, AB, f64>(&config, circuit, output, num_qubits)?,
+            _ => simulate::<B, AB, f32>(&config, circuit, output, num_qubits)?,
+        };
+        match counts {
+            Some(counts) => print_counts(&counts),
+            None => print_classical_registers(&cregs, &clbits),
+        }
 
-    process_output(result, options.output, num_qubits)?;
+        if let (Some(path), Some(schedule)) = (record_schedule, schedule) {
+            log::info!("writing schedule to file {}", path.display());
+            schedule.save(&path)?;
+        }
+    }
 
     log::info!("simulation complete");
 
     Ok(())
 }
 
-fn run<B: BasisIdx, AB: AtomicBasisIdx<B>>(
+/// Collects the parameter sets to bind the circuit to: those in the
+/// `--params` file (a single object or an array of objects), each overridden
+/// by the `--param` options
+fn parameter_sets(options: &Options) -> io::Result<Vec<HashMap<String, Real>>> {
+    let mut sets = match &options.params_file {
+        Some(path) => {
+            log::info!("parameter file: {}", path.display());
+            let value: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+            match value {
+                serde_json::Value::Array(values) => values
+                    .into_iter()
+                    .map(parameter_set)
+                    .collect::<io::Result<Vec<_>>>()?,
+                value => vec![parameter_set(value)?],
+            }
+        }
+        None => vec![HashMap::new()],
+    };
+
+    for set in sets.iter_mut() {
+        set.extend(options.params.iter().cloned());
+    }
+
+    Ok(sets)
+}
+
+fn parameter_set(value: serde_json::Value) -> io::Result<HashMap<String, Real>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    match value {
+        serde_json::Value::Object(map) => map
+            .into_iter()
+            .map(|(name, value)| match value.as_f64() {
+                Some(value) => Ok((name, value as Real)),
+                None => Err(invalid(format!(
+                    "parameter {} must be a number, found {}",
+                    name, value
+                ))),
+            })
+            .collect(),
+        value => Err(invalid(format!(
+            "expected an object of parameter values, found {}",
+            value
+        ))),
+    }
+}
+
+/// Loads the gate matrices in the `--unitaries` file and in those named by
+/// `pragma unitaries`, whose paths are relative to the input file
+fn unitaries(
     options: &Options,
-    config: Config,
+    program: &[LocatedStatement],
+) -> io::Result<HashMap<String, DMatrix<Complex>>> {
+    let input_dir = options.input.parent().unwrap_or(Path::new(""));
+    let paths = options
+        .unitaries_file
+        .iter()
+        .cloned()
+        .chain(
+            program
+                .iter()
+                .filter_map(|located| match &located.statement {
+                    QasmStatement::Unitaries { path } => Some(input_dir.join(path)),
+                    _ => None,
+                }),
+        );
+
+    let mut unitaries = HashMap::new();
+    for path in paths {
+        log::info!("unitaries file: {}", path.display());
+        unitaries.extend(circuit::load_unitaries(&path)?);
+    }
+    Ok(unitaries)
+}
+
+/// `out.txt` becomes `out.<idx>.txt`
+fn indexed_path(path: &Path, idx: usize) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!(".{}", idx));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+/// Simulates `circuit` with weights in precision `P`, writes the densities to
+/// `output` and returns the classical bits, the counts of the shots and the
+/// recorded schedule
+fn simulate<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
+    config: &Config,
     circuit: Circuit<B>,
-) -> Box<dyn Iterator<Item = (B, Complex)>> {
-    match options.simulator {
-        Simulator::Sequential => {
-            log::info!("using sequential simulator");
-            simulator::sequential_simulator::run::<B>(&config, circuit).compactify()
-        }
-        Simulator::Parallel => {
-            log::info!("using parallel simulator");
-            simulator::parallel_simulator::run::<B, AB>(&config, circuit).compactify()
-        }
-        Simulator::Dense => {
-            log::info!("using dense simulator");
-            simulator::dense_simulator::run(&config, circuit).compactify()
-        }
-        Simulator::Hybrid => {
-            log::info!("using hybrid simulator");
-            simulator::hybrid_simulator::run::<B, AB>(&config, circuit)
-        }
-        Simulator::MPS => {
-            log::info!("using MPS simulator");
-            simulator::mps_simulator::run::<B>(&config, circuit).compactify()
-        }
+    output: Option<PathBuf>,
+    bidx_width: usize,
+) -> error::Result<(Vec<bool>, Option<Counts>, Option<Schedule>)> {
+    let outcome = feynsum_rust::run::<B, AB, P>(config, circuit)?;
+    let stats = &outcome.stats;
+    log::info!(
+        "{} simulator: {} gates on {} qubits in {:.4}s",
+        stats.simulator,
+        stats.num_gates,
+        stats.num_qubits,
+        stats.duration.as_secs_f64()
+    );
+    // the counts take the place of the fingerprint, so the densities of a
+    // sampled state are only listed to be written out
+    if outcome.counts.is_none() || output.is_some() {
+        process_output(
+            outcome.densities,
+            output,
+            bidx_width,
+            outcome.counts.is_none(),
+        )?;
+    }
+    Ok((outcome.clbits, outcome.counts, outcome.schedule))
+}
+
+fn print_classical_registers(cregs: &[ClassicalRegister], clbits: &[bool]) {
+    if cregs.is_empty() {
+        return;
+    }
+
+    println!("classical registers:");
+    for creg in cregs {
+        // most significant bit first, as in OpenQASM
+        let bits = clbits[creg.start..creg.start + creg.size]
+            .iter()
+            .rev()
+            .map(|&bit| if bit { '1' } else { '0' })
+            .collect::<String>();
+        println!("{} {}", creg.name, bits);
     }
 }
 
-fn process_output<B: BasisIdx>(
-    densities: Box<dyn Iterator<Item = (B, Complex)>>,
+/// Prints the counts as a JSON object, as Qiskit does
+fn print_counts(counts: &Counts) {
+    println!("counts:");
+    println!("{}", serde_json::Value::from_iter(counts.clone()));
+}
+
+fn process_output<B: BasisIdx, P: Precision>(
+    densities: Densities<B, P>,
     output: Option<PathBuf>,
     bidx_width: usize,
+    print_fingerprint: bool,
 ) -> io::Result<()> {
     if let Some(path) = output.as_ref() {
         log::info!("writing output to file {}", path.display());
@@ -156,6 +326,10 @@ fn process_output<B: BasisIdx>(
         }
     }
 
+    if !print_fingerprint {
+        return Ok(());
+    }
+
     println!("computed fingerprint:");
     fingerprint
         .iter()
@@ -1,9 +1,9 @@
 use std::path::PathBuf;
 use structopt::StructOpt;
 
-use crate::gate_scheduler::GateSchedulingPolicy;
-use crate::simulator::Simulator;
-use crate::types::Real;
+use feynsum_rust::gate_scheduler::GateSchedulingPolicy;
+use feynsum_rust::types::Real;
+use feynsum_rust::Config;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "feynsum", about = "Feynsum quantum simulator")]
@@ -30,30 +30,127 @@ pub struct Options {
         name = "gate scheduling policy",
         long = "scheduler",
         short = "s",
-        default_value = "naive",
+        default_value = "greedy-nonbranching",
         help = "gate scheduling policy to use"
     )]
     pub gate_schduling_policy: GateSchedulingPolicy,
 
+    #[structopt(
+        long = "lookahead",
+        default_value = "5",
+        parse(try_from_str = parse_positive),
+        help = "number of branching gates the dag-a-star scheduler plans ahead"
+    )]
+    pub lookahead: usize,
+
+    #[structopt(
+        parse(from_os_str),
+        long = "record-schedule",
+        help = "path to write the picks of the gate scheduler to, as a JSON array of gate index arrays"
+    )]
+    pub record_schedule: Option<PathBuf>,
+
+    #[structopt(
+        parse(from_os_str),
+        long = "replay-schedule",
+        help = "path to a schedule written by --record-schedule, whose picks are replayed instead of running the gate scheduling policy"
+    )]
+    pub replay_schedule: Option<PathBuf>,
+
     #[structopt(long = "dense-threshold", default_value = "0.25")]
     pub dense_threshold: Real,
 
     #[structopt(long = "pull-threshold", default_value = "0.8")]
     pub pull_threshold: Real,
 
+    #[structopt(
+        long = "sparse-threshold",
+        default_value = "0.05",
+        help = "the hybrid simulator returns a dense state to a sparse table once its density falls below this, or below the dense threshold if that is lower"
+    )]
+    pub sparse_threshold: Real,
+
     #[structopt(long = "bond-dimension-threshold", default_value = "100")]
     pub bond_dimension_threshold: usize,
 
-    #[structopt(long = "disable-gate-fusion")]
+    #[structopt(
+        long = "seed",
+        help = "seed for measurement outcomes and shots. if not specified, a random seed is used"
+    )]
+    pub seed: Option<u64>,
+
+    #[structopt(
+        long = "shots",
+        default_value = "0",
+        help = "number of bitstrings to sample from the final state, which are printed as counts of the classical registers the circuit measures into at its end, or of all qubits if it has none. measurement, reset and classical control are only supported at the end of the circuit"
+    )]
+    pub shots: usize,
+
+    #[structopt(
+        long = "param",
+        parse(try_from_str = parse_param),
+        number_of_values = 1,
+        help = "binds a circuit parameter, e.g. --param theta=0.3. can be repeated"
+    )]
+    pub params: Vec<(String, Real)>,
+
+    #[structopt(
+        parse(from_os_str),
+        long = "params",
+        help = "path to a JSON file with an object of parameter values, or an array of such objects to simulate one after another"
+    )]
+    pub params_file: Option<PathBuf>,
+
+    #[structopt(
+        parse(from_os_str),
+        long = "unitaries",
+        help = "path to a JSON file with an object of gate matrices, which the circuit can call by name"
+    )]
+    pub unitaries_file: Option<PathBuf>,
+
+    #[structopt(
+        long = "optimize",
+        help = "cancel, merge and fold redundant gates before simulation. the final state is unchanged up to a global phase"
+    )]
+    pub optimize: bool,
+
+    #[structopt(
+        long = "scheduler-max-branching-stride",
+        default_value = "2",
+        parse(try_from_str = parse_positive),
+        help = "number of branching gates the greedy-nonbranching and greedy-branching schedulers pick at once, and that the adaptive scheduler starts from"
+    )]
+    pub max_branching_stride: usize,
+
+    #[structopt(
+        long = "disable-gate-fusion",
+        alias = "scheduler-disable-fusion",
+        help = "apply one gate at a time, rather than the runs of gates that the scheduler picks"
+    )]
     pub disable_gate_fusion: bool,
 
+    #[structopt(
+        long = "fusion-max-qubits",
+        default_value = "4",
+        help = "the dense and hybrid simulators multiply runs of gates on up to this many qubits into one unitary"
+    )]
+    pub fusion_max_qubits: usize,
+
     #[structopt(
         name = "simulator",
         long = "simulator",
         default_value = "parallel",
-        help = "simulator to use"
+        help = "simulator to use: sequential, parallel, dense, hybrid, mps, or auto to pick one and its thresholds from an analysis of the circuit"
     )]
-    pub simulator: Simulator,
+    pub simulator: String,
+
+    #[structopt(
+        long = "precision",
+        default_value = "32",
+        possible_values = &["32", "64"],
+        help = "bits of the floating point numbers weights are stored and computed in. with the futhark feature, the dense kernels of the dense and hybrid simulators are 32 bits regardless"
+    )]
+    pub precision: usize,
 
     #[structopt(
         name = "parallelism",
@@ -72,3 +169,75 @@ pub struct Options {
     )]
     pub block_size: usize,
 }
+
+impl Options {
+    pub fn config(&self) -> Config {
+        Config {
+            simulator: self.simulator.clone(),
+            block_size: self.block_size,
+            maxload: 0.75, // FIXME
+            gate_scheduling_policy: self.gate_schduling_policy,
+            lookahead: self.lookahead,
+            max_branching_stride: self.max_branching_stride,
+            disable_gate_fusion: self.disable_gate_fusion,
+            fusion_max_qubits: self.fusion_max_qubits,
+            dense_threshold: self.dense_threshold,
+            pull_threshold: self.pull_threshold,
+            sparse_threshold: self.sparse_threshold,
+            bond_dimension_threshold: self.bond_dimension_threshold,
+            seed: self.seed.unwrap_or_else(rand::random),
+            shots: self.shots,
+            record_schedule: self.record_schedule.is_some(),
+            replayed_schedule: None,
+        }
+    }
+}
+
+fn parse_param(s: &str) -> Result<(String, Real), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected name=value, found {}", s))?;
+    let value = value
+        .trim()
+        .parse::<Real>()
+        .map_err(|err| format!("invalid value for parameter {}: {}", name, err))?;
+    Ok((name.trim().to_string(), value))
+}
+
+fn parse_positive(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("expected a positive number, found 0".to_string()),
+        Ok(n) => Ok(n),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_match_config() {
+        let config = Options::from_iter(["feynsum-rust", "--input", "circuit.qasm"]).config();
+        let default = Config::default();
+
+        assert_eq!(config.simulator, default.simulator);
+        assert_eq!(config.block_size, default.block_size);
+        assert_eq!(
+            config.gate_scheduling_policy.to_string(),
+            default.gate_scheduling_policy.to_string()
+        );
+        assert_eq!(config.lookahead, default.lookahead);
+        assert_eq!(config.max_branching_stride, default.max_branching_stride);
+        assert_eq!(config.disable_gate_fusion, default.disable_gate_fusion);
+        assert_eq!(config.fusion_max_qubits, default.fusion_max_qubits);
+        assert_eq!(config.dense_threshold, default.dense_threshold);
+        assert_eq!(config.pull_threshold, default.pull_threshold);
+        assert_eq!(config.sparse_threshold, default.sparse_threshold);
+        assert_eq!(
+            config.bond_dimension_threshold,
+            default.bond_dimension_threshold
+        );
+        assert_eq!(config.shots, default.shots);
+    }
+}
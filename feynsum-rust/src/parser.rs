@@ -1,40 +1,214 @@
-pub use qasmsim::grammar::ast::{Argument, Expression, OpCode};
+mod qasm3;
+
+pub use qasmsim::grammar::ast::{Argument, Expression, FuncCode, OpCode};
 use qasmsim::{
     self,
-    grammar::ast::{QuantumOperation, Statement as OpenQasmStatement, UnitaryOperation},
+    grammar::ast::{
+        GateOperation, QuantumOperation, Statement as OpenQasmStatement, UnitaryOperation,
+    },
     QasmSimError,
 };
 
+use crate::error::{self, Error, ErrorKind, Location};
+
+pub use qasm3::Qasm3Error;
+
+/// A top-level statement and where it starts in the source
+#[derive(Debug)]
+pub struct LocatedStatement {
+    pub location: Location,
+    pub statement: QasmStatement,
+}
+
 #[derive(Debug)]
 pub enum QasmStatement {
     QReg {
         name: String,
         size: usize,
     },
+    CReg {
+        name: String,
+        size: usize,
+    },
     GateCall {
         name: String,
         params: Vec<Expression>,
         args: Vec<Argument>,
     },
+    /// A user-defined gate. `body` only contains `GateCall`s whose
+    /// arguments refer to the formal `args` by `Argument::Id`.
+    GateDecl {
+        name: String,
+        params: Vec<String>,
+        args: Vec<String>,
+        body: Vec<QasmStatement>,
+    },
+    /// Measures `qubit`, storing the outcome in `bit` if given
+    Measure {
+        qubit: Argument,
+        bit: Option<Argument>,
+    },
+    Reset {
+        qubit: Argument,
+    },
+    /// `if (bits == value) statement;`
+    Conditional {
+        bits: Argument,
+        value: u64,
+        statement: Box<QasmStatement>,
+    },
+    /// `pragma unitaries "path";`, naming a file of gate matrices (see
+    /// `circuit::load_unitaries`) relative to the program
+    Unitaries {
+        path: String,
+    },
+}
+
+impl From<Qasm3Error> for Error {
+    fn from(err: Qasm3Error) -> Self {
+        Error::new(ErrorKind::Parse, err.message).at(Some(Location {
+            line: err.line,
+            column: err.column,
+        }))
+    }
 }
 
-pub fn parse_program(source: &str) -> Result<Vec<QasmStatement>, QasmSimError> {
+impl From<QasmSimError<'_>> for Error {
+    fn from(err: QasmSimError<'_>) -> Self {
+        Error::new(ErrorKind::Parse, format!("{:?}", err))
+    }
+}
+
+/// Parses an OpenQASM program. The frontend is selected by the `OPENQASM`
+/// version header; programs without a header are treated as OpenQASM 2.0.
+pub fn parse_program(source: &str) -> error::Result<Vec<LocatedStatement>> {
+    match version_header(source) {
+        Some(version) if version.starts_with('3') => {
+            log::info!("parsing as OpenQASM {}", version);
+            Ok(qasm3::parse_program(source)?)
+        }
+        _ => parse_qasm2_program(source),
+    }
+}
+
+fn parse_qasm2_program(source: &str) -> error::Result<Vec<LocatedStatement>> {
     let open_qasm_program = qasmsim::parse_and_link(source)?;
 
-    Ok(open_qasm_program
+    open_qasm_program
         .program
         .into_iter()
-        .filter_map(|span| match *span.node {
-            OpenQasmStatement::QRegDecl(name, size) => Some(QasmStatement::QReg { name, size }),
-            OpenQasmStatement::QuantumOperation(QuantumOperation::Unitary(UnitaryOperation(
+        .filter_map(|span| {
+            let location = Location::from_offset(source, span.boundaries.0 .0);
+            match qasm2_statement(*span.node) {
+                Ok(statement) => statement.map(|statement| {
+                    Ok(LocatedStatement {
+                        location,
+                        statement,
+                    })
+                }),
+                Err(err) => Some(Err(err.at(Some(location)))),
+            }
+        })
+        .collect()
+}
+
+fn qasm2_statement(statement: OpenQasmStatement) -> error::Result<Option<QasmStatement>> {
+    Ok(match statement {
+        OpenQasmStatement::QRegDecl(name, size) => Some(QasmStatement::QReg { name, size }),
+        OpenQasmStatement::CRegDecl(name, size) => Some(QasmStatement::CReg { name, size }),
+        OpenQasmStatement::QuantumOperation(operation) => Some(quantum_operation(operation)?),
+        OpenQasmStatement::Conditional(bits, value, operation) => {
+            Some(QasmStatement::Conditional {
+                bits,
+                value,
+                statement: Box::new(quantum_operation(operation)?),
+            })
+        }
+        OpenQasmStatement::GateDecl {
+            signature: (name, params, args, operations),
+            ..
+        } => {
+            let body = operations
+                .into_iter()
+                .filter_map(|operation| match operation {
+                    GateOperation::Unitary(UnitaryOperation(name, params, args)) => {
+                        Some(Ok(QasmStatement::GateCall { name, params, args }))
+                    }
+                    GateOperation::Barrier(_) => None,
+                    operation => {
+                        log::error!("unsupported operation in gate body: {:?}", operation);
+                        Some(Err(Error::new(
+                            ErrorKind::Parse,
+                            format!("unsupported operation in gate body: {:?}", operation),
+                        )))
+                    }
+                })
+                .collect::<error::Result<Vec<_>>>()?;
+            Some(QasmStatement::GateDecl {
                 name,
                 params,
                 args,
-            ))) => Some(QasmStatement::GateCall { name, params, args }),
-            _ => {
-                log::debug!("Ignored unsupported statement: {:?}", *span.node);
-                None
-            }
-        })
-        .collect())
+                body,
+            })
+        }
+        statement => {
+            log::debug!("Ignored unsupported statement: {:?}", statement);
+            None
+        }
+    })
+}
+
+fn quantum_operation(operation: QuantumOperation) -> error::Result<QasmStatement> {
+    match operation {
+        QuantumOperation::Unitary(UnitaryOperation(name, params, args)) => {
+            Ok(QasmStatement::GateCall { name, params, args })
+        }
+        QuantumOperation::Measure(qubit, bit) => Ok(QasmStatement::Measure {
+            qubit,
+            bit: Some(bit),
+        }),
+        QuantumOperation::Reset(qubit) => Ok(QasmStatement::Reset { qubit }),
+        operation => {
+            log::error!("unsupported quantum operation: {:?}", operation);
+            Err(Error::new(
+                ErrorKind::Parse,
+                format!("unsupported quantum operation: {:?}", operation),
+            ))
+        }
+    }
+}
+
+/// Returns the version string of the leading `OPENQASM <version>;` header,
+/// skipping any whitespace and comments before it.
+fn version_header(source: &str) -> Option<&str> {
+    let mut rest = source;
+    loop {
+        rest = rest.trim_start();
+        if let Some(comment) = rest.strip_prefix("//") {
+            rest = comment.split_once('\n').map_or("", |(_, after)| after);
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.split_once("*/").map_or("", |(_, after)| after);
+        } else {
+            break;
+        }
+    }
+
+    let header = rest.strip_prefix("OPENQASM")?;
+    let (version, _) = header.split_once(';')?;
+    Some(version.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_header() {
+        assert_eq!(version_header("OPENQASM 2.0;\nqreg q[1];"), Some("2.0"));
+        assert_eq!(
+            version_header("// generated\n/* by\n tooling */\n  OPENQASM 3;"),
+            Some("3")
+        );
+        assert_eq!(version_header("qreg q[1];"), None);
+    }
 }
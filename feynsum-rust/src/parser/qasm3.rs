@@ -0,0 +1,1364 @@
+//! Frontend for the subset of OpenQASM 3 emitted by our tooling.
+//!
+//! QASM 3 specific constructs (gate modifiers, `gphase`, `const` declarations
+//! and the `qubit[n]` syntax) are lowered here onto the same `QasmStatement`s
+//! produced by the qasmsim-based OpenQASM 2.0 frontend, so `Circuit::new` does
+//! not need to know which version a program was written in.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+
+use super::{Argument, Expression, FuncCode, LocatedStatement, OpCode, QasmStatement};
+use crate::error::Location;
+
+#[derive(Debug)]
+pub struct Qasm3Error {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for Qasm3Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+pub fn parse_program(source: &str) -> Result<Vec<LocatedStatement>, Qasm3Error> {
+    let tokens = tokenize(source).map_err(|(offset, message)| error_at(source, offset, message))?;
+
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        consts: HashMap::new(),
+        scalars: HashSet::new(),
+        first_qubit: None,
+        statements: Vec::new(),
+    };
+
+    let mut statements = vec![];
+    while !parser.at_end() {
+        let offset = parser.offset(source);
+        parser
+            .statement()
+            .map_err(|message| error_at(source, offset, message))?;
+
+        // a statement may be lowered to several
+        let location = Location::from_offset(source, offset);
+        statements.extend(
+            parser
+                .statements
+                .drain(..)
+                .map(|statement| LocatedStatement {
+                    location,
+                    statement,
+                }),
+        );
+    }
+
+    Ok(statements)
+}
+
+fn error_at(source: &str, offset: usize, message: String) -> Qasm3Error {
+    let Location { line, column } = Location::from_offset(source, offset);
+    Qasm3Error {
+        line,
+        column,
+        message,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(u64),
+    Real(f64),
+    Str(String),
+    Symbol(&'static str),
+}
+
+// longer symbols must come first so that e.g. `**` is not lexed as two `*`
+const SYMBOLS: [&str; 25] = [
+    "**", "->", "==", "<<", ">>", "@", "(", ")", "[", "]", "{", "}", ";", ",", ":", "=", "+", "-",
+    "*", "/", "%", "&", "|", "^", "~",
+];
+
+// binary operators on integers, from the loosest binding to the tightest,
+// above `+` and `-`
+const INTEGER_OPERATORS: [&[&str]; 4] = [&["|"], &["^"], &["&"], &["<<", ">>"]];
+
+// `pow(k) @` with integer `k` repeats the gates `|k|` times, up to this many
+const MAX_POW_REPEATS: usize = 1024;
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+
+    loop {
+        let trimmed = rest.trim_start();
+        let offset = source.len() - trimmed.len();
+        rest = trimmed;
+
+        if rest.is_empty() {
+            return Ok(tokens);
+        } else if let Some(comment) = rest.strip_prefix("//") {
+            rest = comment.split_once('\n').map_or("", |(_, after)| after);
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            match comment.split_once("*/") {
+                Some((_, after)) => rest = after,
+                None => return Err((offset, "unterminated block comment".to_string())),
+            }
+        } else if let Some(string) = rest.strip_prefix('"') {
+            match string.split_once('"') {
+                Some((contents, after)) => {
+                    tokens.push((Token::Str(contents.to_string()), offset));
+                    rest = after;
+                }
+                None => return Err((offset, "unterminated string literal".to_string())),
+            }
+        } else {
+            let c = rest.chars().next().unwrap();
+            if c.is_alphabetic() || c == '_' {
+                let len = rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                tokens.push((Token::Ident(rest[..len].to_string()), offset));
+                rest = &rest[len..];
+            } else if c.is_ascii_digit()
+                || (c == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+            {
+                let (token, len) = number(rest).ok_or((offset, "malformed number".to_string()))?;
+                tokens.push((token, offset));
+                rest = &rest[len..];
+            } else {
+                match SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+                    Some(symbol) => {
+                        tokens.push((Token::Symbol(symbol), offset));
+                        rest = &rest[symbol.len()..];
+                    }
+                    None => return Err((offset, format!("unexpected character '{}'", c))),
+                }
+            }
+        }
+    }
+}
+
+fn number(s: &str) -> Option<(Token, usize)> {
+    let bytes = s.as_bytes();
+    let mut len = 0;
+    let mut is_real = false;
+
+    while len < bytes.len() {
+        match bytes[len] {
+            b'0'..=b'9' | b'_' => len += 1,
+            b'.' if !is_real => {
+                is_real = true;
+                len += 1;
+            }
+            b'e' | b'E' => {
+                is_real = true;
+                len += 1;
+                if len < bytes.len() && (bytes[len] == b'+' || bytes[len] == b'-') {
+                    len += 1;
+                }
+            }
+            _ => break,
+        }
+    }
+
+    let text = s[..len].replace('_', "");
+    let token = if is_real {
+        Token::Real(text.parse().ok()?)
+    } else {
+        Token::Int(text.parse().ok()?)
+    };
+    Some((token, len))
+}
+
+#[derive(Debug)]
+enum Modifier {
+    Ctrl(usize),
+    NegCtrl(usize),
+    Inv,
+    Pow(Expression),
+}
+
+impl Modifier {
+    fn num_controls(&self) -> usize {
+        match self {
+            Modifier::Ctrl(n) | Modifier::NegCtrl(n) => *n,
+            Modifier::Inv | Modifier::Pow(_) => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Call {
+    name: String,
+    params: Vec<Expression>,
+    args: Vec<Argument>,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    consts: HashMap<String, Expression>,
+    // registers declared as `qubit q;` or `bit c;`, which are referred to
+    // without an index
+    scalars: HashSet<String>,
+    // used as the target when lowering a bare `gphase`
+    first_qubit: Option<Argument>,
+    statements: Vec<QasmStatement>,
+}
+
+type ParseResult<T> = Result<T, String>;
+
+impl Parser {
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn offset(&self, source: &str) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(source.len(), |(_, offset)| *offset)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn peek_ident(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token::Ident(ident)) => Some(ident.as_str()),
+            _ => None,
+        }
+    }
+
+    fn next(&mut self) -> ParseResult<Token> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| "unexpected end of input".to_string())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        if self.is_symbol(symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> ParseResult<()> {
+        match self.next()? {
+            Token::Symbol(s) if s == symbol => Ok(()),
+            token => Err(format!("expected '{}', found {:?}", symbol, token)),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> ParseResult<()> {
+        match self.next()? {
+            Token::Ident(ident) if ident == keyword => Ok(()),
+            token => Err(format!("expected '{}', found {:?}", keyword, token)),
+        }
+    }
+
+    fn expect_ident(&mut self) -> ParseResult<String> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            token => Err(format!("expected identifier, found {:?}", token)),
+        }
+    }
+
+    fn skip_statement(&mut self) -> ParseResult<()> {
+        while !self.eat_symbol(";") {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> ParseResult<()> {
+        let keyword = match self.peek() {
+            Some(Token::Ident(ident)) => ident.clone(),
+            token => return Err(format!("expected a statement, found {:?}", token)),
+        };
+
+        match keyword.as_str() {
+            "OPENQASM" => {
+                self.skip_statement()?;
+            }
+            "include" => {
+                self.pos += 1;
+                match self.next()? {
+                    // the standard gates are native to Circuit::new
+                    Token::Str(path) if path == "stdgates.inc" || path == "qelib1.inc" => (),
+                    Token::Str(path) => return Err(format!("unsupported include: \"{}\"", path)),
+                    token => return Err(format!("expected include path, found {:?}", token)),
+                }
+                self.expect_symbol(";")?;
+            }
+            "pragma" => {
+                self.pos += 1;
+                self.expect_keyword("unitaries")?;
+                match self.next()? {
+                    Token::Str(path) => self.statements.push(QasmStatement::Unitaries { path }),
+                    token => return Err(format!("expected unitaries path, found {:?}", token)),
+                }
+                self.eat_symbol(";");
+            }
+            "qubit" => {
+                self.pos += 1;
+                let size = if self.eat_symbol("[") {
+                    let size = self.const_index()?;
+                    self.expect_symbol("]")?;
+                    Some(size)
+                } else {
+                    None
+                };
+                let name = self.expect_ident()?;
+                self.expect_symbol(";")?;
+                if size.is_none() {
+                    self.scalars.insert(name.clone());
+                }
+                self.declare_qreg(name, size.unwrap_or(1));
+            }
+            "qreg" => {
+                self.pos += 1;
+                let name = self.expect_ident()?;
+                self.expect_symbol("[")?;
+                let size = self.const_index()?;
+                self.expect_symbol("]")?;
+                self.expect_symbol(";")?;
+                self.declare_qreg(name, size);
+            }
+            "const" => {
+                self.pos += 1;
+                self.skip_type()?;
+                let name = self.expect_ident()?;
+                self.expect_symbol("=")?;
+                let value = self.expression()?;
+                self.expect_symbol(";")?;
+                self.consts.insert(name, value);
+            }
+            "gate" => {
+                self.pos += 1;
+                let name = self.expect_ident()?;
+                let params = if self.eat_symbol("(") {
+                    self.identifiers(")")?
+                } else {
+                    vec![]
+                };
+                let args = self.identifiers("{")?;
+
+                // the body refers to the formal qubits only, so the program's
+                // qubits must not leak into it
+                let statements = mem::take(&mut self.statements);
+                let scalars = mem::take(&mut self.scalars);
+                let first_qubit = mem::replace(
+                    &mut self.first_qubit,
+                    args.first().cloned().map(Argument::Id),
+                );
+                let result = self.gate_body();
+                let body = mem::replace(&mut self.statements, statements);
+                self.scalars = scalars;
+                self.first_qubit = first_qubit;
+                result?;
+
+                self.statements.push(QasmStatement::GateDecl {
+                    name,
+                    params,
+                    args,
+                    body,
+                });
+            }
+            "bit" => {
+                self.pos += 1;
+                let size = if self.eat_symbol("[") {
+                    let size = self.const_index()?;
+                    self.expect_symbol("]")?;
+                    Some(size)
+                } else {
+                    None
+                };
+                let name = self.expect_ident()?;
+                if size.is_none() {
+                    self.scalars.insert(name.clone());
+                }
+                self.statements.push(QasmStatement::CReg {
+                    name: name.clone(),
+                    size: size.unwrap_or(1),
+                });
+                if self.eat_symbol("=") {
+                    // e.g. `bit[2] c = measure q;`
+                    self.expect_keyword("measure")?;
+                    let qubit = self.argument()?;
+                    let bit = if self.scalars.contains(&name) {
+                        Argument::Item(name, 0)
+                    } else {
+                        Argument::Id(name)
+                    };
+                    self.statements.push(QasmStatement::Measure {
+                        qubit,
+                        bit: Some(bit),
+                    });
+                }
+                self.expect_symbol(";")?;
+            }
+            "creg" => {
+                self.pos += 1;
+                let name = self.expect_ident()?;
+                self.expect_symbol("[")?;
+                let size = self.const_index()?;
+                self.expect_symbol("]")?;
+                self.expect_symbol(";")?;
+                self.statements.push(QasmStatement::CReg { name, size });
+            }
+            "measure" => {
+                self.pos += 1;
+                let qubit = self.argument()?;
+                let bit = if self.eat_symbol("->") {
+                    Some(self.argument()?)
+                } else {
+                    None
+                };
+                self.expect_symbol(";")?;
+                self.statements.push(QasmStatement::Measure { qubit, bit });
+            }
+            "reset" => {
+                self.pos += 1;
+                let qubit = self.argument()?;
+                self.expect_symbol(";")?;
+                self.statements.push(QasmStatement::Reset { qubit });
+            }
+            "barrier" => {
+                self.skip_statement()?;
+                log::debug!("Ignored unsupported statement: {}", keyword);
+            }
+            "if" => {
+                self.pos += 1;
+                self.expect_symbol("(")?;
+                let bits = self.argument()?;
+                let value = if self.eat_symbol("==") {
+                    self.const_index()? as u64
+                } else if matches!(bits, Argument::Item(..)) {
+                    1
+                } else {
+                    return Err("only `if (register == value)` is supported".to_string());
+                };
+                self.expect_symbol(")")?;
+
+                let statements = mem::take(&mut self.statements);
+                let result = self.conditional_body();
+                let body = mem::replace(&mut self.statements, statements);
+                result?;
+
+                for statement in body {
+                    match statement {
+                        QasmStatement::QReg { .. }
+                        | QasmStatement::CReg { .. }
+                        | QasmStatement::GateDecl { .. }
+                        | QasmStatement::Unitaries { .. } => {
+                            return Err("declarations are not allowed in an if body".to_string())
+                        }
+                        statement => self.statements.push(QasmStatement::Conditional {
+                            bits: bits.clone(),
+                            value,
+                            statement: Box::new(statement),
+                        }),
+                    }
+                }
+            }
+            "input" => {
+                // input parameters stay symbolic until bound at run time
+                self.pos += 1;
+                match self.peek_ident() {
+                    Some("float") | Some("angle") | Some("int") | Some("uint") => {
+                        self.skip_type()?
+                    }
+                    _ => return Err("only classical numeric inputs are supported".to_string()),
+                }
+                self.expect_ident()?;
+                self.expect_symbol(";")?;
+            }
+            "output" | "else" | "for" | "while" | "def" | "defcal" | "cal" | "let" | "box"
+            | "delay" => {
+                return Err(format!("unsupported statement: {}", keyword));
+            }
+            _ if self.is_assignment() => {
+                // e.g. `c[0] = measure q[0];`
+                let bit = self.argument()?;
+                self.expect_symbol("=")?;
+                if self.peek_ident() != Some("measure") {
+                    return Err(format!("unsupported assignment to {}", keyword));
+                }
+                self.pos += 1;
+                let qubit = self.argument()?;
+                self.expect_symbol(";")?;
+                self.statements.push(QasmStatement::Measure {
+                    qubit,
+                    bit: Some(bit),
+                });
+            }
+            _ => {
+                let calls = self.gate_call()?;
+                for call in calls {
+                    self.emit(call)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // parses either a single statement or a `{ ... }` block
+    fn conditional_body(&mut self) -> ParseResult<()> {
+        if self.eat_symbol("{") {
+            while !self.eat_symbol("}") {
+                self.statement()?;
+            }
+            Ok(())
+        } else {
+            self.statement()
+        }
+    }
+
+    fn gate_body(&mut self) -> ParseResult<()> {
+        while !self.eat_symbol("}") {
+            if self.peek_ident() == Some("barrier") {
+                self.skip_statement()?;
+            } else {
+                for call in self.gate_call()? {
+                    self.emit(call)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // parses `a, b, c` up to and including `terminator`
+    fn identifiers(&mut self, terminator: &str) -> ParseResult<Vec<String>> {
+        let mut identifiers = Vec::new();
+        while !self.eat_symbol(terminator) {
+            if !identifiers.is_empty() {
+                self.expect_symbol(",")?;
+            }
+            identifiers.push(self.expect_ident()?);
+        }
+        Ok(identifiers)
+    }
+
+    fn declare_qreg(&mut self, name: String, size: usize) {
+        if self.first_qubit.is_none() && size > 0 {
+            self.first_qubit = Some(Argument::Item(name.clone(), 0));
+        }
+        self.statements.push(QasmStatement::QReg { name, size });
+    }
+
+    fn is_assignment(&self) -> bool {
+        self.tokens[self.pos..]
+            .iter()
+            .map(|(token, _)| token)
+            .take_while(|token| **token != Token::Symbol(";"))
+            .any(|token| *token == Token::Symbol("="))
+    }
+
+    // skips a classical type such as `float`, `angle[32]` or `int[8]`
+    fn skip_type(&mut self) -> ParseResult<()> {
+        self.expect_ident()?;
+        if self.eat_symbol("[") {
+            while !self.eat_symbol("]") {
+                self.next()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn const_index(&mut self) -> ParseResult<usize> {
+        let expression = self.expression()?;
+        match fold(&expression) {
+            Some(value) if value >= 0.0 && value.fract() == 0.0 => Ok(value as usize),
+            _ => Err(format!(
+                "expected a nonnegative constant integer, found {:?}",
+                expression
+            )),
+        }
+    }
+
+    fn gate_call(&mut self) -> ParseResult<Vec<Call>> {
+        let mut modifiers = Vec::new();
+        loop {
+            let modifier = match self.peek_ident() {
+                Some("ctrl") | Some("negctrl") => {
+                    let negated = self.peek_ident() == Some("negctrl");
+                    self.pos += 1;
+                    let n = if self.eat_symbol("(") {
+                        let n = self.const_index()?;
+                        self.expect_symbol(")")?;
+                        n
+                    } else {
+                        1
+                    };
+                    if negated {
+                        Modifier::NegCtrl(n)
+                    } else {
+                        Modifier::Ctrl(n)
+                    }
+                }
+                Some("inv") => {
+                    self.pos += 1;
+                    Modifier::Inv
+                }
+                Some("pow") => {
+                    self.pos += 1;
+                    self.expect_symbol("(")?;
+                    let exponent = self.expression()?;
+                    self.expect_symbol(")")?;
+                    Modifier::Pow(exponent)
+                }
+                _ => break,
+            };
+            self.expect_symbol("@")?;
+            modifiers.push(modifier);
+        }
+
+        let name = match self.expect_ident()?.as_str() {
+            "U" => "u".to_string(),
+            "CX" => "cx".to_string(),
+            name => name.to_string(),
+        };
+
+        let mut params = Vec::new();
+        if self.eat_symbol("(") && !self.eat_symbol(")") {
+            loop {
+                params.push(self.expression()?);
+                if self.eat_symbol(")") {
+                    break;
+                }
+                self.expect_symbol(",")?;
+            }
+        }
+
+        let mut args = Vec::new();
+        while !self.eat_symbol(";") {
+            if !args.is_empty() {
+                self.expect_symbol(",")?;
+            }
+            args.push(self.argument()?);
+        }
+
+        lower_modifiers(&modifiers, Call { name, params, args })
+    }
+
+    fn argument(&mut self) -> ParseResult<Argument> {
+        let name = self.expect_ident()?;
+        if self.eat_symbol("[") {
+            let index = self.const_index()?;
+            self.expect_symbol("]")?;
+            Ok(Argument::Item(name, index))
+        } else if self.scalars.contains(&name) {
+            Ok(Argument::Item(name, 0))
+        } else {
+            Ok(Argument::Id(name))
+        }
+    }
+
+    fn emit(&mut self, call: Call) -> ParseResult<()> {
+        if call.name == "gphase" && call.args.is_empty() {
+            // e^{i theta} = rz(-2 theta) p(2 theta) on any qubit
+            let target = self
+                .first_qubit
+                .clone()
+                .ok_or_else(|| "gphase before any qubit declaration".to_string())?;
+            let theta = call
+                .params
+                .into_iter()
+                .next()
+                .ok_or("gphase expects one parameter")?;
+            let twice = Expression::Op(OpCode::Mul, Box::new(Expression::Int(2)), Box::new(theta));
+            self.statements.push(QasmStatement::GateCall {
+                name: "p".to_string(),
+                params: vec![twice.clone()],
+                args: vec![target.clone()],
+            });
+            self.statements.push(QasmStatement::GateCall {
+                name: "rz".to_string(),
+                params: vec![Expression::Minus(Box::new(twice))],
+                args: vec![target],
+            });
+        } else {
+            self.statements.push(QasmStatement::GateCall {
+                name: call.name,
+                params: call.params,
+                args: call.args,
+            });
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self) -> ParseResult<Expression> {
+        self.integer_operation(0)
+    }
+
+    /// The operators of `INTEGER_OPERATORS[level..]`, which are folded as
+    /// `Expression` cannot represent them
+    fn integer_operation(&mut self, level: usize) -> ParseResult<Expression> {
+        if level == INTEGER_OPERATORS.len() {
+            return self.sum();
+        }
+        let mut lhs = self.integer_operation(level + 1)?;
+        while let Some(&symbol) = INTEGER_OPERATORS[level]
+            .iter()
+            .find(|symbol| self.is_symbol(symbol))
+        {
+            self.pos += 1;
+            let rhs = self.integer_operation(level + 1)?;
+            lhs = fold_integer_operation(symbol, &lhs, &rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn sum(&mut self) -> ParseResult<Expression> {
+        let mut lhs = self.term()?;
+        loop {
+            let opcode = if self.eat_symbol("+") {
+                OpCode::Add
+            } else if self.eat_symbol("-") {
+                OpCode::Sub
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.term()?;
+            lhs = Expression::Op(opcode, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn term(&mut self) -> ParseResult<Expression> {
+        let mut lhs = self.unary()?;
+        loop {
+            let opcode = if self.eat_symbol("*") {
+                OpCode::Mul
+            } else if self.eat_symbol("/") {
+                OpCode::Div
+            } else if self.eat_symbol("%") {
+                let rhs = self.unary()?;
+                lhs = fold_integer_operation("%", &lhs, &rhs)?;
+                continue;
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.unary()?;
+            lhs = Expression::Op(opcode, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> ParseResult<Expression> {
+        if self.eat_symbol("-") {
+            Ok(Expression::Minus(Box::new(self.unary()?)))
+        } else if self.eat_symbol("+") {
+            self.unary()
+        } else if self.eat_symbol("~") {
+            let operand = self.unary()?;
+            Ok(integer(!constant_integer("~", &operand)?))
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> ParseResult<Expression> {
+        let base = self.primary()?;
+        if self.eat_symbol("**") {
+            // right associative, and binds tighter than unary minus on its left
+            let exponent = self.unary()?;
+            Ok(Expression::Op(
+                OpCode::Pow,
+                Box::new(base),
+                Box::new(exponent),
+            ))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn primary(&mut self) -> ParseResult<Expression> {
+        match self.next()? {
+            Token::Int(x) => Ok(Expression::Int(x)),
+            Token::Real(x) => Ok(Expression::Real(x)),
+            Token::Symbol("(") => {
+                let expression = self.expression()?;
+                self.expect_symbol(")")?;
+                Ok(expression)
+            }
+            Token::Ident(ident) => match ident.as_str() {
+                "pi" | "π" => Ok(Expression::Pi),
+                "tau" | "τ" => Ok(Expression::Op(
+                    OpCode::Mul,
+                    Box::new(Expression::Int(2)),
+                    Box::new(Expression::Pi),
+                )),
+                "euler" | "ℯ" => Ok(Expression::Real(std::f64::consts::E)),
+                "sin" | "cos" | "tan" | "exp" | "ln" | "sqrt" => {
+                    let funccode = match ident.as_str() {
+                        "sin" => FuncCode::Sin,
+                        "cos" => FuncCode::Cos,
+                        "tan" => FuncCode::Tan,
+                        "exp" => FuncCode::Exp,
+                        "ln" => FuncCode::Ln,
+                        _ => FuncCode::Sqrt,
+                    };
+                    self.expect_symbol("(")?;
+                    let argument = self.expression()?;
+                    self.expect_symbol(")")?;
+                    Ok(Expression::Function(funccode, Box::new(argument)))
+                }
+                _ => match self.consts.get(&ident) {
+                    Some(value) => Ok(value.clone()),
+                    None => Ok(Expression::Id(ident)),
+                },
+            },
+            token => Err(format!("unexpected {:?} in expression", token)),
+        }
+    }
+}
+
+/// Evaluates an expression built only from literals, if possible
+fn fold(expression: &Expression) -> Option<f64> {
+    match expression {
+        Expression::Pi => Some(std::f64::consts::PI),
+        Expression::Real(x) => Some(*x),
+        Expression::Int(x) => Some(*x as f64),
+        Expression::Minus(e) => fold(e).map(|x| -x),
+        Expression::Op(opcode, e1, e2) => {
+            let (v1, v2) = (fold(e1)?, fold(e2)?);
+            match opcode {
+                OpCode::Add => Some(v1 + v2),
+                OpCode::Sub => Some(v1 - v2),
+                OpCode::Mul => Some(v1 * v2),
+                OpCode::Div => Some(v1 / v2),
+                OpCode::Pow => Some(v1.powf(v2)),
+                _ => None,
+            }
+        }
+        Expression::Function(funccode, e) => {
+            let v = fold(e)?;
+            match funccode {
+                FuncCode::Sin => Some(v.sin()),
+                FuncCode::Cos => Some(v.cos()),
+                FuncCode::Tan => Some(v.tan()),
+                FuncCode::Exp => Some(v.exp()),
+                FuncCode::Ln => Some(v.ln()),
+                FuncCode::Sqrt => Some(v.sqrt()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Evaluates an integer operator, whose operands must be constant
+fn fold_integer_operation(
+    symbol: &str,
+    lhs: &Expression,
+    rhs: &Expression,
+) -> ParseResult<Expression> {
+    let (a, b) = (
+        constant_integer(symbol, lhs)?,
+        constant_integer(symbol, rhs)?,
+    );
+    let value = match symbol {
+        "%" => a.checked_rem(b),
+        "<<" => u32::try_from(b).ok().and_then(|b| a.checked_shl(b)),
+        ">>" => u32::try_from(b).ok().and_then(|b| a.checked_shr(b)),
+        "&" => Some(a & b),
+        "|" => Some(a | b),
+        _ => Some(a ^ b),
+    };
+    value
+        .map(integer)
+        .ok_or_else(|| format!("invalid operands for {}: {} and {}", symbol, a, b))
+}
+
+fn constant_integer(symbol: &str, expression: &Expression) -> ParseResult<i64> {
+    match fold(expression) {
+        Some(value) if value.fract() == 0.0 => Ok(value as i64),
+        _ => Err(format!("operands of {} must be constant integers", symbol)),
+    }
+}
+
+fn integer(value: i64) -> Expression {
+    if value < 0 {
+        negate(Expression::Int(value.unsigned_abs()))
+    } else {
+        Expression::Int(value as u64)
+    }
+}
+
+fn negate(expression: Expression) -> Expression {
+    Expression::Minus(Box::new(expression))
+}
+
+/// Applies gate modifiers, innermost first, producing an equivalent sequence
+/// of unmodified gate calls.
+fn lower_modifiers(modifiers: &[Modifier], call: Call) -> ParseResult<Vec<Call>> {
+    // the arguments are laid out as [controls of the outermost modifier, ...,
+    // controls of the innermost modifier, arguments of the base gate]
+    let num_controls: usize = modifiers.iter().map(Modifier::num_controls).sum();
+    if call.args.len() < num_controls {
+        return Err(format!(
+            "{} expects at least {} control qubits",
+            call.name, num_controls
+        ));
+    }
+    let mut control_args = call.args;
+    let base_args = control_args.split_off(num_controls);
+
+    let mut calls = vec![Call {
+        name: call.name,
+        params: call.params,
+        args: base_args,
+    }];
+
+    for modifier in modifiers.iter().rev() {
+        calls = match modifier {
+            Modifier::Inv => calls
+                .into_iter()
+                .rev()
+                .map(invert)
+                .collect::<ParseResult<Vec<_>>>()?,
+            Modifier::Pow(exponent) => power(calls, exponent)?,
+            Modifier::Ctrl(n) | Modifier::NegCtrl(n) => {
+                let controls = control_args.split_off(control_args.len() - n);
+                let negated = matches!(modifier, Modifier::NegCtrl(_));
+
+                let mut controlled = calls;
+                for control in controls.iter().rev() {
+                    controlled = controlled
+                        .into_iter()
+                        .flat_map(|call| add_control(call, control.clone(), negated))
+                        .collect();
+                }
+                controlled
+            }
+        };
+    }
+
+    Ok(calls)
+}
+
+/// Controls `call` on `control`, or on `control` being unset if `negated`.
+/// Gates without a named controlled counterpart become `ctrl@<name>` or
+/// `negctrl@<name>`, which the circuit builder resolves into a controlled
+/// gate.
+fn add_control(call: Call, control: Argument, negated: bool) -> Vec<Call> {
+    if call.name == "gphase" {
+        // a controlled global phase is a phase gate on the control
+        let phase = Call {
+            name: "p".to_string(),
+            params: call.params,
+            args: vec![control.clone()],
+        };
+        if !negated {
+            return vec![phase];
+        }
+        let flip = Call {
+            name: "x".to_string(),
+            params: vec![],
+            args: vec![control],
+        };
+        return vec![flip.clone(), phase, flip];
+    }
+
+    if negated {
+        return vec![with_control(
+            format!("negctrl@{}", call.name),
+            call,
+            control,
+        )];
+    }
+
+    let name = match call.name.as_str() {
+        "x" => "cx",
+        "y" => "cy",
+        "z" => "cz",
+        "h" => "ch",
+        "sx" => "csx",
+        "swap" => "cswap",
+        "p" | "phase" => "cp",
+        "u1" => "cu1",
+        "rx" => "crx",
+        "ry" => "cry",
+        "rz" => "crz",
+        "u" | "u3" => "cu3",
+        "cx" => "ccx",
+        "ccx" => "c3x",
+        "c3x" => "c4x",
+        name => return vec![with_control(format!("ctrl@{}", name), call, control)],
+    };
+
+    vec![with_control(name.to_string(), call, control)]
+}
+
+fn with_control(name: String, call: Call, control: Argument) -> Call {
+    let mut args = vec![control];
+    args.extend(call.args);
+    Call {
+        name,
+        params: call.params,
+        args,
+    }
+}
+
+fn invert(call: Call) -> ParseResult<Call> {
+    let Call { name, params, args } = call;
+
+    // controls are unaffected by inversion
+    if let Some((modifier, base)) = name.split_once('@') {
+        let inverted = invert(Call {
+            name: base.to_string(),
+            params,
+            args,
+        })?;
+        return Ok(Call {
+            name: format!("{}@{}", modifier, inverted.name),
+            ..inverted
+        });
+    }
+
+    let (name, params) = match name.as_str() {
+        "id" | "x" | "y" | "z" | "h" | "cx" | "cy" | "cz" | "ch" | "swap" | "ccx" | "cswap"
+        | "c3x" | "c4x" => (name, params),
+        "s" => ("sdg".to_string(), params),
+        "sdg" => ("s".to_string(), params),
+        "t" => ("tdg".to_string(), params),
+        "tdg" => ("t".to_string(), params),
+        "sx" => ("sxdg".to_string(), params),
+        "sxdg" => ("sx".to_string(), params),
+        "gphase" | "p" | "phase" | "u1" | "rx" | "ry" | "rz" | "cp" | "cphase" | "cu1" | "crx"
+        | "cry" | "crz" | "rxx" | "ryy" | "rzz" | "fsim" => {
+            (name, params.into_iter().map(negate).collect())
+        }
+        "u" | "u3" | "cu3" if params.len() == 3 => {
+            // U(theta, phi, lambda)^dagger = U(-theta, -lambda, -phi)
+            let [theta, phi, lambda]: [Expression; 3] = params.try_into().unwrap();
+            (name, vec![negate(theta), negate(lambda), negate(phi)])
+        }
+        "u2" if params.len() == 2 => {
+            // u2(phi, lambda)^dagger = u2(-lambda - pi, -phi + pi)
+            let [phi, lambda]: [Expression; 2] = params.try_into().unwrap();
+            (
+                name,
+                vec![
+                    Expression::Op(
+                        OpCode::Sub,
+                        Box::new(negate(lambda)),
+                        Box::new(Expression::Pi),
+                    ),
+                    Expression::Op(OpCode::Add, Box::new(negate(phi)), Box::new(Expression::Pi)),
+                ],
+            )
+        }
+        _ => return Err(format!("inv @ {} is not supported", name)),
+    };
+
+    Ok(Call { name, params, args })
+}
+
+fn power(calls: Vec<Call>, exponent: &Expression) -> ParseResult<Vec<Call>> {
+    let k = fold(exponent).ok_or_else(|| format!("pow exponent {:?} is not constant", exponent))?;
+    if !k.is_finite() {
+        return Err(format!("pow exponent {} is not finite", k));
+    }
+
+    if k.fract() == 0.0 {
+        if k.abs() > MAX_POW_REPEATS as f64 {
+            return Err(format!(
+                "pow({}) @ repeats the gate more than {} times",
+                k, MAX_POW_REPEATS
+            ));
+        }
+        let base = if k < 0.0 {
+            calls
+                .into_iter()
+                .rev()
+                .map(invert)
+                .collect::<ParseResult<Vec<_>>>()?
+        } else {
+            calls
+        };
+        let repeats = k.abs() as usize;
+        return Ok((0..repeats).flat_map(|_| base.iter().cloned()).collect());
+    }
+
+    // fractional powers are only defined here for single-parameter rotations
+    match calls.as_slice() {
+        [call] if is_rotation(&call.name) => match call.params.as_slice() {
+            [param] => Ok(vec![Call {
+                name: call.name.clone(),
+                params: vec![Expression::Op(
+                    OpCode::Mul,
+                    Box::new(param.clone()),
+                    Box::new(Expression::Real(k)),
+                )],
+                args: call.args.clone(),
+            }]),
+            params => Err(format!(
+                "{} expects one parameter, found {}",
+                call.name,
+                params.len()
+            )),
+        },
+        _ => Err(format!("pow({}) @ is only supported for rotation gates", k)),
+    }
+}
+
+fn is_rotation(name: &str) -> bool {
+    // a controlled rotation is a rotation as well
+    let base = name.rsplit('@').next().unwrap();
+    matches!(
+        base,
+        "gphase"
+            | "p"
+            | "phase"
+            | "u1"
+            | "rx"
+            | "ry"
+            | "rz"
+            | "cp"
+            | "cphase"
+            | "cu1"
+            | "crx"
+            | "cry"
+            | "crz"
+            | "rxx"
+            | "ryy"
+            | "rzz"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Circuit, GateDefn};
+    use crate::types::BasisIdx64;
+
+    fn gate_calls(source: &str) -> Vec<(String, Vec<Argument>)> {
+        parse_program(source)
+            .unwrap()
+            .into_iter()
+            .filter_map(|located| match located.statement {
+                QasmStatement::GateCall { name, args, .. } => Some((name, args)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let calls = gate_calls(
+            r#"
+            OPENQASM 3.0;
+            include "stdgates.inc";
+            qubit[3] q;
+            ctrl @ x q[0], q[1];
+            ctrl(2) @ x q[0], q[1], q[2];
+            inv @ s q[2];
+            negctrl @ z q[1], q[0];
+            pow(2) @ t q[0];
+            "#,
+        );
+
+        let names: Vec<&str> = calls.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["cx", "ccx", "sdg", "negctrl@z", "t", "t"]);
+
+        let pow = |modifier: &str| {
+            parse_program(&format!("OPENQASM 3;\nqubit q;\n{} q;\n", modifier))
+                .map_err(|err| err.message)
+        };
+        assert_eq!(
+            pow("pow(0.5) @ rz").unwrap_err(),
+            "rz expects one parameter, found 0"
+        );
+        assert_eq!(
+            pow("pow(-4096) @ x").unwrap_err(),
+            "pow(-4096) @ repeats the gate more than 1024 times"
+        );
+        assert_eq!(pow("pow(-2) @ s").unwrap().len(), 3);
+        assert_eq!(
+            calls[1].1,
+            vec![
+                Argument::Item("q".to_string(), 0),
+                Argument::Item("q".to_string(), 1),
+                Argument::Item("q".to_string(), 2),
+            ]
+        );
+        assert_eq!(
+            calls[3].1,
+            vec![
+                Argument::Item("q".to_string(), 1),
+                Argument::Item("q".to_string(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_consts_inputs_and_gphase() {
+        let program = parse_program(
+            r#"
+            OPENQASM 3;
+            const float theta = tau / 8;
+            input float[64] gamma;
+            qubit q;
+            qubit[2] r;
+            U(theta, 0, π) q;
+            gphase(theta);
+            ctrl @ gphase(theta) r[1];
+            rz(gamma / 2) r[0];
+            "#,
+        )
+        .unwrap();
+
+        let circuit = Circuit::<BasisIdx64>::new(program).unwrap();
+        assert_eq!(circuit.num_qubits, 3);
+        assert_eq!(circuit.gates.len(), 5);
+        assert!(circuit.parameters().contains("gamma"));
+    }
+
+    #[test]
+    fn test_integer_operators() {
+        let program = parse_program(
+            r#"
+            OPENQASM 3;
+            const int n = 7 % 4 | 1 << 3 ^ ~-2;
+            qubit q;
+            rz(n * pi / 16) q;
+            "#,
+        )
+        .unwrap();
+        match &program[1].statement {
+            QasmStatement::GateCall { params, .. } => {
+                assert_eq!(fold(&params[0]), Some(11.0 * std::f64::consts::PI / 16.0))
+            }
+            statement => panic!("expected a gate call, found {:?}", statement),
+        }
+
+        let err = parse_program("OPENQASM 3;\ninput float gamma;\nqubit q;\nrz(gamma % 2) q;\n")
+            .unwrap_err();
+        assert_eq!(err.message, "operands of % must be constant integers");
+    }
+
+    #[test]
+    fn test_gate_decl() {
+        let program = parse_program(
+            r#"
+            OPENQASM 3.0;
+            qubit[2] q;
+            gate phased(theta) a, b {
+                ctrl @ rz(theta / 2) a, b;
+                gphase(theta);
+            }
+            phased(pi) q[1], q[0];
+            "#,
+        )
+        .unwrap();
+
+        match &program[1].statement {
+            QasmStatement::GateDecl {
+                params, args, body, ..
+            } => {
+                assert_eq!(params, &vec!["theta".to_string()]);
+                assert_eq!(args, &vec!["a".to_string(), "b".to_string()]);
+                // ctrl @ rz, then gphase lowered to p and rz on `a`
+                assert_eq!(body.len(), 3);
+            }
+            statement => panic!("expected a gate declaration, found {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn test_error_location() {
+        let err =
+            parse_program("OPENQASM 3;\nqubit[2] q;\n  inv @ ccphase q[0], q[1];\n").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 3));
+
+        // lowered statements share the location of their source statement
+        let program =
+            parse_program("OPENQASM 3;\nqubit[2] q;\n  negctrl @ gphase(0.5) q[0];\n").unwrap();
+        let locations: Vec<(usize, usize)> = program
+            .iter()
+            .map(|located| (located.location.line, located.location.column))
+            .collect();
+        assert_eq!(locations, vec![(2, 1), (3, 3), (3, 3), (3, 3)]);
+    }
+
+    #[test]
+    fn test_measure_and_conditional() {
+        let program = parse_program(
+            r#"
+            OPENQASM 3.0;
+            include "stdgates.inc";
+            qubit[2] q;
+            bit[2] c;
+            h q[0];
+            c[0] = measure q[0];
+            measure q[1] -> c[1];
+            if (c[0]) x q[1];
+            if (c == 3) {
+                reset q;
+            }
+            "#,
+        )
+        .unwrap();
+
+        let circuit = Circuit::<BasisIdx64>::new(program).unwrap();
+        assert_eq!(circuit.num_clbits(), 2);
+        assert!(circuit.dynamic_gate().is_some());
+        assert_eq!(circuit.gates.len(), 6);
+    }
+
+    #[test]
+    fn test_controlled_gates() {
+        let program = parse_program(
+            r#"
+            OPENQASM 3.0;
+            include "stdgates.inc";
+            qubit[8] q;
+            gate flip a, b {
+                x a;
+                cx a, b;
+            }
+            ctrl(6) @ x q[0], q[1], q[2], q[3], q[4], q[5], q[6];
+            negctrl(2) @ h q[0], q[1], q[2];
+            ctrl @ flip q[7], q[0], q[1];
+            "#,
+        )
+        .unwrap();
+
+        let circuit = Circuit::<BasisIdx64>::new(program).unwrap();
+        assert_eq!(circuit.gates.len(), 4);
+        assert_eq!(circuit.gates[0].touches.len(), 7);
+        assert!(!circuit.gates[0].is_branching());
+        match &circuit.gates[1].defn {
+            GateDefn::Controlled {
+                controls,
+                neg_controls,
+                base,
+            } => {
+                assert!(controls.is_empty());
+                assert_eq!(neg_controls, &vec![0, 1]);
+                assert!(matches!(**base, GateDefn::Hadamard(2)));
+            }
+            defn => panic!("expected a controlled gate, found {:?}", defn),
+        }
+        assert!(circuit
+            .gates
+            .iter()
+            .all(|gate| matches!(gate.defn, GateDefn::Controlled { .. })));
+    }
+}
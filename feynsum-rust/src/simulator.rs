@@ -1,5 +1,7 @@
-use std::cmp;
-use std::str::FromStr;
+mod auto;
+mod backend;
+mod dynamic;
+mod sampling;
 
 pub mod dense_simulator;
 pub mod hybrid_simulator;
@@ -7,36 +9,29 @@ pub mod mps_simulator;
 pub mod parallel_simulator;
 pub mod sequential_simulator;
 
-use crate::types::{BasisIdx, Complex, Real};
+use crate::types::{BasisIdx, Precision, Real, Weight};
 
-pub trait Compactifiable<B: BasisIdx> {
-    fn compactify(self) -> Box<dyn Iterator<Item = (B, Complex)>>;
+pub use backend::{FinalState, Outcome, Registry, SimulatorBackend, Stats};
+pub use dynamic::run_dynamic;
+pub use sampling::Counts;
+
+/// The nonzero weights of a final state, by basis index
+pub type Densities<B, P> = Box<dyn Iterator<Item = (B, Weight<P>)>>;
+
+pub trait Compactifiable<B: BasisIdx, P: Precision> {
+    fn compactify(self) -> Densities<B, P>;
 }
 
-#[derive(Debug)]
-pub enum Simulator {
-    Sequential,
-    Parallel,
-    Dense,
-    Hybrid,
-    MPS,
+fn compactify<B: BasisIdx, P: Precision, S: Compactifiable<B, P>>(
+    (state, clbits): (S, Vec<bool>),
+) -> FinalState<B, P> {
+    (state.compactify(), clbits).into()
 }
 
-impl FromStr for Simulator {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "sequential" | "seq" => Ok(Simulator::Sequential),
-            "parallel" | "par" => Ok(Simulator::Parallel),
-            "dense" => Ok(Simulator::Dense),
-            "hybrid" => Ok(Simulator::Hybrid),
-            "mps" => Ok(Simulator::MPS),
-            _ => Err(format!(
-                "unknown simulator: {}; valid values are: sequential, parallel, dense and mps",
-                s
-            )),
-        }
+/// The futhark kernels are single precision, unlike the Rust ones
+fn warn_single_precision_kernels<P: Precision>() {
+    if cfg!(feature = "futhark") && P::BITS > 32 {
+        log::warn!("the futhark kernels are 32 bits, so dense gates lose precision");
     }
 }
 
@@ -46,12 +41,20 @@ pub fn expected_cost(
     num_nonzeros: usize,
     prev_num_nonzeros: usize,
 ) -> (Real, usize) {
-    let max_num_states = 1 << num_qubits;
+    let max_num_states = (num_qubits as Real).exp2();
     let rate = Real::max(1.0, num_nonzeros as Real / prev_num_nonzeros as Real);
-    let expected_num_nonzeros =
-        cmp::min(max_num_states, (rate * num_nonzeros as Real) as i64) as usize;
-    let expected_density = expected_num_nonzeros as Real / max_num_states as Real;
-    let current_density = num_nonzeros as Real / max_num_states as Real;
+    let expected_num_nonzeros = Real::min(max_num_states, rate * num_nonzeros as Real).floor();
+    let expected_density = expected_num_nonzeros / max_num_states;
+    let current_density = density(num_qubits, num_nonzeros);
+
+    (
+        expected_density.max(current_density),
+        expected_num_nonzeros as usize,
+    )
+}
 
-    (expected_density.max(current_density), expected_num_nonzeros)
+/// the fraction of the basis states that are nonzero. `2^num_qubits` is taken
+/// in `Real`, since it overflows the integers past 63 qubits
+pub fn density(num_qubits: usize, num_nonzeros: usize) -> Real {
+    num_nonzeros as Real / (num_qubits as Real).exp2()
 }
@@ -0,0 +1,262 @@
+use crate::circuit::Circuit;
+use crate::config::Config;
+use crate::gate_scheduler::{self, SupportModel};
+use crate::types::{BasisIdx, Real};
+
+/// The simulator name that picks a backend for each circuit
+pub const AUTO: &str = "auto";
+
+/// States that stay this small are simulated on one thread
+const SEQUENTIAL_MAX_NONZEROS: Real = 16384.0;
+/// Sparse states that grow larger than this do not fit in memory
+const SPARSE_MAX_NONZEROS: Real = 4294967296.0;
+/// The largest dense state that the dense kernels are given, at 8 bytes a
+/// weight
+const DENSE_MAX_QUBITS: usize = 30;
+/// States that get this dense are worth simulating with the dense kernels
+const HYBRID_MIN_DENSITY: Real = 0.5;
+/// The dense threshold of states that are bound to get dense, so that they
+/// stop paying for the sparse table early
+const EARLY_DENSE_THRESHOLD: Real = 0.1;
+/// The fraction of the two-qubit gates that must be on neighbouring qubits
+/// for the MPS simulator, which swaps the others into place
+const MPS_MIN_LOCALITY: Real = 0.9;
+
+/// What the automatic choice of a backend knows of a circuit
+#[derive(Debug)]
+pub struct Analysis {
+    pub num_qubits: usize,
+    pub num_gates: usize,
+    pub num_branching_gates: usize,
+    /// The most nonzeros that the support model predicts after any pick of
+    /// the gate scheduler
+    pub max_num_nonzeros: Real,
+    pub num_two_qubit_gates: usize,
+    /// The two-qubit gates on neighbouring qubits
+    pub num_local_gates: usize,
+    /// Whether a gate touches more than two qubits
+    pub has_wide_gates: bool,
+    /// Whether the circuit measures, resets or controls gates classically
+    pub is_dynamic: bool,
+}
+
+impl Analysis {
+    pub fn new<B: BasisIdx>(config: &Config, circuit: &Circuit<B>) -> Self {
+        let mut model = SupportModel::new(circuit.num_qubits);
+        let mut max_num_nonzeros = model.num_nonzeros();
+        let is_dynamic = circuit.dynamic_gate().is_some();
+
+        if is_dynamic {
+            // the scheduler only sees the segments between measurements
+            for gate in &circuit.gates {
+                model.apply(gate);
+                max_num_nonzeros = max_num_nonzeros.max(model.num_nonzeros());
+            }
+        } else {
+            // neither recording nor replaying, which is up to the simulation
+            let mut gate_scheduler = gate_scheduler::create_policy_gate_scheduler(config, circuit);
+            loop {
+                let next_gates = gate_scheduler.pick_next_gates();
+                if next_gates.is_empty() {
+                    break;
+                }
+                for gi in next_gates {
+                    model.apply(&circuit.gates[gi]);
+                }
+                max_num_nonzeros = max_num_nonzeros.max(model.num_nonzeros());
+            }
+        }
+
+        let two_qubit_gates = circuit
+            .gates
+            .iter()
+            .filter_map(|gate| match gate.touches.as_slice() {
+                [qi, qj] => Some(qi.abs_diff(*qj)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            num_qubits: circuit.num_qubits,
+            num_gates: circuit.num_gates(),
+            num_branching_gates: circuit
+                .gates
+                .iter()
+                .filter(|gate| gate.is_branching())
+                .count(),
+            max_num_nonzeros,
+            num_two_qubit_gates: two_qubit_gates.len(),
+            num_local_gates: two_qubit_gates
+                .iter()
+                .filter(|distance| **distance == 1)
+                .count(),
+            has_wide_gates: circuit.gates.iter().any(|gate| gate.touches.len() > 2),
+            is_dynamic,
+        }
+    }
+
+    /// The largest fraction of the basis states that is nonzero at once
+    pub fn max_density(&self) -> Real {
+        self.max_num_nonzeros / (self.num_qubits as Real).exp2()
+    }
+
+    /// The fraction of the two-qubit gates on neighbouring qubits
+    pub fn locality(&self) -> Real {
+        if self.num_two_qubit_gates == 0 {
+            1.0
+        } else {
+            self.num_local_gates as Real / self.num_two_qubit_gates as Real
+        }
+    }
+}
+
+/// Picks the backend and thresholds to simulate `circuit` with, and logs why
+pub fn choose<B: BasisIdx>(config: &Config, circuit: &Circuit<B>) -> Config {
+    let analysis = Analysis::new(config, circuit);
+    log::info!(
+        "auto: {} qubits, {} gates of which {} branch, up to {:.0} nonzeros (density {:.8}), {} of {} two-qubit gates local",
+        analysis.num_qubits,
+        analysis.num_gates,
+        analysis.num_branching_gates,
+        analysis.max_num_nonzeros,
+        analysis.max_density(),
+        analysis.num_local_gates,
+        analysis.num_two_qubit_gates
+    );
+
+    let mut config = config.clone();
+    let num_threads = rayon::current_num_threads();
+    let is_too_large =
+        analysis.max_num_nonzeros > SPARSE_MAX_NONZEROS && analysis.num_qubits > DENSE_MAX_QUBITS;
+
+    config.simulator = if is_too_large
+        && !analysis.is_dynamic
+        && !analysis.has_wide_gates
+        && analysis.locality() >= MPS_MIN_LOCALITY
+    {
+        log::info!(
+            "auto: choosing mps, since the state is too large to store but the two-qubit gates are local"
+        );
+        "mps"
+    } else if analysis.max_num_nonzeros <= SEQUENTIAL_MAX_NONZEROS || num_threads == 1 {
+        log::info!(
+            "auto: choosing sequential, since {}",
+            if num_threads == 1 {
+                "there is one thread"
+            } else {
+                "the state stays too small to share between threads"
+            }
+        );
+        "sequential"
+    } else if analysis.max_density() >= HYBRID_MIN_DENSITY
+        && analysis.num_qubits <= DENSE_MAX_QUBITS
+    {
+        config.dense_threshold = config.dense_threshold.min(EARLY_DENSE_THRESHOLD);
+        config.sparse_threshold = config.sparse_threshold.min(config.dense_threshold);
+        log::info!(
+            "auto: choosing hybrid with dense threshold {}, since the state gets dense",
+            config.dense_threshold
+        );
+        "hybrid"
+    } else {
+        if is_too_large {
+            log::warn!("auto: the state may be too large to store, but no backend does better");
+        }
+        log::info!(
+            "auto: choosing parallel, since the state {} on {} threads",
+            if analysis.max_density() < config.dense_threshold {
+                "stays sparse"
+            } else {
+                "gets dense only late"
+            },
+            num_threads
+        );
+        "parallel"
+    }
+    .to_string();
+
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::GateDefn;
+    use crate::gate_scheduler::GateSchedulingPolicy;
+    use crate::types::BasisIdx64;
+
+    fn circuit(num_qubits: usize, defns: Vec<GateDefn>) -> Circuit<BasisIdx64> {
+        Circuit::from_gates(num_qubits, 0, defns).unwrap()
+    }
+
+    #[test]
+    fn test_analysis() {
+        let config = Config {
+            gate_scheduling_policy: GateSchedulingPolicy::Naive,
+            ..Config::default()
+        };
+
+        // the second layer of Hadamards undoes the first
+        let defns = (0..4)
+            .chain(0..4)
+            .map(GateDefn::Hadamard)
+            .chain([GateDefn::CX {
+                control: 0,
+                target: 3,
+            }])
+            .collect();
+        let analysis = Analysis::new(&config, &circuit(4, defns));
+        assert_eq!(analysis.num_gates, 9);
+        assert_eq!(analysis.num_branching_gates, 8);
+        assert_eq!(analysis.max_num_nonzeros, 16.0);
+        assert_eq!(analysis.max_density(), 1.0);
+        assert_eq!(analysis.locality(), 0.0);
+        assert!(!analysis.has_wide_gates);
+        assert!(!analysis.is_dynamic);
+
+        let defns = vec![
+            GateDefn::Hadamard(0),
+            GateDefn::CX {
+                control: 0,
+                target: 1,
+            },
+            GateDefn::Measure {
+                target: 1,
+                clbit: None,
+            },
+        ];
+        let analysis = Analysis::new(&config, &circuit(2, defns));
+        // the model forgets what it knows of a measured qubit
+        assert_eq!(analysis.max_num_nonzeros, 4.0);
+        assert_eq!(analysis.locality(), 1.0);
+        assert!(analysis.is_dynamic);
+    }
+
+    #[test]
+    fn test_choose() {
+        let config = Config::default();
+        let small = circuit(2, vec![GateDefn::Hadamard(0)]);
+        assert_eq!(choose(&config, &small).simulator, "sequential");
+
+        // 2^20 nonzeros, all of the basis states
+        let dense = circuit(20, (0..20).map(GateDefn::Hadamard).collect());
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let chosen = pool.install(|| choose(&config, &dense));
+        assert_eq!(chosen.simulator, "hybrid");
+        assert_eq!(chosen.dense_threshold, EARLY_DENSE_THRESHOLD);
+
+        // 2^40 nonzeros on a line of qubits
+        let defns = (0..40)
+            .map(GateDefn::Hadamard)
+            .chain((0..39).map(|qi| GateDefn::CZ {
+                control: qi,
+                target: qi + 1,
+            }))
+            .collect();
+        let chosen = pool.install(|| choose(&config, &circuit(40, defns)));
+        assert_eq!(chosen.simulator, "mps");
+    }
+}
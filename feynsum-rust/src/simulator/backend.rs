@@ -0,0 +1,446 @@
+use std::time::Duration;
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::circuit::Circuit;
+use crate::config::Config;
+use crate::error::{self, Error, ErrorKind};
+use crate::gate_scheduler::{Schedule, ScheduleCursor};
+use crate::profile;
+use crate::types::{AtomicBasisIdx, BasisIdx, Precision, QubitIndex};
+
+use super::{
+    auto, dense_simulator, hybrid_simulator, mps_simulator, parallel_simulator, sampling,
+    sequential_simulator, Counts, Densities,
+};
+
+/// A way of simulating circuits, looked up by name in a `Registry`. Weights
+/// are stored in precision `P`, and basis indices as `B`, or atomically as
+/// `AB` by the backends that share a table between threads.
+pub trait SimulatorBackend<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> {
+    /// The name that `Config::simulator` selects the backend by
+    fn name(&self) -> &'static str;
+
+    /// Other names that select the backend
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Simulates `circuit`, and returns the nonzero weights of the final state
+    /// and the classical bits. The gate schedulers replay and record their
+    /// picks at `cursor`, see `gate_scheduler::create_gate_scheduler`.
+    fn simulate(
+        &self,
+        config: &Config,
+        circuit: Circuit<B>,
+        cursor: &ScheduleCursor,
+    ) -> error::Result<FinalState<B, P>>;
+}
+
+/// What a backend returns from a simulation
+pub struct FinalState<B: BasisIdx, P: Precision> {
+    /// The nonzero weights of the final state, by basis index
+    pub densities: Densities<B, P>,
+    pub clbits: Vec<bool>,
+    /// `config.shots` basis states drawn from the final state, by backends
+    /// that draw them without listing its nonzero weights. Otherwise they are
+    /// drawn from `densities`.
+    pub samples: Option<Vec<B>>,
+}
+
+impl<B: BasisIdx, P: Precision> From<(Densities<B, P>, Vec<bool>)> for FinalState<B, P> {
+    fn from((densities, clbits): (Densities<B, P>, Vec<bool>)) -> Self {
+        Self {
+            densities,
+            clbits,
+            samples: None,
+        }
+    }
+}
+
+/// What every backend reports about a simulation
+#[derive(Debug, Clone)]
+pub struct Stats {
+    /// The name of the backend that ran the simulation
+    pub simulator: &'static str,
+    pub num_qubits: usize,
+    pub num_gates: usize,
+    pub duration: Duration,
+}
+
+/// The result of a simulation
+pub struct Outcome<B: BasisIdx, P: Precision> {
+    /// The nonzero weights of the final state, by basis index
+    pub densities: Densities<B, P>,
+    pub clbits: Vec<bool>,
+    /// The outcomes of `config.shots` measurements of the final state, if
+    /// it is set
+    pub counts: Option<Counts>,
+    /// The picks of the gate schedulers, if `config.record_schedule` is set
+    pub schedule: Option<Schedule>,
+    pub stats: Stats,
+}
+
+/// The backends that simulations can be run with, by name. The default
+/// registry holds the builtin backends.
+pub struct Registry<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> {
+    backends: Vec<Box<dyn SimulatorBackend<B, AB, P>>>,
+}
+
+impl<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> Default for Registry<B, AB, P> {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(sequential_simulator::Backend);
+        registry.register(parallel_simulator::Backend);
+        registry.register(dense_simulator::Backend);
+        registry.register(hybrid_simulator::Backend);
+        registry.register(mps_simulator::Backend);
+        registry
+    }
+}
+
+impl<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> Registry<B, AB, P> {
+    /// An empty registry
+    pub fn new() -> Self {
+        Self {
+            backends: Vec::new(),
+        }
+    }
+
+    /// Adds `backend`, which takes precedence over the backends registered
+    /// before it under the same name
+    pub fn register(&mut self, backend: impl SimulatorBackend<B, AB, P> + 'static) {
+        self.backends.insert(0, Box::new(backend));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn SimulatorBackend<B, AB, P>> {
+        self.backends
+            .iter()
+            .find(|backend| backend.name() == name || backend.aliases().contains(&name))
+            .map(|backend| backend.as_ref())
+    }
+
+    /// The names of the registered backends, in the order they were registered
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        for backend in self.backends.iter().rev() {
+            if !names.contains(&backend.name()) {
+                names.push(backend.name());
+            }
+        }
+        names
+    }
+
+    /// Simulates `circuit` with the backend named by `config.simulator`, or
+    /// the one that `auto::choose` picks if it is `auto`. With `config.shots`
+    /// set, the final measurements are left out, and the final state is
+    /// sampled instead. The state of one run is only the distribution of the
+    /// shots if nothing else in the circuit is random, so circuits that still
+    /// measure, reset or branch on classical bits are rejected.
+    pub fn run(&self, config: &Config, circuit: Circuit<B>) -> error::Result<Outcome<B, P>> {
+        let (circuit, measured) = if config.shots > 0 {
+            circuit.without_final_measurements()
+        } else {
+            (circuit, Vec::new())
+        };
+        if let Some(gate) = circuit.dynamic_gate().filter(|_| config.shots > 0) {
+            log::error!("shots of a circuit with the dynamic gate {:?}", gate.defn);
+            return Err(Error::new(
+                ErrorKind::UnsupportedGate,
+                "shots are drawn from the final state, so measurement, reset and classical control are only supported at the end of the circuit",
+            )
+            .at(gate.location));
+        }
+
+        if config.simulator == auto::AUTO {
+            return self.run_backend(&auto::choose(config, &circuit), circuit, &measured);
+        }
+        self.run_backend(config, circuit, &measured)
+    }
+
+    fn run_backend(
+        &self,
+        config: &Config,
+        circuit: Circuit<B>,
+        measured: &[(QubitIndex, usize)],
+    ) -> error::Result<Outcome<B, P>> {
+        let backend = match self.get(&config.simulator) {
+            Some(backend) => backend,
+            None => {
+                log::error!("unknown simulator: {}", config.simulator);
+                return Err(Error::new(
+                    ErrorKind::UnknownSimulator,
+                    format!(
+                        "unknown simulator: {}; valid values are: {}, {}",
+                        config.simulator,
+                        auto::AUTO,
+                        self.names().join(", ")
+                    ),
+                ));
+            }
+        };
+
+        log::info!("using {} simulator", backend.name());
+        let num_qubits = circuit.num_qubits;
+        let num_gates = circuit.num_gates();
+        let cregs = circuit.cregs.clone();
+        let cursor = ScheduleCursor::new(config);
+        let (duration, result) = profile!(backend.simulate(config, circuit, &cursor));
+        if cursor.num_left() > 0 {
+            log::warn!(
+                "the replayed schedule has {} picks left over",
+                cursor.num_left()
+            );
+        }
+        let FinalState {
+            densities,
+            clbits,
+            samples,
+        } = result?;
+
+        let (densities, counts) = if config.shots > 0 {
+            let mut rng = StdRng::seed_from_u64(config.seed);
+            let (densities, samples) = match samples {
+                Some(samples) => (densities, samples),
+                None => sampling::sample_densities(densities, config.shots, &mut rng),
+            };
+            let counts = sampling::counts(&samples, num_qubits, &cregs, measured, &clbits);
+            (densities, Some(counts))
+        } else {
+            (densities, None)
+        };
+
+        Ok(Outcome {
+            densities,
+            clbits,
+            counts,
+            schedule: cursor.into_recorded(),
+            stats: Stats {
+                simulator: backend.name(),
+                num_qubits,
+                num_gates,
+                duration,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::GateDefn;
+    use crate::types::{BasisIdx64, Weight};
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicU64;
+
+    type TestRegistry = Registry<BasisIdx64, AtomicU64, f64>;
+
+    /// Skips the gates and returns the initial state
+    struct IdentityBackend;
+
+    impl SimulatorBackend<BasisIdx64, AtomicU64, f64> for IdentityBackend {
+        fn name(&self) -> &'static str {
+            "identity"
+        }
+
+        fn aliases(&self) -> &'static [&'static str] {
+            &["seq"]
+        }
+
+        fn simulate(
+            &self,
+            _config: &Config,
+            circuit: Circuit<BasisIdx64>,
+            _cursor: &ScheduleCursor,
+        ) -> error::Result<FinalState<BasisIdx64, f64>> {
+            let initial = (BasisIdx64::zeros(), Weight::new(1.0, 0.0));
+            let densities: Densities<BasisIdx64, f64> = Box::new(std::iter::once(initial));
+            Ok((densities, vec![false; circuit.num_clbits()]).into())
+        }
+    }
+
+    fn circuit() -> Circuit<BasisIdx64> {
+        Circuit::from_gates(
+            2,
+            1,
+            [
+                GateDefn::Hadamard(0),
+                GateDefn::CX {
+                    control: 0,
+                    target: 1,
+                },
+            ],
+        )
+        .unwrap()
+    }
+
+    fn config(simulator: &str) -> Config {
+        Config {
+            simulator: simulator.to_string(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_get() {
+        let mut registry = TestRegistry::default();
+        assert_eq!(registry.get("seq").unwrap().name(), "sequential");
+        assert!(registry.get("identity").is_none());
+
+        registry.register(IdentityBackend);
+        assert_eq!(registry.get("seq").unwrap().name(), "identity");
+        assert_eq!(registry.get("par").unwrap().name(), "parallel");
+        assert_eq!(
+            registry.names(),
+            vec![
+                "sequential",
+                "parallel",
+                "dense",
+                "hybrid",
+                "mps",
+                "identity"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        let mut registry = TestRegistry::default();
+        let outcome = registry.run(&config("sequential"), circuit()).unwrap();
+        assert_eq!(outcome.densities.count(), 2);
+        assert_eq!(outcome.clbits, vec![false]);
+        assert_eq!(outcome.stats.simulator, "sequential");
+        assert_eq!(outcome.stats.num_qubits, 2);
+        assert_eq!(outcome.stats.num_gates, 2);
+
+        registry.register(IdentityBackend);
+        let outcome = registry.run(&config("identity"), circuit()).unwrap();
+        assert_eq!(outcome.densities.count(), 1);
+        assert_eq!(outcome.stats.simulator, "identity");
+
+        let err = registry.run(&config("qiskit"), circuit()).err().unwrap();
+        assert!(matches!(err.kind, ErrorKind::UnknownSimulator));
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let circuit = || {
+            Circuit::from_qasm(
+                r#"
+OPENQASM 2.0;
+include "qelib1.inc";
+qreg q[3];
+creg c[1];
+h q[0];
+cx q[0], q[1];
+measure q[0] -> c[0];
+if (c == 1) x q[2];
+h q[1];
+cx q[1], q[2];
+"#,
+            )
+            .unwrap()
+        };
+
+        let registry = TestRegistry::default();
+        for simulator in ["dense", "hybrid"] {
+            for seed in 0..4 {
+                let record = Config {
+                    seed,
+                    record_schedule: true,
+                    ..config(simulator)
+                };
+                let recorded = registry.run(&record, circuit()).unwrap();
+                let schedule = recorded.schedule.unwrap();
+                // one empty pick ends each of the segments
+                assert_eq!(
+                    schedule.picks.iter().filter(|pick| pick.is_empty()).count(),
+                    3
+                );
+                assert!(schedule.picks.last().unwrap().is_empty());
+                assert!(schedule
+                    .picks
+                    .windows(2)
+                    .all(|picks| !picks[0].is_empty() || !picks[1].is_empty()));
+
+                let replay = Config {
+                    seed,
+                    replayed_schedule: Some(schedule),
+                    ..config(simulator)
+                };
+                let replayed = registry.run(&replay, circuit()).unwrap();
+                assert_eq!(replayed.clbits, recorded.clbits);
+                assert!(replayed.schedule.is_none());
+                let recorded = recorded.densities.collect::<HashMap<_, _>>();
+                let replayed = replayed.densities.collect::<HashMap<_, _>>();
+                assert_eq!(replayed.len(), recorded.len());
+                for (bidx, weight) in &replayed {
+                    assert!((weight - recorded[bidx]).norm() < 1e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_shots() {
+        let bell = || {
+            Circuit::from_qasm(
+                r#"
+OPENQASM 2.0;
+include "qelib1.inc";
+qreg q[3];
+creg c[2];
+h q[0];
+cx q[0], q[2];
+measure q[0] -> c[0];
+measure q[2] -> c[1];
+"#,
+            )
+            .unwrap()
+        };
+
+        let registry = TestRegistry::default();
+        for simulator in ["sequential", "mps"] {
+            let config = Config {
+                shots: 1000,
+                seed: 7,
+                ..config(simulator)
+            };
+            let outcome = registry.run(&config, bell()).unwrap();
+            let counts = outcome.counts.unwrap();
+            assert_eq!(counts.keys().collect::<Vec<_>>(), vec!["00", "11"]);
+            assert_eq!(counts.values().sum::<usize>(), 1000);
+            assert!(counts["11"].abs_diff(500) < 50);
+            // the measurements are sampled, so the state is left entangled
+            assert_eq!(outcome.densities.count(), 2);
+
+            let again = registry.run(&config, bell()).unwrap();
+            assert_eq!(again.counts.unwrap(), counts);
+        }
+
+        let outcome = registry.run(&config("sequential"), bell()).unwrap();
+        assert!(outcome.counts.is_none());
+        assert_eq!(outcome.densities.count(), 1);
+
+        // the measurement of q[0] is read by the conditional
+        let teleport = Circuit::from_qasm(
+            r#"
+OPENQASM 2.0;
+include "qelib1.inc";
+qreg q[2];
+creg c[1];
+h q[0];
+measure q[0] -> c[0];
+if (c == 1) x q[1];
+"#,
+        )
+        .unwrap();
+        let config = Config {
+            shots: 10,
+            ..config("sequential")
+        };
+        let err = registry.run(&config, teleport).err().unwrap();
+        assert!(matches!(err.kind, ErrorKind::UnsupportedGate));
+        assert_eq!(err.location.map(|location| location.line), Some(7));
+    }
+}
@@ -1,19 +1,21 @@
 use log;
 
-use crate::circuit::{Circuit, Unitary};
+use crate::circuit::{self, Circuit, Unitary};
 use crate::config::Config;
-use crate::futhark::{self, FutharkVector};
-use crate::gate_scheduler;
+use crate::error;
+use crate::gate_scheduler::{self, Expansion, Feedback, ScheduleCursor};
+use crate::kernel::{self, Context, DenseVector};
 use crate::profile;
-use crate::types::{BasisIdx, Complex};
+use crate::simulator::{self, FinalState, SimulatorBackend};
+use crate::types::{AtomicBasisIdx, BasisIdx, Precision, QubitIndex, Real, Weight};
 use crate::utility;
 
 use super::Compactifiable;
 
-type State = Vec<Complex>;
+type State<P> = Vec<Weight<P>>;
 
-impl<B: BasisIdx> Compactifiable<B> for State {
-    fn compactify(self) -> Box<dyn Iterator<Item = (B, Complex)>> {
+impl<B: BasisIdx, P: Precision> Compactifiable<B, P> for State<P> {
+    fn compactify(self) -> Box<dyn Iterator<Item = (B, Weight<P>)>> {
         Box::new(self.into_iter().enumerate().filter_map(|(i, v)| {
             if utility::is_nonzero(v) {
                 Some((B::from_idx(i), v))
@@ -24,44 +26,112 @@ impl<B: BasisIdx> Compactifiable<B> for State {
     }
 }
 
-pub fn run<B: BasisIdx>(config: &Config, circuit: Circuit<B>) -> State {
-    let dim = 1 << circuit.num_qubits;
+/// Simulates in a dense vector, with the dense kernels
+pub struct Backend;
 
-    let mut gate_scheduler = gate_scheduler::create_gate_scheduler(config, &circuit);
+impl<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> SimulatorBackend<B, AB, P> for Backend {
+    fn name(&self) -> &'static str {
+        "dense"
+    }
 
-    let mut num_gates_visited = 0;
+    fn simulate(
+        &self,
+        config: &Config,
+        circuit: Circuit<B>,
+        cursor: &ScheduleCursor,
+    ) -> error::Result<FinalState<B, P>> {
+        simulator::warn_single_precision_kernels::<P>();
+        Ok(simulator::compactify(run::<B, P>(config, circuit, cursor)?))
+    }
+}
 
-    let futhark_context = futhark::create_context();
+pub fn run<B: BasisIdx, P: Precision>(
+    config: &Config,
+    circuit: Circuit<B>,
+    cursor: &ScheduleCursor,
+) -> error::Result<(State<P>, Vec<bool>)> {
+    let dim = 1 << circuit.num_qubits;
+
+    let kernel_context = kernel::create_context();
 
-    let mut state = FutharkVector::new(
-        &futhark_context,
+    let state = DenseVector::new(
+        &kernel_context,
         (0..dim)
             .map(|i| {
                 if i == 0 {
-                    Complex::new(1.0, 0.0)
+                    Weight::new(P::one(), P::zero())
                 } else {
-                    Complex::new(0.0, 0.0)
+                    Weight::new(P::zero(), P::zero())
                 }
             })
-            .collect::<Vec<Complex>>(),
+            .collect::<Vec<Weight<P>>>(),
     );
 
-    let (duration, _) = profile!(loop {
+    let mut num_gates_visited = 0;
+
+    let (duration, result) = profile!(simulator::run_dynamic(
+        config,
+        circuit,
+        state,
+        |segment, state| {
+            num_gates_visited += segment.num_gates();
+            run_segment(&kernel_context, config, segment, state, cursor)
+        },
+        |state, qubit| probability_of_one::<B, P>(&kernel_context, state, qubit),
+    ));
+    let (state, clbits) = result?;
+
+    let result = state.into_vec();
+    let num_nonzeros = result.iter().filter(|&&v| utility::is_nonzero(v)).count();
+
+    log::info!(
+        "gate: {:<2} density: ????????? nonzero: {:>10}, time: {}s",
+        num_gates_visited,
+        num_nonzeros,
+        duration.as_secs_f32()
+    );
+    Ok((result, clbits))
+}
+
+fn run_segment<'a, B: BasisIdx, P: Precision>(
+    kernel_context: &'a Context,
+    config: &Config,
+    circuit: Circuit<B>,
+    state: DenseVector<'a, P>,
+    cursor: &ScheduleCursor,
+) -> error::Result<DenseVector<'a, P>> {
+    let mut gate_scheduler =
+        gate_scheduler::create_fusing_gate_scheduler(config, &circuit, cursor)?;
+
+    let mut num_gates_visited = 0;
+    let mut state = state;
+
+    loop {
         let these_gates = gate_scheduler.pick_next_gates();
         if these_gates.is_empty() {
             break;
         }
-        assert!(these_gates.len() == 1);
-        let gate = &circuit.gates[these_gates[0]];
-
-        log::debug!("applying gate: {:?}", gate);
+        let unitary = circuit::fuse(
+            these_gates
+                .iter()
+                .map(|gi| {
+                    let gate = &circuit.gates[*gi];
+                    log::debug!("applying gate: {:?}", gate);
+                    gate.unitary()
+                })
+                .collect(),
+        );
 
-        let num_gates_visited_here = 1;
+        let num_gates_visited_here = these_gates.len();
 
-        let (duration, new_state) =
-            profile!(futhark::apply_vec(&futhark_context, state, gate.unitary()));
+        let (duration, new_state) = profile!(kernel::apply_vec(kernel_context, state, unitary));
+        gate_scheduler.observe(&Feedback {
+            num_nonzeros: 1 << circuit.num_qubits,
+            expansion: Expansion::Dense,
+            duration,
+        });
 
-        println!(
+        log::debug!(
             "gate: {:<3} density: ????????? nonzero: ??????????? hop:  {} dense(gpu) time: {:.4}s",
             num_gates_visited,
             num_gates_visited_here,
@@ -70,18 +140,27 @@ pub fn run<B: BasisIdx>(config: &Config, circuit: Circuit<B>) -> State {
 
         state = new_state;
         num_gates_visited += num_gates_visited_here;
-    });
+    }
 
-    let result = state.into_vec();
-    let num_nonzeros = result.iter().filter(|&&v| utility::is_nonzero(v)).count();
+    Ok(state)
+}
 
-    println!(
-        "gate: {:<2} density: ????????? nonzero: {:>10}\n time: {}s",
-        num_gates_visited,
-        num_nonzeros,
-        duration.as_secs_f32()
-    );
-    result
+fn probability_of_one<'a, B: BasisIdx, P: Precision>(
+    kernel_context: &'a Context,
+    state: DenseVector<'a, P>,
+    qubit: QubitIndex,
+) -> (DenseVector<'a, P>, Real) {
+    let vec = state.into_vec();
+    let probability: P = vec
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| B::from_idx(*idx).get(qubit))
+        .map(|(_, weight)| weight.norm_sqr())
+        .sum();
+    (
+        DenseVector::new(kernel_context, vec),
+        probability.into_real(),
+    )
 }
 
 #[cfg(test)]
@@ -93,21 +172,33 @@ mod tests {
 
     #[test]
     fn test_run() {
-        let config = Config {
+        check_run(Config {
             disable_gate_fusion: true,
             ..Config::default()
-        };
+        });
+    }
+
+    #[test]
+    fn test_run_fused() {
+        check_run(Config {
+            fusion_max_qubits: 3,
+            ..Config::default()
+        });
+    }
+
+    fn check_run(config: Config) {
         let source = fs::read_to_string(test_case!("adder_n10.qasm")).unwrap();
         let program = parser::parse_program(&source).unwrap();
         let circuit = Circuit::<BasisIdx64>::new(program).unwrap();
 
-        let result = run(&config, circuit);
+        let (result, _) =
+            run::<BasisIdx64, f32>(&config, circuit, &ScheduleCursor::default()).unwrap();
         let expected = fs::read_to_string(test_case!("adder_n10_expected.txt"))
             .unwrap()
             .lines()
             .map(|line| {
                 let (bidx, w) = line.split_once(" ").unwrap();
-                let c = Complex::from_str(w).unwrap();
+                let c = Weight::<f32>::from_str(w).unwrap();
                 let bidx = BasisIdx64::from_str(bidx).unwrap();
 
                 (bidx, c)
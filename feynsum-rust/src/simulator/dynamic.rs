@@ -0,0 +1,168 @@
+use std::mem;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::circuit::{Circuit, Gate, GateDefn};
+use crate::config::Config;
+use crate::error;
+use crate::types::{BasisIdx, QubitIndex, Real};
+
+/// Simulates a circuit that may contain measurements, resets and classically
+/// controlled gates.
+///
+/// The circuit is split into measurement-free segments, which are simulated
+/// by `run_segment`. At a measurement, the outcome is drawn from
+/// `probability_of_one` using the seeded RNG, and the state is collapsed and
+/// renormalized by running a `GateDefn::Project` segment. Returns the final
+/// state and the classical bits, or the first error of `run_segment`.
+pub fn run_dynamic<B: BasisIdx, S>(
+    config: &Config,
+    circuit: Circuit<B>,
+    mut state: S,
+    mut run_segment: impl FnMut(Circuit<B>, S) -> error::Result<S>,
+    mut probability_of_one: impl FnMut(S, QubitIndex) -> (S, Real),
+) -> error::Result<(S, Vec<bool>)> {
+    let num_qubits = circuit.num_qubits;
+    let mut clbits = vec![false; circuit.num_clbits()];
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut segment = Vec::<Gate<B>>::new();
+
+    let into_circuit = |gates| Circuit {
+        num_qubits,
+        cregs: vec![],
+        gates,
+    };
+
+    for gate in circuit.gates {
+        if !gate.defn.is_dynamic() {
+            segment.push(gate);
+            continue;
+        }
+
+        let defn = match resolve_conditional(gate.defn, &clbits) {
+            Some(defn) => defn,
+            None => continue,
+        };
+
+        let (target, clbit, reset) = match defn {
+            GateDefn::Measure { target, clbit } => (target, clbit, false),
+            GateDefn::Reset(target) => (target, None, true),
+            defn => {
+                segment.push(Gate::new(defn));
+                continue;
+            }
+        };
+
+        if !segment.is_empty() {
+            state = run_segment(into_circuit(mem::take(&mut segment)), state)?;
+        }
+
+        let (new_state, probability) = probability_of_one(state, target);
+        let outcome = rng.gen::<Real>() < probability;
+        let probability = if outcome {
+            probability
+        } else {
+            1.0 - probability
+        };
+        log::info!(
+            "measured qubit {}: {} with probability {}",
+            target,
+            outcome as u8,
+            probability
+        );
+
+        let mut gates = vec![Gate::new(GateDefn::Project {
+            target,
+            outcome,
+            scale: 1.0 / probability.sqrt(),
+        })];
+        if reset && outcome {
+            gates.push(Gate::new(GateDefn::X(target)));
+        }
+        state = run_segment(into_circuit(gates), new_state)?;
+
+        if let Some(clbit) = clbit {
+            clbits[clbit] = outcome;
+        }
+    }
+
+    if !segment.is_empty() {
+        state = run_segment(into_circuit(segment), state)?;
+    }
+
+    Ok((state, clbits))
+}
+
+/// Strips the conditions of `defn`, or returns `None` if one does not hold
+fn resolve_conditional(defn: GateDefn, clbits: &[bool]) -> Option<GateDefn> {
+    match defn {
+        GateDefn::Conditional {
+            clbits: bits,
+            value,
+            defn,
+        } => {
+            let actual = bits
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (i, &bit)| acc | ((clbits[bit] as u64) << i));
+            if actual == value {
+                resolve_conditional(*defn, clbits)
+            } else {
+                None
+            }
+        }
+        defn => Some(defn),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gate_scheduler::ScheduleCursor;
+    use crate::parser;
+    use crate::simulator::sequential_simulator;
+    use crate::types::BasisIdx64;
+
+    #[test]
+    fn test_teleportation() {
+        // teleports |1> from q[0] to q[2]; the corrections make q[2] end up in
+        // |1> whatever the outcomes
+        let source = r#"
+        OPENQASM 2.0;
+        include "qelib1.inc";
+        qreg q[3];
+        creg m0[1];
+        creg m1[1];
+        creg out[1];
+        x q[0];
+        h q[1];
+        cx q[1], q[2];
+        cx q[0], q[1];
+        h q[0];
+        measure q[0] -> m0[0];
+        measure q[1] -> m1[0];
+        if (m1 == 1) x q[2];
+        if (m0 == 1) z q[2];
+        measure q[2] -> out[0];
+        reset q[0];
+        reset q[1];
+        "#;
+
+        for seed in 0..8 {
+            let config = Config {
+                seed,
+                ..Config::default()
+            };
+            let circuit =
+                Circuit::<BasisIdx64>::new(parser::parse_program(source).unwrap()).unwrap();
+            let (state, clbits) =
+                sequential_simulator::run::<_, f64>(&config, circuit, &ScheduleCursor::default())
+                    .unwrap();
+
+            assert!(clbits[2]);
+            assert_eq!(state.num_nonzeros(), 1);
+            let weight = state.get(&BasisIdx64::from_idx(0b100)).unwrap();
+            assert!((weight.norm() - 1.0).abs() < 1e-10);
+        }
+    }
+}
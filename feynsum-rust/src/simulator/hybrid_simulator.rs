@@ -1,41 +1,101 @@
 use crate::circuit::Circuit;
 use crate::config::Config;
-use crate::futhark::{self, FutharkVector};
-use crate::gate_scheduler;
+use crate::error;
+use crate::gate_scheduler::{self, Feedback, ScheduleCursor};
+use crate::kernel::{self, Context, DenseVector};
 use crate::profile;
-use crate::types::{AtomicBasisIdx, BasisIdx, Complex};
+use crate::simulator::{self, Densities, FinalState, SimulatorBackend};
+use crate::types::{AtomicBasisIdx, BasisIdx, Precision, QubitIndex, Real, Weight};
 
 use super::parallel_simulator::SparseStateTable;
 
 mod state_expander;
 use state_expander::ExpandResult;
 
-pub enum State<'a, B: BasisIdx, AB: AtomicBasisIdx<B>> {
-    Sparse(SparseStateTable<B, AB>),
-    Dense(FutharkVector<'a>),
+pub enum State<'a, B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> {
+    Sparse(SparseStateTable<B, AB, P>),
+    Dense(DenseVector<'a, P>),
 }
 
-pub fn run<B: BasisIdx, AB: AtomicBasisIdx<B>>(
+/// Simulates sparse states like the parallel simulator, and dense ones with
+/// the dense kernels
+pub struct Backend;
+
+impl<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> SimulatorBackend<B, AB, P> for Backend {
+    fn name(&self) -> &'static str {
+        "hybrid"
+    }
+
+    fn simulate(
+        &self,
+        config: &Config,
+        circuit: Circuit<B>,
+        cursor: &ScheduleCursor,
+    ) -> error::Result<FinalState<B, P>> {
+        simulator::warn_single_precision_kernels::<P>();
+        Ok(run::<B, AB, P>(config, circuit, cursor)?.into())
+    }
+}
+
+pub fn run<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
     config: &Config,
     circuit: Circuit<B>,
-) -> Box<dyn Iterator<Item = (B, Complex)>> {
+    cursor: &ScheduleCursor,
+) -> error::Result<(Densities<B, P>, Vec<bool>)> {
     let num_qubits = circuit.num_qubits;
 
-    let mut gate_scheduler = gate_scheduler::create_gate_scheduler(config, &circuit);
-
-    let mut num_gates_visited = 0;
+    let kernel_context = kernel::create_context();
 
-    let futhark_context = futhark::create_context();
-
-    let mut state = State::Sparse(SparseStateTable::<B, AB>::singleton(
+    let state = State::Sparse(SparseStateTable::<B, AB, P>::singleton(
         num_qubits,
         B::zeros(),
-        Complex::new(1.0, 0.0),
+        Weight::new(P::one(), P::zero()),
         config.maxload,
         1,
     )); // initial state
-    let mut num_nonzeros = 1;
-    let mut prev_num_nonzeros = 1;
+
+    let (state, clbits) = simulator::run_dynamic(
+        config,
+        circuit,
+        state,
+        |segment, state| run_segment(&kernel_context, config, segment, state, cursor),
+        |state, qubit| probability_of_one(&kernel_context, state, qubit),
+    )?;
+
+    let result = match state {
+        State::Sparse(table) => Box::new(table.nonzeros().into_iter()),
+        State::Dense(dense_vec) => Box::new(
+            dense_vec
+                .into_vec()
+                .into_iter()
+                .enumerate()
+                .map(|(idx, weight)| (B::from_idx(idx), weight)),
+        ) as Box<dyn Iterator<Item = (B, Weight<P>)>>,
+    };
+    Ok((result, clbits))
+}
+
+fn run_segment<'a, B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
+    kernel_context: &'a Context,
+    config: &Config,
+    circuit: Circuit<B>,
+    state: State<'a, B, AB, P>,
+    cursor: &ScheduleCursor,
+) -> error::Result<State<'a, B, AB, P>> {
+    let num_qubits = circuit.num_qubits;
+
+    let mut gate_scheduler =
+        gate_scheduler::create_fusing_gate_scheduler(config, &circuit, cursor)?;
+
+    let mut num_gates_visited = 0;
+
+    let mut num_nonzeros = match &state {
+        State::Sparse(table) => table.num_nonzeros(),
+        State::Dense(dense_vec) => kernel::num_nonzeros(kernel_context, dense_vec),
+    };
+    let mut num_gate_apps = 0;
+    let mut prev_num_nonzeros = num_nonzeros;
+    let mut state = state;
 
     log::info!("starting gate application loop.");
 
@@ -44,23 +104,26 @@ pub fn run<B: BasisIdx, AB: AtomicBasisIdx<B>>(
         if these_gates.is_empty() {
             break;
         }
-        assert!(these_gates.len() == 1);
-        let gate = &circuit.gates[these_gates[0]];
+        let gates = these_gates
+            .iter()
+            .map(|gi| &circuit.gates[*gi])
+            .collect::<Vec<_>>();
 
-        log::debug!("applying gate: {:?}", gate);
+        log::debug!("applying gates: {:?}", gates);
 
-        let num_gates_visited_here = 1;
+        let num_gates_visited_here = gates.len();
 
         let (
             duration,
             ExpandResult {
                 state: new_state,
                 num_nonzeros: new_num_nonzeros,
+                num_gate_apps: num_gate_apps_here,
                 method,
             },
         ) = profile!(state_expander::expand(
-            &futhark_context,
-            gate,
+            kernel_context,
+            gates,
             config,
             num_qubits,
             num_nonzeros,
@@ -68,42 +131,84 @@ pub fn run<B: BasisIdx, AB: AtomicBasisIdx<B>>(
             state,
         ));
 
-        println!(
-            "gate: {:<3} density: ????????? nonzero: ??????????? hop:  {} {} time: {:.4}s",
-            // FIXME: Print density and nonzero
+        gate_scheduler.observe(&Feedback {
+            num_nonzeros: new_num_nonzeros,
+            expansion: (&method).into(),
+            duration,
+        });
+
+        let density = simulator::density(num_qubits, num_nonzeros);
+
+        let throughput = (num_gate_apps_here as Real / 1e6) / duration.as_secs_f64();
+
+        log::debug!(
+            "gate: {:<3} density: {:.8} nonzero: {:>10} hop: {:<2} {} time: {:.4}s throughput: {:.2}M gates/s",
             num_gates_visited,
+            density,
+            num_nonzeros,
             num_gates_visited_here,
             method,
             duration.as_secs_f32(),
+            throughput
         );
 
         num_gates_visited += num_gates_visited_here;
+        num_gate_apps += num_gate_apps_here;
         state = new_state;
         prev_num_nonzeros = num_nonzeros;
         num_nonzeros = new_num_nonzeros;
     });
 
-    println!(
-        "gate: {:<2} density: ????????? nonzero: ???????????\ngate app count: ???, time: {}s",
+    let final_density = simulator::density(num_qubits, num_nonzeros);
+
+    log::info!(
+        "gate: {:<2} density: {:.8} nonzero: {:>10}, gate app count: {}, time: {}s",
         num_gates_visited,
+        final_density,
+        num_nonzeros,
+        num_gate_apps,
         duration.as_secs_f32()
     );
 
+    Ok(state)
+}
+
+fn probability_of_one<'a, B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
+    kernel_context: &'a Context,
+    state: State<'a, B, AB, P>,
+    qubit: QubitIndex,
+) -> (State<'a, B, AB, P>, Real) {
     match state {
-        State::Sparse(table) => Box::new(table.nonzeros().into_iter()),
-        State::Dense(futhark_vec) => Box::new(
-            futhark_vec
-                .into_vec()
+        State::Sparse(table) => {
+            let probability: P = table
+                .nonzeros()
                 .into_iter()
+                .filter(|(bidx, _)| bidx.get(qubit))
+                .map(|(_, weight)| weight.norm_sqr())
+                .sum();
+            (State::Sparse(table), probability.into_real())
+        }
+        State::Dense(dense_vec) => {
+            let vec = dense_vec.into_vec();
+            let probability: P = vec
+                .iter()
                 .enumerate()
-                .map(|(idx, weight)| (B::from_idx(idx), weight)),
-        ) as Box<dyn Iterator<Item = (B, Complex)>>,
+                .filter(|(idx, _)| B::from_idx(*idx).get(qubit))
+                .map(|(_, weight)| weight.norm_sqr())
+                .sum();
+            (
+                State::Dense(DenseVector::new(kernel_context, vec)),
+                probability.into_real(),
+            )
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::circuit::GateDefn;
+    use crate::gate_scheduler::GateSchedulingPolicy;
     use crate::{circuit::Circuit, parser, test_case, types::BasisIdx64, utility};
     use std::collections::HashMap;
     use std::sync::atomic::AtomicU64;
@@ -111,21 +216,88 @@ mod tests {
 
     #[test]
     fn test_run() {
+        check_run(Config {
+            disable_gate_fusion: true,
+            ..Config::default()
+        });
+    }
+
+    #[test]
+    fn test_run_fused() {
+        check_run(Config {
+            fusion_max_qubits: 3,
+            ..Config::default()
+        });
+    }
+
+    #[test]
+    fn test_run_low_dense_threshold() {
+        // below the default sparse threshold, which is then clamped to it
+        check_run(Config {
+            dense_threshold: 0.01,
+            ..Config::default()
+        });
+    }
+
+    #[test]
+    fn test_return_to_sparse() {
         let config = Config {
+            gate_scheduling_policy: GateSchedulingPolicy::Naive,
             disable_gate_fusion: true,
             ..Config::default()
         };
+        // the second layer of Hadamards undoes the first, so the dense state
+        // is back to one nonzero when x is applied
+        let circuit = Circuit::<BasisIdx64>::from_gates(
+            5,
+            0,
+            (0..5)
+                .chain(0..5)
+                .map(GateDefn::Hadamard)
+                .chain([GateDefn::X(0)]),
+        )
+        .unwrap();
+
+        let kernel_context = kernel::create_context();
+        let state = State::Sparse(SparseStateTable::<BasisIdx64, AtomicU64, f32>::singleton(
+            5,
+            BasisIdx64::zeros(),
+            Weight::new(1.0, 0.0),
+            config.maxload,
+            1,
+        ));
+        match run_segment(
+            &kernel_context,
+            &config,
+            circuit,
+            state,
+            &ScheduleCursor::default(),
+        )
+        .unwrap()
+        {
+            State::Sparse(table) => {
+                assert_eq!(table.num_nonzeros(), 1);
+                let weight = table.get(&BasisIdx64::from_idx(1)).unwrap();
+                assert!((weight - Weight::new(1.0, 0.0)).norm() < 1e-6);
+            }
+            State::Dense(_) => panic!("the state stays dense"),
+        }
+    }
+
+    fn check_run(config: Config) {
         let source = fs::read_to_string(test_case!("basis_change_n3.qasm")).unwrap();
         let program = parser::parse_program(&source).unwrap();
         let circuit = Circuit::<BasisIdx64>::new(program).unwrap();
 
-        let result = run::<BasisIdx64, AtomicU64>(&config, circuit);
+        let (result, _) =
+            run::<BasisIdx64, AtomicU64, f32>(&config, circuit, &ScheduleCursor::default())
+                .unwrap();
         let expected = fs::read_to_string(test_case!("basis_change_n3_expected.txt"))
             .unwrap()
             .lines()
             .map(|line| {
                 let (bidx, w) = line.split_once(" ").unwrap();
-                let c = Complex::from_str(w).unwrap();
+                let c = Weight::<f32>::from_str(w).unwrap();
                 let bidx = BasisIdx64::from_str(bidx).unwrap();
 
                 (bidx, c)
@@ -1,13 +1,14 @@
 use std::fmt::{self, Display, Formatter};
 
-use crate::circuit::{Gate, Unitary};
+use crate::circuit::{self, Gate, Unitary};
 use crate::config::Config;
-use crate::futhark::{self, Context, FutharkVector};
+use crate::gate_scheduler::Expansion;
+use crate::kernel::{self, Context, DenseVector};
 use crate::simulator::parallel_simulator::SparseStateTable;
-use crate::types::{AtomicBasisIdx, BasisIdx, Complex};
+use crate::types::{AtomicBasisIdx, BasisIdx, Precision, Weight};
 
-use super::super::expected_cost;
 use super::super::parallel_simulator;
+use super::super::{density, expected_cost};
 use super::State;
 
 pub enum ExpandMethod {
@@ -23,101 +24,149 @@ impl Display for ExpandMethod {
         }
     }
 }
-pub struct ExpandResult<'a, B: BasisIdx, AB: AtomicBasisIdx<B>> {
-    pub state: State<'a, B, AB>,
+
+impl From<&ExpandMethod> for Expansion {
+    fn from(method: &ExpandMethod) -> Self {
+        match method {
+            ExpandMethod::Sparse => Expansion::Sparse,
+            ExpandMethod::Dense => Expansion::Dense,
+        }
+    }
+}
+
+pub struct ExpandResult<'a, B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> {
+    pub state: State<'a, B, AB, P>,
     pub num_nonzeros: usize,
+    pub num_gate_apps: usize,
     pub method: ExpandMethod,
 }
 
-pub fn expand<'a, B: BasisIdx, AB: AtomicBasisIdx<B>>(
-    futhark_ctx: &'a Context,
-    gate: &Gate<B>,
+/// Applies `gates` in order, fusing them into one unitary on the dense path.
+/// A sparse state goes dense once it is expected to reach the dense
+/// threshold, but a dense state only goes back to sparse once it falls below
+/// the lower sparse threshold, so that a state around the dense threshold
+/// does not go back and forth. A sparse threshold above the dense one is
+/// taken to be the dense one.
+pub fn expand<'a, B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
+    kernel_ctx: &'a Context,
+    gates: Vec<&Gate<B>>,
     config: &Config,
     num_qubits: usize,
     num_nonzeros: usize,
     prev_num_nonzeros: usize,
-    state: State<'a, B, AB>,
-) -> ExpandResult<'a, B, AB> {
-    let (expected_density, expected_num_nonzeros) =
-        expected_cost(num_qubits, num_nonzeros, prev_num_nonzeros);
+    state: State<'a, B, AB, P>,
+) -> ExpandResult<'a, B, AB, P> {
+    let sparse_threshold = config.sparse_threshold.min(config.dense_threshold);
+    let (expected_density, _) = expected_cost(num_qubits, num_nonzeros, prev_num_nonzeros);
+    let is_dense = match &state {
+        State::Sparse(_) => expected_density >= config.dense_threshold,
+        State::Dense(_) => {
+            expected_density >= config.dense_threshold
+                || density(num_qubits, num_nonzeros) >= sparse_threshold
+        }
+    };
 
-    if expected_density < config.dense_threshold {
-        expand_sparse(gate, config, num_qubits, expected_num_nonzeros, state)
+    if !is_dense {
+        expand_sparse(
+            gates,
+            config,
+            num_qubits,
+            num_nonzeros,
+            prev_num_nonzeros,
+            state,
+        )
     } else {
-        expand_dense(futhark_ctx, gate, num_qubits, state)
+        expand_dense(kernel_ctx, gates, num_qubits, state)
     }
 }
 
-fn expand_sparse<'a, B: BasisIdx, AB: AtomicBasisIdx<B>>(
-    gate: &Gate<B>,
+/// Pushing a basis index through several branching gates at once follows
+/// every path separately, so a run of gates is expanded one gate at a time
+fn expand_sparse<'a, B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
+    gates: Vec<&Gate<B>>,
     config: &Config,
     num_qubits: usize,
-    expected_num_nonzeros: usize,
-    state: State<'a, B, AB>,
-) -> ExpandResult<'a, B, AB> {
-    let parallel_simulator_state = match state {
+    num_nonzeros: usize,
+    prev_num_nonzeros: usize,
+    state: State<'a, B, AB, P>,
+) -> ExpandResult<'a, B, AB, P> {
+    let mut state = match state {
         State::Sparse(table) => parallel_simulator::State::Sparse(table),
-        State::Dense(futhark_vec) => parallel_simulator::State::Sparse(
-            SparseStateTable::new_from_dense(num_qubits, futhark_vec.into_vec()),
-        ),
+        State::Dense(dense_vec) => {
+            log::debug!("returning to a sparse state of {} nonzeros", num_nonzeros);
+            parallel_simulator::State::Sparse(SparseStateTable::new_from_dense(
+                num_qubits,
+                config.maxload,
+                num_nonzeros,
+                dense_vec.into_vec(),
+            ))
+        }
     };
-    let parallel_simulator::ExpandResult {
-        state,
-        num_nonzeros,
-        method: parallel_simulator_expand_method,
-        ..
-    } = parallel_simulator::expand_sparse(
-        vec![gate],
-        num_qubits,
-        config,
-        expected_num_nonzeros,
-        &parallel_simulator_state,
-    );
+    let mut num_nonzeros = num_nonzeros;
+    let mut prev_num_nonzeros = prev_num_nonzeros;
+    let mut num_gate_apps = 0;
+    let mut method = ExpandMethod::Sparse;
+
+    for gate in gates {
+        let (_, expected_num_nonzeros) = expected_cost(num_qubits, num_nonzeros, prev_num_nonzeros);
+        let result = parallel_simulator::expand_sparse(
+            vec![gate],
+            num_qubits,
+            config,
+            expected_num_nonzeros,
+            &state,
+        );
+
+        state = result.state;
+        prev_num_nonzeros = num_nonzeros;
+        num_nonzeros = result.num_nonzeros;
+        num_gate_apps += result.num_gate_apps;
+        method = match result.method {
+            parallel_simulator::ExpandMethod::Sparse => ExpandMethod::Sparse,
+            parallel_simulator::ExpandMethod::PushDense
+            | parallel_simulator::ExpandMethod::PullDense => ExpandMethod::Dense,
+        };
+    }
 
     let state = match state {
         parallel_simulator::State::Sparse(table) => State::Sparse(table),
         _ => unreachable!("Result of parallel_simulator::expand_sparse should be sparse"),
     };
 
-    let method = match parallel_simulator_expand_method {
-        parallel_simulator::ExpandMethod::Sparse => ExpandMethod::Sparse,
-        parallel_simulator::ExpandMethod::PushDense
-        | parallel_simulator::ExpandMethod::PullDense => ExpandMethod::Dense,
-    };
-
     ExpandResult {
         state,
         num_nonzeros,
+        num_gate_apps,
         method,
     }
 }
 
-fn expand_dense<'a, B: BasisIdx, AB: AtomicBasisIdx<B>>(
-    futhark_ctx: &'a Context,
-    gate: &Gate<B>,
+fn expand_dense<'a, B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
+    kernel_ctx: &'a Context,
+    gates: Vec<&Gate<B>>,
     num_qubits: usize,
-    state: State<'a, B, AB>,
-) -> ExpandResult<'a, B, AB> {
-    let futhark_vec = create_futhark_vec(futhark_ctx, num_qubits, state);
-
-    let new_state = futhark::apply_vec(futhark_ctx, futhark_vec, gate.unitary());
+    state: State<'a, B, AB, P>,
+) -> ExpandResult<'a, B, AB, P> {
+    let dense_vec = create_dense_vec(kernel_ctx, num_qubits, state);
 
-    let num_nonzeros = 1 << num_qubits;
-    // FIXME: Temporarily we use num_nonzeros = 1 << num_qubits, just to treat that the new state is dense
-    // We need a way to compute num_nonzeros of the new state without actually retrieving the data from the GPU
+    let num_gate_apps = gates.len() << num_qubits;
+    let unitary = circuit::fuse(gates.iter().map(|gate| gate.unitary()).collect());
+    let new_state = kernel::apply_vec(kernel_ctx, dense_vec, unitary);
+    let num_nonzeros = kernel::num_nonzeros(kernel_ctx, &new_state);
 
     ExpandResult {
         state: State::Dense(new_state),
         num_nonzeros,
+        num_gate_apps,
         method: ExpandMethod::Dense,
     }
 }
 
-fn create_futhark_vec<'a, B: BasisIdx, AB: AtomicBasisIdx<B>>(
+fn create_dense_vec<'a, B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
     ctx: &'a Context,
     num_qubits: usize,
-    state: State<'a, B, AB>,
-) -> FutharkVector<'a> {
+    state: State<'a, B, AB, P>,
+) -> DenseVector<'a, P> {
     match state {
         State::Sparse(table) => {
             let dim = 1 << num_qubits;
@@ -128,12 +177,12 @@ fn create_futhark_vec<'a, B: BasisIdx, AB: AtomicBasisIdx<B>>(
                     let bidx = B::from_idx(i);
                     match table.get(&bidx) {
                         Some(weight) => weight,
-                        None => Complex::new(0.0, 0.0),
+                        None => Weight::new(P::zero(), P::zero()),
                     }
                 })
                 .collect::<Vec<_>>();
-            FutharkVector::new(ctx, vec)
+            DenseVector::new(ctx, vec)
         }
-        State::Dense(futhark_vec) => futhark_vec,
+        State::Dense(dense_vec) => dense_vec,
     }
 }
@@ -4,26 +4,87 @@ mod sparse_table;
 mod state;
 mod state_expander;
 
-use crate::circuit::Circuit;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::circuit::{Circuit, GateDefn};
 use crate::config::Config;
-use crate::gate_scheduler;
+use crate::error::{self, Error, ErrorKind};
+use crate::gate_scheduler::{self, Feedback, ScheduleCursor};
 use crate::profile;
-use crate::types::{BasisIdx, Real};
+use crate::simulator::{self, FinalState, SimulatorBackend};
+use crate::types::{AtomicBasisIdx, BasisIdx, Precision, Real};
 
 pub use state::State;
 pub use state_expander::{expand, ExpandResult};
 
-pub fn run<B: BasisIdx>(config: &Config, circuit: Circuit<B>) -> State<B> {
+/// Simulates in a matrix product state until the bond dimension grows too
+/// large
+pub struct Backend;
+
+impl<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> SimulatorBackend<B, AB, P> for Backend {
+    fn name(&self) -> &'static str {
+        "mps"
+    }
+
+    fn simulate(
+        &self,
+        config: &Config,
+        circuit: Circuit<B>,
+        cursor: &ScheduleCursor,
+    ) -> error::Result<FinalState<B, P>> {
+        let (state, clbits) = run::<B, P>(config, circuit, cursor)?;
+        let samples = if config.shots > 0 {
+            state.sample(config.shots, &mut StdRng::seed_from_u64(config.seed))
+        } else {
+            None
+        };
+        Ok(FinalState {
+            samples,
+            ..simulator::compactify((state, clbits))
+        })
+    }
+}
+
+pub fn run<B: BasisIdx, P: Precision>(
+    config: &Config,
+    mut circuit: Circuit<B>,
+    cursor: &ScheduleCursor,
+) -> error::Result<(State<B, P>, Vec<bool>)> {
+    // the sites only take gates on one or two qubits
+    let circuit = circuit.decompose();
+    if let Some(gate) = circuit.gates.iter().find(|gate| gate.touches.len() > 2) {
+        log::error!("gate {:?} does not decompose to two qubits", gate.defn);
+        return Err(Error::new(
+            ErrorKind::UnsupportedGate,
+            "gates on more than two qubits are not supported by the MPS simulator",
+        )
+        .at(gate.location));
+    }
+
+    if let Some(gate) = circuit.dynamic_gate() {
+        let name = match gate.defn {
+            GateDefn::Measure { .. } => "measure",
+            GateDefn::Reset(_) => "reset",
+            _ => "if",
+        };
+        return Err(Error::new(
+            ErrorKind::UnsupportedGate,
+            "measurement, reset and classical control are not supported by the MPS simulator",
+        )
+        .at(gate.location)
+        .in_gate(Some(name)));
+    }
+
+    let num_clbits = circuit.num_clbits();
     let num_gates = circuit.num_gates();
     let num_qubits = circuit.num_qubits;
     let mut num_nonzeros = 1;
-    let mut prev_num_nonzeros = 1;
 
     let mut num_gates_visited = 0;
     let mut state = State::MPS(mps::MPSState::singleton(num_qubits));
     let mut num_gate_apps = 0;
 
-    let mut gate_scheduler = gate_scheduler::create_gate_scheduler(config, &circuit);
+    let mut gate_scheduler = gate_scheduler::create_gate_scheduler(config, &circuit, cursor)?;
 
     let (duration, _) = profile!(loop {
         let these_gates = gate_scheduler
@@ -49,22 +110,19 @@ pub fn run<B: BasisIdx>(config: &Config, circuit: Circuit<B>) -> State<B> {
                 method,
                 ..
             },
-        ) = profile!(expand::<B>(
-            these_gates,
-            config,
-            num_qubits,
-            prev_num_nonzeros,
-            state
-        ));
-
-        let density = {
-            let max_num_states: u64 = 1 << num_qubits;
-            num_nonzeros as Real / max_num_states as Real
-        };
+        ) = profile!(expand::<B, P>(these_gates, config, num_qubits, state));
+
+        gate_scheduler.observe(&Feedback {
+            num_nonzeros: new_num_nonzeros,
+            expansion: (&method).into(),
+            duration,
+        });
+
+        let density = simulator::density(num_qubits, num_nonzeros);
 
-        let throughput = (num_gate_apps_here as Real / 1e6) / duration.as_secs_f32();
+        let throughput = (num_gate_apps_here as Real / 1e6) / duration.as_secs_f64();
 
-        println!(
+        log::debug!(
             "gate: {:<3} density: {:.8} nonzero: {:>10} hop: {:<2} {} time: {:.4}s throughput: {:.2}M gates/s",
             num_gates_visited,
             density,
@@ -77,18 +135,14 @@ pub fn run<B: BasisIdx>(config: &Config, circuit: Circuit<B>) -> State<B> {
 
         num_gates_visited += num_gates_visited_here;
         num_gate_apps += num_gate_apps_here;
-        prev_num_nonzeros = num_nonzeros;
         num_nonzeros = new_num_nonzeros;
         state = new_state;
     });
 
-    let final_density = {
-        let max_num_states: u64 = 1 << num_qubits;
-        num_nonzeros as f64 / max_num_states as f64
-    };
+    let final_density = simulator::density(num_qubits, num_nonzeros);
 
-    println!(
-        "gate: {:<2} density: {:.8} nonzero: {:>10}\ngate app count: {}, time: {}s",
+    log::info!(
+        "gate: {:<2} density: {:.8} nonzero: {:>10}, gate app count: {}, time: {}s",
         num_gates_visited,
         final_density,
         num_nonzeros,
@@ -97,14 +151,14 @@ pub fn run<B: BasisIdx>(config: &Config, circuit: Circuit<B>) -> State<B> {
     );
 
     assert!(num_gates_visited >= num_gates);
-    state
+    Ok((state, vec![false; num_clbits]))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::parser;
-    use crate::types::BasisIdx64;
+    use crate::types::{BasisIdx, BasisIdx64};
 
     #[test]
     fn test_run() {
@@ -154,8 +208,74 @@ h q0[29];
         )
         .unwrap();
 
-        let _state = run::<BasisIdx64>(&config, circuit);
+        let (_state, _) =
+            run::<BasisIdx64, f32>(&config, circuit, &ScheduleCursor::default()).unwrap();
 
         println!("{:?}", _state);
     }
+
+    #[test]
+    fn test_run_wide_gates() {
+        let config = Config::default();
+        let circuit = Circuit::from_gates(
+            4,
+            0,
+            [
+                GateDefn::X(0),
+                GateDefn::X(1),
+                GateDefn::CCX {
+                    control1: 0,
+                    control2: 1,
+                    target: 2,
+                },
+                GateDefn::CSwap {
+                    control: 2,
+                    target1: 0,
+                    target2: 3,
+                },
+            ],
+        )
+        .unwrap();
+
+        let (state, _) =
+            run::<BasisIdx64, f64>(&config, circuit, &ScheduleCursor::default()).unwrap();
+        let nonzeros = simulator::compactify((state, vec![]))
+            .densities
+            .filter(|(_, weight)| weight.norm_sqr() > 1e-6)
+            .collect::<Vec<_>>();
+        assert_eq!(nonzeros.len(), 1);
+        assert_eq!(nonzeros[0].0.as_idx(), 0b1110);
+    }
+
+    #[test]
+    fn test_sample() {
+        let config = Config::default();
+        let circuit = Circuit::from_gates(
+            3,
+            0,
+            [
+                GateDefn::Hadamard(0),
+                GateDefn::CX {
+                    control: 0,
+                    target: 1,
+                },
+                GateDefn::CX {
+                    control: 1,
+                    target: 2,
+                },
+                GateDefn::X(0),
+            ],
+        )
+        .unwrap();
+
+        let (state, _) =
+            run::<BasisIdx64, f64>(&config, circuit, &ScheduleCursor::default()).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let samples = state.sample(1000, &mut rng).unwrap();
+        assert!(samples
+            .iter()
+            .all(|bidx| bidx.as_idx() == 0b001 || bidx.as_idx() == 0b110));
+        let num_ones = samples.iter().filter(|bidx| bidx.get(0)).count();
+        assert!((num_ones as f64 / 1000.0 - 0.5).abs() < 0.05);
+    }
 }
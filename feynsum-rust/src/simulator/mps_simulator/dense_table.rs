@@ -1,18 +1,17 @@
-use crate::types::{BasisIdx, Complex};
-use crate::simulator::mps_simulator::state::Table;
+use crate::types::{BasisIdx, Precision, Weight};
 use crate::utility;
 
 #[derive(Debug)]
-pub struct DenseStateTable {
-    pub array: Vec<Complex>,
+pub struct DenseStateTable<P: Precision> {
+    pub array: Vec<Weight<P>>,
 }
 
-impl DenseStateTable {
+impl<P: Precision> DenseStateTable<P> {
     pub fn new(num_qubits: usize) -> Self {
         let capacity = 1 << num_qubits;
 
         Self {
-            array: vec![Complex::new(0.0, 0.0); capacity],
+            array: vec![Weight::new(P::zero(), P::zero()); capacity],
         }
     }
 
@@ -23,15 +22,7 @@ impl DenseStateTable {
             .count()
     }
 
-    pub fn get<B: BasisIdx>(&self, bidx: &B) -> Option<&Complex> {
+    pub fn get<B: BasisIdx>(&self, bidx: &B) -> Option<&Weight<P>> {
         self.array.get(bidx.as_idx())
     }
 }
-
-impl<B: BasisIdx> Table<B> for DenseStateTable {
-    fn put(&mut self, bidx: B, weight: Complex) {
-        let idx = bidx.as_idx();
-
-        self.array[idx] += weight;
-    }
-}
@@ -1,36 +1,37 @@
 use crate::utility::is_zero;
 use crate::{config::Config, simulator::Compactifiable};
 use nalgebra::*;
+use rand::Rng;
 
 use super::state::State;
 
 use crate::{
     circuit::{Gate, GateDefn, Unitary, UnitaryMatrix},
-    types::{BasisIdx, Complex},
+    types::{BasisIdx, Precision, Real, Weight},
 };
 
+// The |0> and |1> components of a site
+type SiteTensors<P> = (DMatrix<Weight<P>>, DMatrix<Weight<P>>);
+
 // TODO: we should be able to switch to/from this and the other representations
 #[derive(Debug)]
-pub struct MPSState {
+pub struct MPSState<P: Precision> {
     // One component in the pair for Left |0> and Right |1> respectively.
-    pub tensors: Vec<(DMatrix<Complex>, DMatrix<Complex>)>,
+    pub tensors: Vec<SiteTensors<P>>,
     // Bond dimensions between sites: (dL, dR)
     pub bond_dims: Vec<(usize, usize)>,
     // Number of sites (qubits)
     pub n_sites: usize,
 }
 
-impl MPSState {
+impl<P: Precision> MPSState<P> {
     /// Create a new MPS (0,...,0) state
     pub fn singleton(num_qubits: usize) -> Self {
-        let (tensors, bond_dims): (
-            Vec<(DMatrix<Complex>, DMatrix<Complex>)>,
-            Vec<(usize, usize)>,
-        ) = (0..num_qubits)
+        let (tensors, bond_dims): (Vec<SiteTensors<P>>, Vec<(usize, usize)>) = (0..num_qubits)
             .map(|_| {
                 // For each qubit, create the |0> and |1> matrices. Initialise the left/right bond dimensions as (1,1)
-                let tensor_0 = DMatrix::from_element(1, 1, Complex::new(1.0, 0.0)); // |0>
-                let tensor_1 = DMatrix::from_element(1, 1, Complex::new(0.0, 0.0)); // |1>
+                let tensor_0 = DMatrix::from_element(1, 1, Weight::new(P::one(), P::zero())); // |0>
+                let tensor_1 = DMatrix::from_element(1, 1, Weight::new(P::zero(), P::zero())); // |1>
 
                 ((tensor_0, tensor_1), (1, 1))
             })
@@ -45,17 +46,17 @@ impl MPSState {
 
     pub fn from_nonzeros<B: BasisIdx>(
         config: &Config,
-        prev_state: State<B>,
+        prev_state: State<B, P>,
         num_qubits: usize,
     ) -> Self {
         // Construct dense state psi from the non-zero basis indices
         // NB: requires expensive 2^n array construction!
-        let mut psi: Vec<Complex> = vec![Complex::new(0.0, 0.0); 1 << num_qubits];
+        let mut psi: Vec<Weight<P>> = vec![Weight::new(P::zero(), P::zero()); 1 << num_qubits];
         for (bidx, ampl) in prev_state.compactify() {
             psi[bidx.as_idx()] += ampl;
         }
 
-        let mut tensors: Vec<(DMatrix<Complex>, DMatrix<Complex>)> = Vec::with_capacity(num_qubits);
+        let mut tensors: Vec<SiteTensors<P>> = Vec::with_capacity(num_qubits);
         let mut bond_dims: Vec<(usize, usize)> = Vec::with_capacity(num_qubits);
 
         // Process left-most site
@@ -108,7 +109,7 @@ impl MPSState {
             bond_left = chi;
 
             let new_len = chi * bond_right;
-            let mut new_psi: Vec<Complex> = vec![Complex::new(0.0, 0.0); new_len];
+            let mut new_psi: Vec<Weight<P>> = vec![Weight::new(P::zero(), P::zero()); new_len];
 
             for row in 0..chi {
                 for col in 0..bond_right {
@@ -153,15 +154,15 @@ impl MPSState {
     }
 
     /// Returns the basis indices which have non-zero amplitude from the MPS state.
-    pub fn nonzeros<B: BasisIdx>(&self) -> Vec<(B, Complex)> {
+    pub fn nonzeros<B: BasisIdx>(&self) -> Vec<(B, Weight<P>)> {
         let n = self.n_sites;
         if n == 0 {
             // No qubits => empty state
-            return vec![(B::zeros(), Complex::new(1.0, 0.0))];
+            return vec![(B::zeros(), Weight::new(P::one(), P::zero()))];
         }
 
         // Maintain a list of partial expansions: (bitstring_so_far, bond_vector)
-        let mut partials: Vec<(B, DVector<Complex>)> = Vec::new();
+        let mut partials: Vec<(B, DVector<Weight<P>>)> = Vec::new();
 
         // Starting from the left-most state, we add the partial contributions to the vec
         {
@@ -180,7 +181,7 @@ impl MPSState {
 
         // Iterate through the rest of the sites
         for site in 1..n {
-            let mut next_partials: Vec<(B, DVector<Complex>)> = Vec::new();
+            let mut next_partials: Vec<(B, DVector<Weight<P>>)> = Vec::new();
             let (tensor_0, tensor_1) = &self.tensors[site];
             let (d_in, d_out) = self.bond_dims[site];
 
@@ -221,17 +222,61 @@ impl MPSState {
         partials
             .iter()
             .map(|(bits, bond_vec)| (bits.clone(), bond_vec[0]))
-            .collect::<Vec<(B, Complex)>>()
+            .collect::<Vec<(B, Weight<P>)>>()
     }
 
-    pub fn num_nonzeros<B: BasisIdx>(&self) -> usize {
-        self.nonzeros::<B>().iter().count()
+    /// Draws `shots` basis states with the probabilities of the state, without
+    /// listing its nonzeros. The bit of each site is drawn conditioned on those
+    /// of the sites before it, from the contraction of the partial state with
+    /// the environment of the sites after it.
+    pub fn sample<B: BasisIdx>(&self, shots: usize, rng: &mut impl Rng) -> Vec<B> {
+        let n = self.n_sites;
+        let one = DMatrix::from_element(1, 1, Weight::new(P::one(), P::zero()));
+
+        // environments[site] contracts the sites from `site` on with their
+        // conjugates: the sum over both bits of A E A^dagger
+        let mut environments = vec![one.clone(); n + 1];
+        for site in (0..n).rev() {
+            let (tensor_0, tensor_1) = &self.tensors[site];
+            let next = &environments[site + 1];
+            environments[site] =
+                tensor_0 * next * tensor_0.adjoint() + tensor_1 * next * tensor_1.adjoint();
+        }
+
+        // the squared norm of the states that extend `partial`
+        let probability = |partial: &DMatrix<Weight<P>>, environment: &DMatrix<Weight<P>>| {
+            (partial * environment * partial.adjoint())[(0, 0)]
+                .re
+                .into_real()
+        };
+
+        (0..shots)
+            .map(|_| {
+                let mut bits = B::zeros();
+                // a row vector over the right bond of the last site drawn
+                let mut partial = one.clone();
+                for site in 0..n {
+                    let (tensor_0, tensor_1) = &self.tensors[site];
+                    let partial_0 = &partial * tensor_0;
+                    let partial_1 = &partial * tensor_1;
+                    let p_0 = probability(&partial_0, &environments[site + 1]);
+                    let p_1 = probability(&partial_1, &environments[site + 1]);
+                    if rng.gen::<Real>() * (p_0 + p_1) < p_1 {
+                        bits = bits.set(site);
+                        partial = partial_1;
+                    } else {
+                        partial = partial_0;
+                    }
+                }
+                bits
+            })
+            .collect()
     }
 
     /// Apply the unitary matrix of a gate to a chosen site
     fn apply_single_qubit_gate(&mut self, gate: &UnitaryMatrix, site: usize) {
         let (tensor_0, tensor_1) = &mut self.tensors[site];
-        let mat = &gate.mat;
+        let mat = gate.mat.map(P::from_complex);
 
         // Apply the gate to the |0> and |1> components of the site
         let new_tensor_0 = tensor_0.clone() * mat[(0, 0)] + tensor_1.clone() * mat[(0, 1)];
@@ -280,7 +325,7 @@ impl MPSState {
             let i = col / 2; // in [0..2)
             let j = col % 2; // in [0..2)
 
-            let mut val = Complex::new(0.0, 0.0);
+            let mut val = Weight::new(P::zero(), P::zero());
             for alpha_middle in 0..bond_middle {
                 // i in {tensor1_0, tensor1_1}, j in {tensor2_0, tensor2_1}
                 match (i, j) {
@@ -307,12 +352,15 @@ impl MPSState {
         });
 
         // Apply gate
-        combined = combined * gate.mat.clone();
+        combined = combined * gate.mat.map(P::from_complex);
 
         // Turn (bond_left * bond_right, 4) matrix back into (bond_left * 2, bond_right * 2) otherwise we will
         // permanently fuse the site by the SVD
-        let mut expanded =
-            DMatrix::from_element(bond_left * 2, 2 * bond_right, Complex::new(0.0, 0.0));
+        let mut expanded = DMatrix::from_element(
+            bond_left * 2,
+            2 * bond_right,
+            Weight::new(P::zero(), P::zero()),
+        );
 
         for new_row in 0..(bond_left * 2) {
             let alpha_left = new_row / 2; // 0..(bond_left-1)
@@ -462,6 +510,9 @@ impl MPSState {
             | GateDefn::U { target: qindex, .. } => {
                 self.apply_single_qubit_gate(&gate.unitary(), qindex)
             }
+            GateDefn::Unitary { ref matrix, .. } if matrix.qubit_indices.len() == 1 => {
+                self.apply_single_qubit_gate(&gate.unitary(), matrix.qubit_indices[0])
+            }
             GateDefn::CZ { control, target }
             | GateDefn::CX { control, target }
             | GateDefn::CPhase {
@@ -512,12 +563,50 @@ impl MPSState {
                     );
                 }
             }
-            // We don't handle >= 3 qubit gates, they must have been decomposed already
-            GateDefn::CSwap { .. } | GateDefn::CCX { .. } | GateDefn::Other { .. } => {
-                log::warn!(
-                    "Skipping gate {:?} as 3-qubit gates are not implemented by the MPS simulator.",
-                    gate.defn
-                );
+            GateDefn::CH { .. }
+            | GateDefn::CRX { .. }
+            | GateDefn::CRY { .. }
+            | GateDefn::CRZ { .. }
+            | GateDefn::CU { .. }
+            | GateDefn::CY { .. }
+            | GateDefn::ECR { .. }
+            | GateDefn::ISwap { .. }
+            | GateDefn::RXX { .. }
+            | GateDefn::RYY { .. }
+            | GateDefn::RZZ { .. }
+            // larger unitaries have been decomposed already
+            | GateDefn::Unitary { .. } => {
+                let mut mat = gate.unitary();
+                let (first, second) = (mat.qubit_indices[0], mat.qubit_indices[1]);
+                // unitary() has the first qubit as the least significant bit,
+                // while the left site must be the most significant one
+                let (left_site, right_site) = if first < second {
+                    mat.mat.swap_rows(1, 2);
+                    mat.mat.swap_columns(1, 2);
+                    (first, second)
+                } else {
+                    (second, first)
+                };
+                // the state multiplies the matrix from the left
+                mat.mat.transpose_mut();
+
+                if left_site + 1 == right_site {
+                    self.apply_two_qubit_gate(config, &mat, left_site, right_site);
+                } else {
+                    self.apply_two_qubit_gate_nonadjacent::<B>(config, &mat, left_site, right_site);
+                }
+            }
+            GateDefn::CSwap { .. } | GateDefn::CCX { .. } | GateDefn::Controlled { .. } => {
+                unreachable!("{:?} must be decomposed by mps_simulator::run", gate.defn)
+            }
+            GateDefn::Measure { .. }
+            | GateDefn::Reset(_)
+            | GateDefn::Conditional { .. }
+            | GateDefn::Project { .. } => {
+                unimplemented!("{:?} is not supported by the MPS simulator", gate.defn)
+            }
+            GateDefn::Parameterized { .. } => {
+                unreachable!("{:?} must be bound before simulation", gate.defn)
             }
         };
     }
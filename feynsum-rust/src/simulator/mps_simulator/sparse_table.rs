@@ -1,17 +1,15 @@
 use std::collections::HashMap;
 
-use crate::types::{BasisIdx, Complex};
+use crate::types::{BasisIdx, Precision, Weight};
 use crate::utility;
 
-use super::state::Table;
-
 #[derive(Debug)]
-pub struct SparseStateTable<B: BasisIdx> {
-    pub table: HashMap<B, Complex>,
+pub struct SparseStateTable<B: BasisIdx, P: Precision> {
+    pub table: HashMap<B, Weight<P>>,
 }
 
-impl<B: BasisIdx> SparseStateTable<B> {
-    pub fn singleton(bidx: B, weight: Complex) -> Self {
+impl<B: BasisIdx, P: Precision> SparseStateTable<B, P> {
+    pub fn singleton(bidx: B, weight: Weight<P>) -> Self {
         Self {
             table: HashMap::from([(bidx, weight)]),
         }
@@ -30,16 +28,7 @@ impl<B: BasisIdx> SparseStateTable<B> {
             .count()
     }
 
-    pub fn get(&self, bidx: &B) -> Option<&Complex> {
+    pub fn get(&self, bidx: &B) -> Option<&Weight<P>> {
         self.table.get(bidx)
     }
 }
-
-impl<B: BasisIdx> Table<B> for SparseStateTable<B> {
-    fn put(&mut self, bidx: B, weight: Complex) {
-        self.table
-            .entry(bidx)
-            .and_modify(|w| *w += weight)
-            .or_insert(weight);
-    }
-}
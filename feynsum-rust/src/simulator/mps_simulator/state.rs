@@ -1,34 +1,35 @@
+use rand::Rng;
+
 use super::{dense_table::DenseStateTable, mps::MPSState, sparse_table::SparseStateTable};
 use crate::utility;
 
 use super::super::Compactifiable;
-use crate::types::{BasisIdx, Complex};
+use crate::types::{BasisIdx, Precision, Weight};
 
 #[derive(Debug)]
-pub enum State<B: BasisIdx> {
-    MPS(MPSState),
-    Sparse(SparseStateTable<B>),
-    Dense(DenseStateTable),
-}
-
-pub trait Table<B: BasisIdx> {
-    fn put(&mut self, bidx: B, weight: Complex);
+pub enum State<B: BasisIdx, P: Precision> {
+    MPS(MPSState<P>),
+    Sparse(SparseStateTable<B, P>),
+    Dense(DenseStateTable<P>),
 }
 
-impl<B: BasisIdx> State<B> {
-    pub fn num_nonzeros(&self) -> usize {
+impl<B: BasisIdx, P: Precision> State<B, P> {
+    /// Draws `shots` basis states from an MPS state, see `MPSState::sample`.
+    /// The tables are sampled from their densities instead.
+    pub fn sample(&self, shots: usize, rng: &mut impl Rng) -> Option<Vec<B>> {
         match self {
-            State::MPS(mps) => mps.num_nonzeros::<B>(),
-            State::Sparse(table) => table.num_nonzeros(),
-            State::Dense(table) => table.num_nonzeros(),
+            State::MPS(mps) => Some(mps.sample(shots, rng)),
+            State::Sparse(_) | State::Dense(_) => None,
         }
     }
 }
 
-impl<B: BasisIdx> Compactifiable<B> for State<B> {
-    fn compactify(self) -> Box<dyn Iterator<Item = (B, Complex)>> {
+impl<B: BasisIdx, P: Precision> Compactifiable<B, P> for State<B, P> {
+    fn compactify(self) -> Box<dyn Iterator<Item = (B, Weight<P>)>> {
         match self {
-            State::MPS(mps) => Box::new(mps.nonzeros().into_iter()),
+            // contracted only once the densities are read, which they need
+            // not be when the state is sampled
+            State::MPS(mps) => Box::new(std::iter::once(mps).flat_map(|mps| mps.nonzeros())),
             State::Sparse(table) => Box::new(
                 table
                     .table
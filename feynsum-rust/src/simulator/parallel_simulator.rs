@@ -5,34 +5,82 @@ pub use state::State;
 
 use crate::circuit::Circuit;
 use crate::config::Config;
-use crate::gate_scheduler;
+use crate::error;
+use crate::gate_scheduler::{self, Feedback, ScheduleCursor};
 use crate::profile;
-use crate::types::{AtomicBasisIdx, BasisIdx, Complex, Real};
+use crate::simulator::{self, FinalState, SimulatorBackend};
+use crate::types::{AtomicBasisIdx, BasisIdx, Precision, Real, Weight};
 
 pub use state::SparseStateTable;
 pub use state_expander::expand_sparse;
 pub use state_expander::{ExpandMethod, ExpandResult};
 
-pub fn run<B: BasisIdx, AB: AtomicBasisIdx<B>>(
+/// Simulates on the current rayon thread pool, in a sparse table until it
+/// gets dense
+pub struct Backend;
+
+impl<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> SimulatorBackend<B, AB, P> for Backend {
+    fn name(&self) -> &'static str {
+        "parallel"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["par"]
+    }
+
+    fn simulate(
+        &self,
+        config: &Config,
+        circuit: Circuit<B>,
+        cursor: &ScheduleCursor,
+    ) -> error::Result<FinalState<B, P>> {
+        Ok(simulator::compactify(run::<B, AB, P>(
+            config, circuit, cursor,
+        )?))
+    }
+}
+
+pub fn run<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
     config: &Config,
     circuit: Circuit<B>,
-) -> State<B, AB> {
-    let num_gates = circuit.num_gates();
-    let num_qubits = circuit.num_qubits;
-
-    let mut num_gates_visited = 0;
-    let mut state = State::Sparse(SparseStateTable::singleton(
-        num_qubits,
+    cursor: &ScheduleCursor,
+) -> error::Result<(State<B, AB, P>, Vec<bool>)> {
+    let state = State::Sparse(SparseStateTable::singleton(
+        circuit.num_qubits,
         B::zeros(),
-        Complex::new(1.0, 0.0),
+        Weight::new(P::one(), P::zero()),
         config.maxload,
         1,
     )); // initial state
-    let mut num_nonzeros = 1;
+
+    simulator::run_dynamic(
+        config,
+        circuit,
+        state,
+        |segment, state| run_segment(config, segment, state, cursor),
+        |state, qubit| {
+            let probability = state.probability_of_one(qubit);
+            (state, probability.into_real())
+        },
+    )
+}
+
+fn run_segment<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
+    config: &Config,
+    circuit: Circuit<B>,
+    state: State<B, AB, P>,
+    cursor: &ScheduleCursor,
+) -> error::Result<State<B, AB, P>> {
+    let num_gates = circuit.num_gates();
+    let num_qubits = circuit.num_qubits;
+
+    let mut num_gates_visited = 0;
+    let mut state = state;
+    let mut num_nonzeros = state.num_nonzeros();
     let mut num_gate_apps = 0;
-    let mut prev_num_nonzeros = 1;
+    let mut prev_num_nonzeros = num_nonzeros;
 
-    let mut gate_scheduler = gate_scheduler::create_gate_scheduler(config, &circuit);
+    let mut gate_scheduler = gate_scheduler::create_gate_scheduler(config, &circuit, cursor)?;
 
     log::info!("starting gate application loop.");
 
@@ -67,14 +115,17 @@ pub fn run<B: BasisIdx, AB: AtomicBasisIdx<B>>(
             state
         ));
 
-        let density = {
-            let max_num_states: u64 = 1 << num_qubits;
-            num_nonzeros as Real / max_num_states as Real
-        };
+        gate_scheduler.observe(&Feedback {
+            num_nonzeros: new_num_nonzeros,
+            expansion: (&method).into(),
+            duration,
+        });
 
-        let throughput = (num_gate_apps_here as Real / 1e6) / duration.as_secs_f32();
+        let density = simulator::density(num_qubits, num_nonzeros);
 
-        println!(
+        let throughput = (num_gate_apps_here as Real / 1e6) / duration.as_secs_f64();
+
+        log::debug!(
             "gate: {:<3} density: {:.8} nonzero: {:>10} hop: {:<2} {} time: {:.4}s throughput: {:.2}M gates/s",
             num_gates_visited,
             density,
@@ -92,13 +143,10 @@ pub fn run<B: BasisIdx, AB: AtomicBasisIdx<B>>(
         state = new_state;
     });
 
-    let final_density = {
-        let max_num_states: u64 = 1 << num_qubits;
-        num_nonzeros as f64 / max_num_states as f64
-    };
+    let final_density = simulator::density(num_qubits, num_nonzeros);
 
-    println!(
-        "gate: {:<2} density: {:.8} nonzero: {:>10}\ngate app count: {}, time: {}s",
+    log::info!(
+        "gate: {:<2} density: {:.8} nonzero: {:>10}, gate app count: {}, time: {}s",
         num_gates_visited,
         final_density,
         num_nonzeros,
@@ -107,7 +155,7 @@ pub fn run<B: BasisIdx, AB: AtomicBasisIdx<B>>(
     );
 
     assert!(num_gates_visited >= num_gates);
-    state
+    Ok(state)
 }
 
 #[cfg(test)]
@@ -216,7 +264,9 @@ h q0[28];
         )
         .unwrap();
 
-        let state = run::<BasisIdx64, AtomicU64>(&config, circuit);
+        let (state, _) =
+            run::<BasisIdx64, AtomicU64, f64>(&config, circuit, &ScheduleCursor::default())
+                .unwrap();
 
         //        println!("{:?}", state);
 
@@ -234,7 +284,7 @@ h q0[28];
         assert!(abs_diff_eq!(
             nonzero_field_1.re,
             constants::RECP_SQRT_2,
-            epsilon = 0.0001
+            epsilon = 1e-10
         ));
 
         let nonzero_field_2 = table
@@ -243,7 +293,39 @@ h q0[28];
         assert!(abs_diff_eq!(
             nonzero_field_2.re,
             -constants::RECP_SQRT_2,
-            epsilon = 0.0001
+            epsilon = 1e-10
         ));
     }
+
+    #[test]
+    fn test_precision() {
+        // thousands of rotations that undo each other, as in deep variational
+        // circuits
+        let mut source =
+            String::from("OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\nh q[0];\n");
+        for _ in 0..1000 {
+            source.push_str(
+                "rx(0.3) q[1];\ncrz(0.7) q[0], q[1];\ncrz(-0.7) q[0], q[1];\nrx(-0.3) q[1];\n",
+            );
+        }
+        let config = Config::default();
+        let circuit =
+            || Circuit::<BasisIdx64>::new(parser::parse_program(&source).unwrap()).unwrap();
+
+        let (state, _) =
+            run::<BasisIdx64, AtomicU64, f64>(&config, circuit(), &ScheduleCursor::default())
+                .unwrap();
+        for idx in [0b00, 0b01] {
+            let weight = state.get(&BasisIdx64::from_idx(idx)).unwrap();
+            assert!((weight - constants::RECP_SQRT_2).norm() < 1e-10);
+        }
+
+        let (state, _) =
+            run::<BasisIdx64, AtomicU64, f32>(&config, circuit(), &ScheduleCursor::default())
+                .unwrap();
+        for idx in [0b00, 0b01] {
+            let weight = state.get(&BasisIdx64::from_idx(idx)).unwrap();
+            assert!((weight - constants::RECP_SQRT_2 as f32).norm() < 1e-3);
+        }
+    }
 }
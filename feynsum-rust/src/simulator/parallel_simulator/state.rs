@@ -1,9 +1,9 @@
 use std::convert::Infallible;
 use std::marker::PhantomData;
 
-use std::sync::atomic::Ordering;
+use rayon::prelude::*;
 
-use crate::types::{AtomicBasisIdx, BasisIdx, Complex};
+use crate::types::{AtomicBasisIdx, AtomicWeight, BasisIdx, Precision, QubitIndex, Weight};
 use crate::utility;
 
 mod dense_state_table;
@@ -15,16 +15,16 @@ pub use sparse_state_table::SparseStateTable;
 use super::super::Compactifiable;
 
 //#[derive(Debug)]
-pub enum State<B: BasisIdx, AB: AtomicBasisIdx<B>> {
-    Sparse(SparseStateTable<B, AB>),
-    Dense(DenseStateTable),
+pub enum State<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> {
+    Sparse(SparseStateTable<B, AB, P>),
+    Dense(DenseStateTable<P>),
     // Used to avoid a compiler error that says B is not used.  Refer to
     // https://github.com/rust-lang/rust/issues/23246 for more details.
     #[allow(dead_code)]
     Never(Infallible, PhantomData<B>),
 }
 
-impl<B: BasisIdx, AB: AtomicBasisIdx<B>> State<B, AB> {
+impl<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> State<B, AB, P> {
     pub fn num_nonzeros(&self) -> usize {
         match self {
             State::Sparse(table) => table.num_nonzeros(),
@@ -33,22 +33,42 @@ impl<B: BasisIdx, AB: AtomicBasisIdx<B>> State<B, AB> {
         }
     }
 
-    pub fn get(&self, bidx: &B) -> Option<Complex> {
+    pub fn get(&self, bidx: &B) -> Option<Weight<P>> {
         match self {
             State::Sparse(table) => table.get(bidx),
             State::Dense(table) => table.get(bidx),
             _ => unreachable!(),
         }
     }
+
+    /// Probability of measuring 1 on `qubit`
+    pub fn probability_of_one(&self, qubit: QubitIndex) -> P {
+        match self {
+            State::Sparse(table) => table
+                .nonzeros()
+                .into_par_iter()
+                .filter(|(bidx, _)| bidx.get(qubit))
+                .map(|(_, weight)| weight.norm_sqr())
+                .sum(),
+            State::Dense(table) => table
+                .array
+                .par_iter()
+                .enumerate()
+                .filter(|(idx, _)| B::from_idx(*idx).get(qubit))
+                .map(|(_, v)| v.load().norm_sqr())
+                .sum(),
+            _ => unreachable!(),
+        }
+    }
 }
 
-impl<B: BasisIdx, AB: AtomicBasisIdx<B>> Compactifiable<B> for State<B, AB> {
-    fn compactify(self) -> Box<dyn Iterator<Item = (B, Complex)>> {
+impl<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> Compactifiable<B, P> for State<B, AB, P> {
+    fn compactify(self) -> Box<dyn Iterator<Item = (B, Weight<P>)>> {
         match self {
             State::Sparse(table) => Box::new(table.nonzeros().into_iter()),
             State::Dense(table) => {
                 Box::new(table.array.into_iter().enumerate().filter_map(|(idx, v)| {
-                    let weight = utility::unpack_complex(v.load(Ordering::Relaxed));
+                    let weight = v.load();
                     if utility::is_nonzero(weight) {
                         Some((B::from_idx(idx), weight))
                     } else {
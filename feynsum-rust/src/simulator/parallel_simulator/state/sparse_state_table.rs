@@ -2,33 +2,31 @@ use rayon::prelude::*;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
-use crate::types::{AtomicBasisIdx, AtomicComplex, AtomicReal, BasisIdx, Complex, Real};
+use crate::types::{AtomicBasisIdx, AtomicWeight, BasisIdx, Precision, Real, Weight};
 use crate::utility;
 
-use std::sync::atomic::Ordering;
-
 fn calculate_hash<T: Hash>(t: &T) -> u64 {
     let mut s = DefaultHasher::new();
     t.hash(&mut s);
     s.finish()
 }
 
-pub struct SparseStateTable<B: BasisIdx, AB: AtomicBasisIdx<B>> {
+pub struct SparseStateTable<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> {
     pub keys: Vec<AB>,
-    pub weights: Vec<AtomicComplex>,
+    pub weights: Vec<P::AtomicWeight>,
     num_qubits: usize,
     empty_key: B,
 }
 
-impl<B: BasisIdx, AB: AtomicBasisIdx<B>> SparseStateTable<B, AB> {
+impl<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> SparseStateTable<B, AB, P> {
     fn new_with_capacity(num_qubits: usize, capacity: usize) -> Self {
         let keys: Vec<AB> = (0..capacity)
             .into_par_iter()
             .map(|_i| AB::empty_key(num_qubits))
             .collect();
-        let weights: Vec<AtomicComplex> = (0..capacity)
+        let weights: Vec<P::AtomicWeight> = (0..capacity)
             .into_par_iter()
-            .map(|_i| (AtomicReal::new(0.0), AtomicReal::new(0.0)))
+            .map(|_i| P::AtomicWeight::zero())
             .collect();
         Self {
             keys,
@@ -41,23 +39,26 @@ impl<B: BasisIdx, AB: AtomicBasisIdx<B>> SparseStateTable<B, AB> {
         let capacity = (1.1 * (1.0 / maxload) * (expected_num_nonzeros as Real)).ceil() as usize;
         Self::new_with_capacity(num_qubits, capacity)
     }
-
-    pub fn new_from_dense(num_qubits: usize, dense_array: Vec<Complex>) -> Self {
-        // FIXME: Optimize this
-        let capacity = dense_array.len();
-        assert!(capacity == 1 << num_qubits);
-        let table = Self::new_with_capacity(num_qubits, capacity);
-        for (i, v) in dense_array.into_iter().enumerate() {
-            if utility::is_nonzero(v) {
-                table.force_insert_unique(B::from_idx(i), v);
-            }
-        }
+    /// The nonzeros of `dense_array`, of which there are `num_nonzeros`
+    pub fn new_from_dense(
+        num_qubits: usize,
+        maxload: Real,
+        num_nonzeros: usize,
+        dense_array: Vec<Weight<P>>,
+    ) -> Self {
+        assert!(dense_array.len() == 1 << num_qubits);
+        let table = Self::new(num_qubits, maxload, num_nonzeros.max(1));
+        dense_array
+            .into_par_iter()
+            .enumerate()
+            .filter(|(_, v)| utility::is_nonzero(*v))
+            .for_each(|(i, v)| table.force_insert_unique(B::from_idx(i), v));
         table
     }
     pub fn singleton(
         num_qubits: usize,
         bidx: B,
-        weight: Complex,
+        weight: Weight<P>,
         maxload: Real,
         expected_num_nonzeros: usize,
     ) -> Self {
@@ -78,22 +79,17 @@ impl<B: BasisIdx, AB: AtomicBasisIdx<B>> SparseStateTable<B, AB> {
             })
             .count()
     }
-    fn put_value_at(&self, i: usize, v: Complex) {
-        self.weights[i].0.fetch_add(v.re, Ordering::SeqCst);
-        self.weights[i].1.fetch_add(v.im, Ordering::SeqCst);
+    fn put_value_at(&self, i: usize, v: Weight<P>) {
+        self.weights[i].add(v);
     }
-    fn put_value_at_nonatomic(&self, i: usize, v: Complex) {
+    fn put_value_at_nonatomic(&self, i: usize, v: Weight<P>) {
         let v0 = self.get_value_at(i);
-        self.weights[i].0.store(v0.re + v.re, Ordering::Relaxed);
-        self.weights[i].1.store(v0.im + v.im, Ordering::Relaxed);
+        self.weights[i].store(v0 + v);
     }
-    pub fn get_value_at(&self, i: usize) -> Complex {
-        Complex::new(
-            self.weights[i].0.load(Ordering::Relaxed),
-            self.weights[i].1.load(Ordering::Relaxed),
-        )
+    pub fn get_value_at(&self, i: usize) -> Weight<P> {
+        self.weights[i].load()
     }
-    fn force_insert_unique(&self, x: B, v: Complex) {
+    fn force_insert_unique(&self, x: B, v: Weight<P>) {
         let n = self.keys.len();
         let start = calculate_hash(&x) as usize % n;
         let mut i: usize = start;
@@ -117,7 +113,7 @@ impl<B: BasisIdx, AB: AtomicBasisIdx<B>> SparseStateTable<B, AB> {
         &self,
         tolerance: usize,
         x: B,
-        v: Complex,
+        v: Weight<P>,
     ) -> Result<(), ()> {
         let n = self.keys.len();
         let mut i: usize = calculate_hash(&x) as usize % n;
@@ -150,7 +146,7 @@ impl<B: BasisIdx, AB: AtomicBasisIdx<B>> SparseStateTable<B, AB> {
         }
         Ok(())
     }
-    pub fn get(&self, x: &B) -> Option<Complex> {
+    pub fn get(&self, x: &B) -> Option<Weight<P>> {
         let n = self.keys.len();
         let init_hash = calculate_hash(&x) as usize % n;
         let mut i = init_hash;
@@ -176,21 +172,21 @@ impl<B: BasisIdx, AB: AtomicBasisIdx<B>> SparseStateTable<B, AB> {
             .par_iter()
             .zip(self.weights.par_iter())
             .for_each(|(k, pw)| {
-                let w = Complex::new(pw.0.load(Ordering::Relaxed), pw.1.load(Ordering::Relaxed));
+                let w = pw.load();
                 if utility::is_nonzero(w) {
                     new_table.force_insert_unique(k.load(), w)
                 }
             });
         new_table
     }
-    pub fn try_put(&self, bidx: B, weight: Complex, maxload: Real) -> Result<(), ()> {
+    pub fn try_put(&self, bidx: B, weight: Weight<P>, maxload: Real) -> Result<(), ()> {
         let n = self.capacity();
         let probably_longest_probe =
             ((n as Real).log2() / (maxload - 1.0 - maxload.log2())).ceil() as usize;
         let tolerance = std::cmp::min(4 * std::cmp::max(10, probably_longest_probe), n);
         self.insert_add_weights_limit_probes(tolerance, bidx, weight)
     }
-    pub fn nonzeros(&self) -> Vec<(B, Complex)> {
+    pub fn nonzeros(&self) -> Vec<(B, Weight<P>)> {
         (0..self.capacity())
             .into_par_iter()
             .map(|i| {
@@ -5,7 +5,8 @@ use rayon::prelude::*;
 
 use crate::circuit::{Gate, PullApplyOutput, PushApplicable, PushApplyOutput};
 use crate::config::Config;
-use crate::types::{AtomicBasisIdx, BasisIdx, Complex, Real};
+use crate::gate_scheduler::Expansion;
+use crate::types::{AtomicBasisIdx, AtomicWeight, BasisIdx, Precision, Real, Weight};
 use crate::utility;
 
 use super::super::expected_cost;
@@ -27,20 +28,29 @@ impl Display for ExpandMethod {
     }
 }
 
-pub struct ExpandResult<B: BasisIdx, AB: AtomicBasisIdx<B>> {
-    pub state: State<B, AB>,
+impl From<&ExpandMethod> for Expansion {
+    fn from(method: &ExpandMethod) -> Self {
+        match method {
+            ExpandMethod::Sparse => Expansion::Sparse,
+            ExpandMethod::PushDense | ExpandMethod::PullDense => Expansion::Dense,
+        }
+    }
+}
+
+pub struct ExpandResult<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> {
+    pub state: State<B, AB, P>,
     pub num_nonzeros: usize,
     pub num_gate_apps: usize,
     pub method: ExpandMethod,
 }
 
-pub fn expand<B: BasisIdx, AB: AtomicBasisIdx<B>>(
+pub fn expand<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
     gates: Vec<&Gate<B>>,
     config: &Config,
     num_qubits: usize,
     prev_num_nonzeros: usize,
-    state: State<B, AB>,
-) -> ExpandResult<B, AB> {
+    state: State<B, AB, P>,
+) -> ExpandResult<B, AB, P> {
     let (expected_density, expected_num_nonzeros) =
         expected_cost(num_qubits, state.num_nonzeros(), prev_num_nonzeros);
 
@@ -57,21 +67,21 @@ pub fn expand<B: BasisIdx, AB: AtomicBasisIdx<B>>(
     }
 }
 
-enum SuccessorsResult<B: BasisIdx> {
+enum SuccessorsResult<B: BasisIdx, P: Precision> {
     AllSucceeded,
-    SomeFailed(Vec<(B, Complex, usize)>),
+    SomeFailed(Vec<(B, Weight<P>, usize)>),
 }
 
-fn apply_gates1<B: BasisIdx, AB: AtomicBasisIdx<B>>(
+fn apply_gates1<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
     gatenum: usize,
     gates: &[&Gate<B>],
-    table: &SparseStateTable<B, AB>,
+    table: &SparseStateTable<B, AB, P>,
     bidx: B,
-    weight: Complex,
+    weight: Weight<P>,
     is_full: &AtomicBool,
     apps: usize,
     maxload: Real,
-) -> (usize, SuccessorsResult<B>) {
+) -> (usize, SuccessorsResult<B, P>) {
     if utility::is_zero(weight) {
         return (apps, SuccessorsResult::AllSucceeded);
     }
@@ -115,21 +125,53 @@ fn apply_gates1<B: BasisIdx, AB: AtomicBasisIdx<B>>(
                 maxload,
             )
         }
+        PushApplyOutput::Multibranching(successors) => apply_gates_n(
+            gatenum + 1,
+            gates,
+            table,
+            successors,
+            is_full,
+            apps + 1,
+            maxload,
+        ),
     }
 }
 
-fn apply_gates2<B: BasisIdx, AB: AtomicBasisIdx<B>>(
+fn apply_gates_n<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
     gatenum: usize,
     gates: &[&Gate<B>],
-    table: &SparseStateTable<B, AB>,
+    table: &SparseStateTable<B, AB, P>,
+    successors: Vec<(B, Weight<P>)>,
+    is_full: &AtomicBool,
+    apps: usize,
+    maxload: Real,
+) -> (usize, SuccessorsResult<B, P>) {
+    let mut apps = apps;
+    let mut successors = successors.into_iter();
+    while let Some((bidx, weight)) = successors.next() {
+        match apply_gates1(gatenum, gates, table, bidx, weight, is_full, apps, maxload) {
+            (new_apps, SuccessorsResult::AllSucceeded) => apps = new_apps,
+            (new_apps, SuccessorsResult::SomeFailed(mut v)) => {
+                v.extend(successors.map(|(bidx, weight)| (bidx, weight, gatenum)));
+                return (new_apps, SuccessorsResult::SomeFailed(v));
+            }
+        }
+    }
+    (apps, SuccessorsResult::AllSucceeded)
+}
+
+fn apply_gates2<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
+    gatenum: usize,
+    gates: &[&Gate<B>],
+    table: &SparseStateTable<B, AB, P>,
     bidx1: B,
-    weight1: Complex,
+    weight1: Weight<P>,
     bidx2: B,
-    weight2: Complex,
+    weight2: Weight<P>,
     is_full: &AtomicBool,
     apps: usize,
     maxload: Real,
-) -> (usize, SuccessorsResult<B>) {
+) -> (usize, SuccessorsResult<B, P>) {
     match apply_gates1(
         gatenum, gates, table, bidx1, weight1, is_full, apps, maxload,
     ) {
@@ -144,13 +186,13 @@ fn apply_gates2<B: BasisIdx, AB: AtomicBasisIdx<B>>(
     }
 }
 
-pub fn expand_sparse<B: BasisIdx, AB: AtomicBasisIdx<B>>(
+pub fn expand_sparse<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
     gates: Vec<&Gate<B>>,
     num_qubits: usize,
     config: &Config,
     expected_num_nonzeros: usize,
-    state: &State<B, AB>,
-) -> ExpandResult<B, AB> {
+    state: &State<B, AB, P>,
+) -> ExpandResult<B, AB, P> {
     let mut table = SparseStateTable::new(num_qubits, config.maxload, expected_num_nonzeros);
     let n: usize = match state {
         State::Sparse(prev_table) => prev_table.num_nonzeros(),
@@ -161,18 +203,17 @@ pub fn expand_sparse<B: BasisIdx, AB: AtomicBasisIdx<B>>(
     let num_blocks = (n as f64 / block_size as f64).ceil() as usize;
     let block_start = |b: usize| block_size * b;
     let block_stop = |b: usize| std::cmp::min(n, block_size + block_start(b));
-    let mut blocks: Vec<(usize, usize, Vec<(B, Complex, usize)>)> = (0..num_blocks)
+    let mut blocks: Vec<(usize, usize, Vec<(B, Weight<P>, usize)>)> = (0..num_blocks)
         .into_par_iter()
         .map(|b| (b, block_start(b), vec![]))
         .collect();
-    let get: Box<dyn Fn(usize) -> (B, Complex) + Sync> = match state {
+    let get: Box<dyn Fn(usize) -> (B, Weight<P>) + Sync> = match state {
         State::Sparse(prev_table) => {
             let nonzeros = prev_table.nonzeros();
             Box::new(move |i: usize| nonzeros[i].clone())
         }
         State::Dense(prev_table) => Box::new(|i: usize| {
-            let v = prev_table.array[i].load(Ordering::Relaxed);
-            let weight = utility::unpack_complex(v);
+            let weight = prev_table.array[i].load();
             (B::from_idx(i), weight)
         }),
         State::Never(_, _) => unreachable!(),
@@ -255,11 +296,11 @@ pub fn expand_sparse<B: BasisIdx, AB: AtomicBasisIdx<B>>(
     }
 }
 
-fn expand_push_dense<B: BasisIdx, AB: AtomicBasisIdx<B>>(
+fn expand_push_dense<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
     gates: Vec<&Gate<B>>,
     num_qubits: usize,
-    state: State<B, AB>,
-) -> ExpandResult<B, AB> {
+    state: State<B, AB, P>,
+) -> ExpandResult<B, AB, P> {
     let table = DenseStateTable::new(num_qubits);
 
     let num_gate_apps = match state {
@@ -274,10 +315,7 @@ fn expand_push_dense<B: BasisIdx, AB: AtomicBasisIdx<B>>(
             .array
             .into_par_iter()
             .enumerate()
-            .map(|(idx, v)| {
-                let weight = utility::unpack_complex(v.load(Ordering::Relaxed));
-                apply_gates(&gates, &table, B::from_idx(idx), weight)
-            })
+            .map(|(idx, v)| apply_gates(&gates, &table, B::from_idx(idx), v.load()))
             .sum(),
         _ => unreachable!(),
     };
@@ -292,11 +330,11 @@ fn expand_push_dense<B: BasisIdx, AB: AtomicBasisIdx<B>>(
     }
 }
 
-fn expand_pull_dense<B: BasisIdx, AB: AtomicBasisIdx<B>>(
+fn expand_pull_dense<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
     gates: Vec<&Gate<B>>,
     num_qubits: usize,
-    state: State<B, AB>,
-) -> ExpandResult<B, AB> {
+    state: State<B, AB, P>,
+) -> ExpandResult<B, AB, P> {
     let table = DenseStateTable::new(num_qubits);
     let capacity = 1 << num_qubits;
 
@@ -324,11 +362,11 @@ fn expand_pull_dense<B: BasisIdx, AB: AtomicBasisIdx<B>>(
     }
 }
 
-fn apply_gates<B: BasisIdx>(
+fn apply_gates<B: BasisIdx, P: Precision>(
     gates: &[&Gate<B>],
-    table: &DenseStateTable,
+    table: &DenseStateTable<P>,
     bidx: B,
-    weight: Complex,
+    weight: Weight<P>,
 ) -> usize {
     if utility::is_zero(weight) {
         return 0;
@@ -347,32 +385,56 @@ fn apply_gates<B: BasisIdx>(
             let num_gate_apps_2 = apply_gates(&gates[1..], table, new_bidx2, new_weight2);
             1 + num_gate_apps_1 + num_gate_apps_2
         }
+        PushApplyOutput::Multibranching(successors) => {
+            1 + successors
+                .into_iter()
+                .map(|(new_bidx, new_weight)| apply_gates(&gates[1..], table, new_bidx, new_weight))
+                .sum::<usize>()
+        }
     }
 }
 
-fn apply_pull_gates<B: BasisIdx, AB: AtomicBasisIdx<B>>(
+fn apply_pull_gates<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision>(
     gates: &[&Gate<B>],
-    prev_state: &State<B, AB>,
+    prev_state: &State<B, AB, P>,
     bidx: B,
-) -> (Complex, usize) {
-    if gates.is_empty() {
-        let weight = prev_state.get(&bidx).unwrap_or(Complex::new(0.0, 0.0));
-        return (weight, 0);
-    }
+) -> (Weight<P>, usize) {
+    // the weight at `bidx` after the gates is pulled through the last one
+    // first
+    let (gate, gates) = match gates.split_last() {
+        Some(split) => split,
+        None => {
+            let weight = prev_state
+                .get(&bidx)
+                .unwrap_or(Weight::new(P::zero(), P::zero()));
+            return (weight, 0);
+        }
+    };
 
-    match gates[0].pull_action.as_ref().unwrap()(bidx) {
+    match gate.pull_apply(bidx) {
         PullApplyOutput::Nonbranching(neighbor, multiplier) => {
-            let (weight, num_gate_apps) = apply_pull_gates(&gates[1..], prev_state, neighbor);
+            let (weight, num_gate_apps) = apply_pull_gates(gates, prev_state, neighbor);
             (weight * multiplier, 1 + num_gate_apps)
         }
         PullApplyOutput::Branching((neighbor1, multiplier1), (neighbor2, multiplier2)) => {
-            let (weight1, num_gate_apps_1) = apply_pull_gates(&gates[1..], prev_state, neighbor1);
-            let (weight2, num_gate_apps_2) = apply_pull_gates(&gates[1..], prev_state, neighbor2);
+            let (weight1, num_gate_apps_1) = apply_pull_gates(gates, prev_state, neighbor1);
+            let (weight2, num_gate_apps_2) = apply_pull_gates(gates, prev_state, neighbor2);
 
             (
                 weight1 * multiplier1 + weight2 * multiplier2,
                 1 + num_gate_apps_1 + num_gate_apps_2,
             )
         }
+        PullApplyOutput::Multibranching(neighbors) => neighbors.into_iter().fold(
+            (Weight::new(P::zero(), P::zero()), 1),
+            |(weight, num_gate_apps), (neighbor, multiplier)| {
+                let (neighbor_weight, neighbor_apps) =
+                    apply_pull_gates(gates, prev_state, neighbor);
+                (
+                    weight + neighbor_weight * multiplier,
+                    num_gate_apps + neighbor_apps,
+                )
+            },
+        ),
     }
 }
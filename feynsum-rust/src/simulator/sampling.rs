@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+
+use rand::Rng;
+
+use crate::circuit::ClassicalRegister;
+use crate::types::{BasisIdx, Precision, QubitIndex, Real};
+
+use super::Densities;
+
+/// How many shots gave each outcome, keyed as in Qiskit: the bits of each
+/// classical register, most significant first, with the registers separated
+/// by spaces and the last one first
+pub type Counts = BTreeMap<String, usize>;
+
+/// Draws indices with given probabilities in constant time, by Vose's alias
+/// method. Each column `i` is drawn uniformly, and then stands for `i` with
+/// probability `probabilities[i]` and for `aliases[i]` otherwise.
+pub struct AliasTable {
+    probabilities: Vec<Real>,
+    aliases: Vec<usize>,
+}
+
+impl AliasTable {
+    /// `weights` need not sum to 1, but must not all be 0
+    pub fn new(weights: &[Real]) -> Self {
+        let n = weights.len();
+        let total = weights.iter().sum::<Real>();
+        assert!(total > 0.0);
+
+        let mut probabilities = weights
+            .iter()
+            .map(|weight| weight * n as Real / total)
+            .collect::<Vec<_>>();
+        let mut aliases = (0..n).collect::<Vec<_>>();
+        let (mut small, mut large): (Vec<_>, Vec<_>) =
+            (0..n).partition(|&i| probabilities[i] < 1.0);
+
+        // fill the rest of each small column with a large one
+        while let (Some(&s), Some(&l)) = (small.last(), large.last()) {
+            small.pop();
+            aliases[s] = l;
+            probabilities[l] -= 1.0 - probabilities[s];
+            if probabilities[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // the columns left over are full, up to rounding
+        for i in small.into_iter().chain(large) {
+            probabilities[i] = 1.0;
+        }
+
+        Self {
+            probabilities,
+            aliases,
+        }
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> usize {
+        let i = rng.gen_range(0..self.probabilities.len());
+        if rng.gen::<Real>() < self.probabilities[i] {
+            i
+        } else {
+            self.aliases[i]
+        }
+    }
+}
+
+/// Draws `shots` basis states from `densities` with probability the squared
+/// magnitude of their weights, and returns the densities along with them.
+/// The densities are sorted first, since the order of the hash tables they
+/// come from differs between runs, and the samples should not.
+pub fn sample_densities<B: BasisIdx, P: Precision>(
+    densities: Densities<B, P>,
+    shots: usize,
+    rng: &mut impl Rng,
+) -> (Densities<B, P>, Vec<B>) {
+    let mut nonzeros = densities.collect::<Vec<_>>();
+    nonzeros.sort_by_cached_key(|(bidx, _)| bidx.as_bytes());
+    let table = AliasTable::new(
+        &nonzeros
+            .iter()
+            .map(|(_, weight)| weight.norm_sqr().into_real())
+            .collect::<Vec<_>>(),
+    );
+    let samples = (0..shots)
+        .map(|_| nonzeros[table.sample(rng)].0.clone())
+        .collect();
+    (Box::new(nonzeros.into_iter()), samples)
+}
+
+/// Counts the outcomes of `samples` in the classical registers `cregs`, in
+/// which the bits that the final measurements write are read from the
+/// measured qubits, see `Circuit::without_final_measurements`, and the others
+/// are `clbits`. Without classical registers, the outcome is all qubits.
+pub fn counts<B: BasisIdx>(
+    samples: &[B],
+    num_qubits: usize,
+    cregs: &[ClassicalRegister],
+    measured: &[(QubitIndex, usize)],
+    clbits: &[bool],
+) -> Counts {
+    let bit = |b: bool| if b { '1' } else { '0' };
+
+    let mut counts = Counts::new();
+    for sample in samples {
+        let key = if cregs.is_empty() {
+            (0..num_qubits)
+                .rev()
+                .map(|qi| bit(sample.get(qi)))
+                .collect()
+        } else {
+            let mut bits = clbits.to_vec();
+            for &(qi, clbit) in measured {
+                bits[clbit] = sample.get(qi);
+            }
+            cregs
+                .iter()
+                .rev()
+                .map(|creg| {
+                    bits[creg.start..creg.start + creg.size]
+                        .iter()
+                        .rev()
+                        .map(|&b| bit(b))
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        *counts.entry(key).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{BasisIdx64, Weight};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_alias_table() {
+        let weights = [0.5, 0.0, 0.125, 0.375];
+        let table = AliasTable::new(&weights);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let shots = 100_000;
+        let mut hits = [0; 4];
+        for _ in 0..shots {
+            hits[table.sample(&mut rng)] += 1;
+        }
+        assert_eq!(hits[1], 0);
+        for (hit, weight) in hits.iter().zip(weights) {
+            assert!((*hit as Real / shots as Real - weight).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_counts() {
+        let densities: Densities<BasisIdx64, f64> = Box::new(
+            [
+                (BasisIdx64::from_idx(0b000), Weight::new(0.6, 0.0)),
+                (BasisIdx64::from_idx(0b101), Weight::new(0.0, 0.8)),
+            ]
+            .into_iter(),
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        let (densities, samples) = sample_densities(densities, 1000, &mut rng);
+        assert_eq!(densities.count(), 2);
+
+        let counts = super::counts(&samples, 3, &[], &[], &[]);
+        assert_eq!(counts.keys().collect::<Vec<_>>(), vec!["000", "101"]);
+        assert_eq!(counts.values().sum::<usize>(), 1000);
+        assert!((counts["101"] as Real / 1000.0 - 0.64).abs() < 0.05);
+
+        // qubit 0 into a[1], qubit 2 into b[0], and a[0] as left by the run
+        let cregs = [
+            ClassicalRegister {
+                name: "a".to_string(),
+                start: 0,
+                size: 2,
+            },
+            ClassicalRegister {
+                name: "b".to_string(),
+                start: 2,
+                size: 1,
+            },
+        ];
+        let counts = super::counts(
+            &samples,
+            3,
+            &cregs,
+            &[(0, 1), (2, 2)],
+            &[true, false, false],
+        );
+        assert_eq!(counts.keys().collect::<Vec<_>>(), vec!["0 01", "1 11"]);
+    }
+}
@@ -5,27 +5,75 @@ use log::{debug, info};
 
 use crate::circuit::Circuit;
 use crate::config::Config;
-use crate::gate_scheduler;
+use crate::error;
+use crate::gate_scheduler::{self, Feedback, ScheduleCursor};
 use crate::profile;
-use crate::types::{BasisIdx, Complex, Real};
+use crate::simulator::{self, FinalState, SimulatorBackend};
+use crate::types::{AtomicBasisIdx, BasisIdx, Precision, Real, Weight};
 
 use state::{SparseStateTable, State};
 use state_expander::ExpandResult;
 
-pub fn run<B: BasisIdx>(config: &Config, circuit: Circuit<B>) -> State<B> {
+/// Simulates on one thread, in a sparse table until it gets dense
+pub struct Backend;
+
+impl<B: BasisIdx, AB: AtomicBasisIdx<B>, P: Precision> SimulatorBackend<B, AB, P> for Backend {
+    fn name(&self) -> &'static str {
+        "sequential"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["seq"]
+    }
+
+    fn simulate(
+        &self,
+        config: &Config,
+        circuit: Circuit<B>,
+        cursor: &ScheduleCursor,
+    ) -> error::Result<FinalState<B, P>> {
+        Ok(simulator::compactify(run::<B, P>(config, circuit, cursor)?))
+    }
+}
+
+pub fn run<B: BasisIdx, P: Precision>(
+    config: &Config,
+    circuit: Circuit<B>,
+    cursor: &ScheduleCursor,
+) -> error::Result<(State<B, P>, Vec<bool>)> {
+    let state = State::Sparse(SparseStateTable::singleton(
+        B::zeros(),
+        Weight::new(P::one(), P::zero()),
+    )); // initial state
+
+    simulator::run_dynamic(
+        config,
+        circuit,
+        state,
+        |segment, state| run_segment(config, segment, state, cursor),
+        |state, qubit| {
+            let probability = state.probability_of_one(qubit);
+            (state, probability.into_real())
+        },
+    )
+}
+
+fn run_segment<B: BasisIdx, P: Precision>(
+    config: &Config,
+    circuit: Circuit<B>,
+    state: State<B, P>,
+    cursor: &ScheduleCursor,
+) -> error::Result<State<B, P>> {
     let num_gates = circuit.num_gates();
     let num_qubits = circuit.num_qubits;
 
     let mut num_gates_visited = 0;
-    let mut state = State::Sparse(SparseStateTable::singleton(
-        B::zeros(),
-        Complex::new(1.0, 0.0),
-    )); // initial state
-    let mut num_nonzeros = 1;
+    let mut state = state;
+    let mut num_nonzeros = state.num_nonzeros();
     let mut num_gate_apps = 0;
-    let mut prev_num_nonzeros = 1;
+    let mut prev_num_nonzeros = num_nonzeros;
 
-    let mut gate_scheduler = gate_scheduler::create_gate_scheduler(config, &circuit);
+    let mut gate_scheduler = gate_scheduler::create_gate_scheduler(config, &circuit, cursor)?;
 
     info!("starting gate application loop.");
 
@@ -60,14 +108,17 @@ pub fn run<B: BasisIdx>(config: &Config, circuit: Circuit<B>) -> State<B> {
             state
         ));
 
-        let density = {
-            let max_num_states: u64 = 1 << num_qubits;
-            num_nonzeros as Real / max_num_states as Real
-        };
+        gate_scheduler.observe(&Feedback {
+            num_nonzeros: new_num_nonzeros,
+            expansion: (&method).into(),
+            duration,
+        });
+
+        let density = simulator::density(num_qubits, num_nonzeros);
 
-        let throughput = (num_gate_apps_here as Real / 1e6) / duration.as_secs_f32();
+        let throughput = (num_gate_apps_here as Real / 1e6) / duration.as_secs_f64();
 
-        println!(
+        debug!(
             "gate: {:<3} density: {:.8} nonzero: {:>10} hop: {:<2} {} time: {:.4}s throughput: {:.2}M gates/s",
             num_gates_visited,
             density,
@@ -85,13 +136,10 @@ pub fn run<B: BasisIdx>(config: &Config, circuit: Circuit<B>) -> State<B> {
         state = new_state;
     });
 
-    let final_density = {
-        let max_num_states: u64 = 1 << num_qubits;
-        num_nonzeros as Real / max_num_states as Real
-    };
+    let final_density = simulator::density(num_qubits, num_nonzeros);
 
-    println!(
-        "gate: {:<2} density: {:.8} nonzero: {:>10}\ngate app count: {}, time: {}s",
+    info!(
+        "gate: {:<2} density: {:.8} nonzero: {:>10}, gate app count: {}, time: {}s",
         num_gates_visited,
         final_density,
         num_nonzeros,
@@ -100,7 +148,7 @@ pub fn run<B: BasisIdx>(config: &Config, circuit: Circuit<B>) -> State<B> {
     );
 
     assert!(num_gates_visited >= num_gates);
-    state
+    Ok(state)
 }
 
 #[cfg(test)]
@@ -207,7 +255,8 @@ h q0[28];
         )
         .unwrap();
 
-        let state = run::<BasisIdx64>(&config, circuit);
+        let (state, _) =
+            run::<BasisIdx64, f64>(&config, circuit, &ScheduleCursor::default()).unwrap();
 
         println!("{:?}", state);
 
@@ -225,7 +274,7 @@ h q0[28];
         assert!(abs_diff_eq!(
             nonzero_field_1.re,
             constants::RECP_SQRT_2,
-            epsilon = 0.0001
+            epsilon = 1e-10
         ));
 
         let nonzero_field_2 = table
@@ -234,7 +283,7 @@ h q0[28];
         assert!(abs_diff_eq!(
             nonzero_field_2.re,
             -constants::RECP_SQRT_2,
-            epsilon = 0.0001
+            epsilon = 1e-10
         ));
     }
 }
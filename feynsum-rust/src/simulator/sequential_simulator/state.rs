@@ -1,4 +1,4 @@
-use crate::types::{BasisIdx, Complex};
+use crate::types::{BasisIdx, Precision, QubitIndex, Weight};
 use crate::utility;
 
 mod dense_state_table;
@@ -9,17 +9,17 @@ pub use sparse_state_table::SparseStateTable;
 
 use super::super::Compactifiable;
 
-pub trait Table<B: BasisIdx> {
-    fn put(&mut self, bidx: B, weight: Complex);
+pub trait Table<B: BasisIdx, P: Precision> {
+    fn put(&mut self, bidx: B, weight: Weight<P>);
 }
 
 #[derive(Debug)]
-pub enum State<B: BasisIdx> {
-    Sparse(SparseStateTable<B>),
-    Dense(DenseStateTable),
+pub enum State<B: BasisIdx, P: Precision> {
+    Sparse(SparseStateTable<B, P>),
+    Dense(DenseStateTable<P>),
 }
 
-impl<B: BasisIdx> State<B> {
+impl<B: BasisIdx, P: Precision> State<B, P> {
     pub fn num_nonzeros(&self) -> usize {
         match self {
             State::Sparse(table) => table.num_nonzeros(),
@@ -27,16 +27,35 @@ impl<B: BasisIdx> State<B> {
         }
     }
 
-    pub fn get(&self, bidx: &B) -> Option<&Complex> {
+    pub fn get(&self, bidx: &B) -> Option<&Weight<P>> {
         match self {
             State::Sparse(table) => table.get(bidx),
             State::Dense(table) => table.get(bidx),
         }
     }
+
+    /// Probability of measuring 1 on `qubit`
+    pub fn probability_of_one(&self, qubit: QubitIndex) -> P {
+        match self {
+            State::Sparse(table) => table
+                .table
+                .iter()
+                .filter(|(bidx, _)| bidx.get(qubit))
+                .map(|(_, weight)| weight.norm_sqr())
+                .sum(),
+            State::Dense(table) => table
+                .array
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| B::from_idx(*idx).get(qubit))
+                .map(|(_, weight)| weight.norm_sqr())
+                .sum(),
+        }
+    }
 }
 
-impl<B: BasisIdx> Compactifiable<B> for State<B> {
-    fn compactify(self) -> Box<dyn Iterator<Item = (B, Complex)>> {
+impl<B: BasisIdx, P: Precision> Compactifiable<B, P> for State<B, P> {
+    fn compactify(self) -> Box<dyn Iterator<Item = (B, Weight<P>)>> {
         match self {
             State::Sparse(table) => Box::new(
                 table
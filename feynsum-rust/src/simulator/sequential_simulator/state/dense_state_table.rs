@@ -1,19 +1,19 @@
-use crate::types::{BasisIdx, Complex};
+use crate::types::{BasisIdx, Precision, Weight};
 use crate::utility;
 
 use super::Table;
 
 #[derive(Debug)]
-pub struct DenseStateTable {
-    pub array: Vec<Complex>,
+pub struct DenseStateTable<P: Precision> {
+    pub array: Vec<Weight<P>>,
 }
 
-impl DenseStateTable {
+impl<P: Precision> DenseStateTable<P> {
     pub fn new(num_qubits: usize) -> Self {
         let capacity = 1 << num_qubits;
 
         Self {
-            array: vec![Complex::new(0.0, 0.0); capacity],
+            array: vec![Weight::new(P::zero(), P::zero()); capacity],
         }
     }
 
@@ -24,13 +24,13 @@ impl DenseStateTable {
             .count()
     }
 
-    pub fn get<B: BasisIdx>(&self, bidx: &B) -> Option<&Complex> {
+    pub fn get<B: BasisIdx>(&self, bidx: &B) -> Option<&Weight<P>> {
         self.array.get(bidx.as_idx())
     }
 }
 
-impl<B: BasisIdx> Table<B> for DenseStateTable {
-    fn put(&mut self, bidx: B, weight: Complex) {
+impl<B: BasisIdx, P: Precision> Table<B, P> for DenseStateTable<P> {
+    fn put(&mut self, bidx: B, weight: Weight<P>) {
         let idx = bidx.as_idx();
 
         self.array[idx] += weight;
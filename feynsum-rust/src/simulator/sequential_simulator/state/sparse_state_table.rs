@@ -1,17 +1,17 @@
 use std::collections::HashMap;
 
-use crate::types::{BasisIdx, Complex};
+use crate::types::{BasisIdx, Precision, Weight};
 use crate::utility;
 
 use super::Table;
 
 #[derive(Debug)]
-pub struct SparseStateTable<B: BasisIdx> {
-    pub table: HashMap<B, Complex>,
+pub struct SparseStateTable<B: BasisIdx, P: Precision> {
+    pub table: HashMap<B, Weight<P>>,
 }
 
-impl<B: BasisIdx> SparseStateTable<B> {
-    pub fn singleton(bidx: B, weight: Complex) -> Self {
+impl<B: BasisIdx, P: Precision> SparseStateTable<B, P> {
+    pub fn singleton(bidx: B, weight: Weight<P>) -> Self {
         Self {
             table: HashMap::from([(bidx, weight)]),
         }
@@ -30,13 +30,13 @@ impl<B: BasisIdx> SparseStateTable<B> {
             .count()
     }
 
-    pub fn get(&self, bidx: &B) -> Option<&Complex> {
+    pub fn get(&self, bidx: &B) -> Option<&Weight<P>> {
         self.table.get(bidx)
     }
 }
 
-impl<B: BasisIdx> Table<B> for SparseStateTable<B> {
-    fn put(&mut self, bidx: B, weight: Complex) {
+impl<B: BasisIdx, P: Precision> Table<B, P> for SparseStateTable<B, P> {
+    fn put(&mut self, bidx: B, weight: Weight<P>) {
         self.table
             .entry(bidx)
             .and_modify(|w| *w += weight)
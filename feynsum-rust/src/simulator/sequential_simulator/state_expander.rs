@@ -2,7 +2,8 @@ use std::fmt::{self, Display, Formatter};
 
 use crate::circuit::{Gate, PullApplyOutput, PushApplicable, PushApplyOutput};
 use crate::config::Config;
-use crate::types::{BasisIdx, Complex};
+use crate::gate_scheduler::Expansion;
+use crate::types::{BasisIdx, Precision, Weight};
 use crate::utility;
 
 use super::super::{expected_cost, Compactifiable};
@@ -25,20 +26,29 @@ impl Display for ExpandMethod {
     }
 }
 
-pub struct ExpandResult<B: BasisIdx> {
-    pub state: State<B>,
+impl From<&ExpandMethod> for Expansion {
+    fn from(method: &ExpandMethod) -> Self {
+        match method {
+            ExpandMethod::Sparse => Expansion::Sparse,
+            ExpandMethod::PushDense | ExpandMethod::PullDense => Expansion::Dense,
+        }
+    }
+}
+
+pub struct ExpandResult<B: BasisIdx, P: Precision> {
+    pub state: State<B, P>,
     pub num_nonzeros: usize,
     pub num_gate_apps: usize,
     pub method: ExpandMethod,
 }
 
-pub fn expand<B: BasisIdx>(
+pub fn expand<B: BasisIdx, P: Precision>(
     gates: Vec<&Gate<B>>,
     config: &Config,
     num_qubits: usize,
     prev_num_nonzeros: usize,
-    state: State<B>,
-) -> ExpandResult<B> {
+    state: State<B, P>,
+) -> ExpandResult<B, P> {
     let (expected_density, _) = expected_cost(num_qubits, state.num_nonzeros(), prev_num_nonzeros);
 
     let all_gates_pullable = gates.iter().all(|gate| gate.is_pullable());
@@ -54,8 +64,11 @@ pub fn expand<B: BasisIdx>(
     }
 }
 
-fn expand_sparse<B: BasisIdx>(gates: Vec<&Gate<B>>, state: State<B>) -> ExpandResult<B> {
-    let mut table = SparseStateTable::<B>::new();
+fn expand_sparse<B: BasisIdx, P: Precision>(
+    gates: Vec<&Gate<B>>,
+    state: State<B, P>,
+) -> ExpandResult<B, P> {
+    let mut table = SparseStateTable::<B, P>::new();
 
     let num_gate_apps = state
         .compactify()
@@ -72,11 +85,11 @@ fn expand_sparse<B: BasisIdx>(gates: Vec<&Gate<B>>, state: State<B>) -> ExpandRe
     }
 }
 
-fn expand_push_dense<B: BasisIdx>(
+fn expand_push_dense<B: BasisIdx, P: Precision>(
     gates: Vec<&Gate<B>>,
     num_qubits: usize,
-    state: State<B>,
-) -> ExpandResult<B> {
+    state: State<B, P>,
+) -> ExpandResult<B, P> {
     let mut table = DenseStateTable::new(num_qubits);
 
     let num_gate_apps = state
@@ -94,11 +107,11 @@ fn expand_push_dense<B: BasisIdx>(
     }
 }
 
-fn expand_pull_dense<B: BasisIdx>(
+fn expand_pull_dense<B: BasisIdx, P: Precision>(
     gates: Vec<&Gate<B>>,
     num_qubits: usize,
-    state: State<B>,
-) -> ExpandResult<B> {
+    state: State<B, P>,
+) -> ExpandResult<B, P> {
     let mut table = DenseStateTable::new(num_qubits);
 
     let capacity = 1 << num_qubits;
@@ -125,11 +138,11 @@ fn expand_pull_dense<B: BasisIdx>(
     }
 }
 
-fn apply_gates<B: BasisIdx>(
+fn apply_gates<B: BasisIdx, P: Precision>(
     gates: &[&Gate<B>],
-    table: &mut impl Table<B>,
+    table: &mut impl Table<B, P>,
     bidx: B,
-    weight: Complex,
+    weight: Weight<P>,
 ) -> usize {
     if utility::is_zero(weight) {
         return 0;
@@ -148,35 +161,57 @@ fn apply_gates<B: BasisIdx>(
             let num_gate_apps_2 = apply_gates(&gates[1..], table, new_bidx2, new_weight2);
             1 + num_gate_apps_1 + num_gate_apps_2
         }
+        PushApplyOutput::Multibranching(successors) => {
+            1 + successors
+                .into_iter()
+                .map(|(new_bidx, new_weight)| apply_gates(&gates[1..], table, new_bidx, new_weight))
+                .sum::<usize>()
+        }
     }
 }
 
-fn apply_pull_gates<B: BasisIdx>(
+fn apply_pull_gates<B: BasisIdx, P: Precision>(
     gates: &[&Gate<B>],
-    prev_state: &State<B>,
+    prev_state: &State<B, P>,
     bidx: &B,
-) -> (Complex, usize) {
-    if gates.is_empty() {
-        let weight = prev_state
-            .get(bidx)
-            .map_or(Complex::new(0.0, 0.0), Clone::clone);
-        return (weight, 0);
-    }
+) -> (Weight<P>, usize) {
+    // the weight at `bidx` after the gates is pulled through the last one
+    // first
+    let (gate, gates) = match gates.split_last() {
+        Some(split) => split,
+        None => {
+            let weight = prev_state
+                .get(bidx)
+                .map_or(Weight::new(P::zero(), P::zero()), Clone::clone);
+            return (weight, 0);
+        }
+    };
 
-    match gates[0].pull_action.as_ref().unwrap()(bidx.clone()) {
+    match gate.pull_apply(bidx.clone()) {
         // FIXME: No clone
         PullApplyOutput::Nonbranching(neighbor, multiplier) => {
-            let (weight, num_gate_apps) = apply_pull_gates(&gates[1..], prev_state, &neighbor);
+            let (weight, num_gate_apps) = apply_pull_gates(gates, prev_state, &neighbor);
             (weight * multiplier, 1 + num_gate_apps)
         }
         PullApplyOutput::Branching((neighbor1, multiplier1), (neighbor2, multiplier2)) => {
-            let (weight1, num_gate_apps_1) = apply_pull_gates(&gates[1..], prev_state, &neighbor1);
-            let (weight2, num_gate_apps_2) = apply_pull_gates(&gates[1..], prev_state, &neighbor2);
+            let (weight1, num_gate_apps_1) = apply_pull_gates(gates, prev_state, &neighbor1);
+            let (weight2, num_gate_apps_2) = apply_pull_gates(gates, prev_state, &neighbor2);
 
             (
                 weight1 * multiplier1 + weight2 * multiplier2,
                 1 + num_gate_apps_1 + num_gate_apps_2,
             )
         }
+        PullApplyOutput::Multibranching(neighbors) => neighbors.into_iter().fold(
+            (Weight::new(P::zero(), P::zero()), 1),
+            |(weight, num_gate_apps), (neighbor, multiplier)| {
+                let (neighbor_weight, neighbor_apps) =
+                    apply_pull_gates(gates, prev_state, &neighbor);
+                (
+                    weight + neighbor_weight * multiplier,
+                    num_gate_apps + neighbor_apps,
+                )
+            },
+        ),
     }
 }
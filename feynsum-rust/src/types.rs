@@ -1,20 +1,21 @@
-use atomic_float::AtomicF32;
-use num_complex::Complex32;
+use num_complex::Complex64;
 
 pub type QubitIndex = usize;
 pub type GateIndex = usize;
-pub type Real = f32;
-pub type Complex = Complex32;
-
-// used to store weights in the concurrent hash table
-pub type AtomicReal = AtomicF32;
-pub type AtomicComplex = (AtomicReal, AtomicReal);
+// circuits are built in double precision; the simulators store weights in
+// the `Precision` selected at run time
+pub type Real = f64;
+pub type Complex = Complex64;
 
 pub mod constants {
-    pub const RECP_SQRT_2: super::Real = std::f32::consts::FRAC_1_SQRT_2;
+    pub const RECP_SQRT_2: super::Real = std::f64::consts::FRAC_1_SQRT_2;
     pub const ZERO_THRESHOLD: super::Real = 0.00000001;
 }
 
 pub mod basis_idx;
+mod precision;
 
-pub use basis_idx::{AtomicBasisIdx, BasisIdx, BasisIdx64, BasisIdxUnlimited};
+pub use basis_idx::{
+    AtomicBasisIdx, AtomicBasisIdxN, BasisIdx, BasisIdx64, BasisIdxN, BasisIdxUnlimited,
+};
+pub use precision::{AtomicWeight, Precision, Weight};
@@ -1,9 +1,11 @@
 use std::{fmt::Display, hash::Hash};
 
 mod basis_idx_64;
+mod basis_idx_n;
 mod basis_idx_unlimited;
 
 pub use basis_idx_64::BasisIdx64;
+pub use basis_idx_n::{AtomicBasisIdxN, BasisIdxN};
 pub use basis_idx_unlimited::BasisIdxUnlimited;
 
 pub trait BasisIdx: Eq + Hash + Sync + Send + Clone + 'static + Display {
@@ -25,5 +27,6 @@ pub trait AtomicBasisIdx<B: BasisIdx>: Sync + Send {
     // TODO: Add methods
     fn empty_key(num_qubits: usize) -> Self;
     fn load(&self) -> B;
+    #[allow(clippy::result_unit_err)]
     fn compare_exchange(&self, current: B, new: B) -> Result<B, ()>;
 }
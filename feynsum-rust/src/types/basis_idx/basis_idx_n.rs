@@ -0,0 +1,244 @@
+use std::array;
+use std::fmt::{self, Display, Formatter};
+use std::hash::Hash;
+use std::hint;
+use std::sync::atomic::{self, AtomicU64, Ordering};
+
+use super::{AtomicBasisIdx, BasisIdx};
+
+/// A basis index of `W` 64-bit words, least significant word first. The top
+/// bit is reserved for the empty key, so it fits up to `64 * W - 1` qubits.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct BasisIdxN<const W: usize> {
+    words: [u64; W],
+}
+
+impl<const W: usize> BasisIdxN<W> {
+    pub const MAX_QUBITS: usize = 64 * W - 1;
+
+    fn with_bit(&self, qi: usize, bit: bool) -> Self {
+        let mut words = self.words;
+        if bit {
+            words[qi / 64] |= 1 << (qi % 64);
+        } else {
+            words[qi / 64] &= !(1 << (qi % 64));
+        }
+        Self { words }
+    }
+}
+
+impl<const W: usize> Display for BasisIdxN<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let top = self.words.iter().rposition(|w| *w != 0).unwrap_or(0);
+        let mut bits = format!("{:b}", self.words[top]);
+        for word in self.words[..top].iter().rev() {
+            bits.push_str(&format!("{:064b}", word));
+        }
+        match f.width() {
+            Some(width) => format!("{:0>width$}", bits, width = width).fmt(f),
+            None => bits.fmt(f),
+        }
+    }
+}
+
+impl<const W: usize> BasisIdx for BasisIdxN<W> {
+    fn get(&self, qi: usize) -> bool {
+        self.words[qi / 64] & (1 << (qi % 64)) != 0
+    }
+
+    fn flip(&self, qi: usize) -> Self {
+        self.with_bit(qi, !self.get(qi))
+    }
+
+    fn zeros() -> Self {
+        Self { words: [0; W] }
+    }
+
+    fn set(&self, qi: usize) -> Self {
+        self.with_bit(qi, true)
+    }
+
+    fn unset(&self, qi: usize) -> Self {
+        self.with_bit(qi, false)
+    }
+
+    fn swap(&self, qi1: usize, qi2: usize) -> Self {
+        self.with_bit(qi1, self.get(qi2))
+            .with_bit(qi2, self.get(qi1))
+    }
+
+    fn from_idx(idx: usize) -> Self {
+        let mut words = [0; W];
+        words[0] = idx as u64;
+        Self { words }
+    }
+
+    fn as_idx(&self) -> usize {
+        self.words[0] as usize
+    }
+
+    fn empty_key(_num_qubits: usize) -> Self {
+        Self::zeros().set(Self::MAX_QUBITS)
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        self.words
+            .iter()
+            .rev()
+            .flat_map(|word| word.to_be_bytes())
+            .collect()
+    }
+}
+
+/// A `BasisIdxN` behind a seqlock: loads never block each other and only
+/// retry while a `compare_exchange` is writing the words, which happens at
+/// most once per slot of a sparse table.
+pub struct AtomicBasisIdxN<const W: usize> {
+    // odd while a writer holds the lock
+    seq: AtomicU64,
+    words: [AtomicU64; W],
+}
+
+impl<const W: usize> AtomicBasisIdxN<W> {
+    fn new(bidx: BasisIdxN<W>) -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            words: bidx.words.map(AtomicU64::new),
+        }
+    }
+
+    fn read_words(&self) -> BasisIdxN<W> {
+        BasisIdxN {
+            words: array::from_fn(|i| self.words[i].load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Takes the write lock, returning the sequence number to release it with
+    fn lock(&self) -> u64 {
+        loop {
+            let seq = self.seq.load(Ordering::Relaxed);
+            if seq & 1 == 0
+                && self
+                    .seq
+                    .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                // keep the word stores from moving above the lock
+                atomic::fence(Ordering::Release);
+                return seq;
+            }
+            hint::spin_loop();
+        }
+    }
+}
+
+impl<const W: usize> AtomicBasisIdx<BasisIdxN<W>> for AtomicBasisIdxN<W> {
+    fn empty_key(num_qubits: usize) -> Self {
+        Self::new(BasisIdxN::empty_key(num_qubits))
+    }
+
+    fn load(&self) -> BasisIdxN<W> {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                let bidx = self.read_words();
+                atomic::fence(Ordering::Acquire);
+                if self.seq.load(Ordering::Relaxed) == seq {
+                    return bidx;
+                }
+            }
+            hint::spin_loop();
+        }
+    }
+
+    fn compare_exchange(
+        &self,
+        current: BasisIdxN<W>,
+        new: BasisIdxN<W>,
+    ) -> Result<BasisIdxN<W>, ()> {
+        // most failures are visible without taking the lock
+        if self.load() != current {
+            return Err(());
+        }
+
+        let seq = self.lock();
+        if self.read_words() == current {
+            for (word, value) in self.words.iter().zip(new.words) {
+                word.store(value, Ordering::Relaxed);
+            }
+            self.seq.store(seq + 2, Ordering::Release);
+            Ok(current)
+        } else {
+            // nothing was written, so readers that started before can keep
+            // what they read
+            self.seq.store(seq, Ordering::Release);
+            Err(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_bits_across_words() {
+        let bidx = BasisIdxN::<2>::zeros().set(1).set(64).set(127);
+        assert!(bidx.get(1));
+        assert!(bidx.get(64));
+        assert!(!bidx.get(63));
+        assert_eq!(bidx.words, [0b10, 1 | (1 << 63)]);
+
+        let bidx = bidx.flip(64).flip(65).unset(127);
+        assert_eq!(bidx.words, [0b10, 0b10]);
+
+        let bidx = bidx.swap(1, 100);
+        assert_eq!(bidx.words, [0, 0b10 | (1 << 36)]);
+    }
+
+    #[test]
+    fn test_display() {
+        let bidx = BasisIdxN::<2>::zeros().set(0).set(64);
+        let expected = format!("1{}1", "0".repeat(63));
+        assert_eq!(bidx.to_string(), expected);
+        assert_eq!(format!("{:67}", bidx), format!("00{}", expected));
+        assert_eq!(format!("{:3}", BasisIdxN::<3>::from_idx(0b10)), "010");
+    }
+
+    #[test]
+    fn test_compare_exchange() {
+        let keys = Arc::new(
+            (0..64)
+                .map(|_| AtomicBasisIdxN::<3>::empty_key(100))
+                .collect::<Vec<_>>(),
+        );
+        let empty = BasisIdxN::<3>::empty_key(100);
+
+        // every thread tries to claim every slot; each slot has one winner
+        let winners = (0..4)
+            .map(|t| {
+                let keys = keys.clone();
+                thread::spawn(move || {
+                    let bidx = BasisIdxN::<3>::zeros().set(130).set(t);
+                    keys.iter()
+                        .filter(|key| key.compare_exchange(empty, bidx).is_ok())
+                        .count()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum::<usize>();
+        assert_eq!(winners, keys.len());
+
+        for key in keys.iter() {
+            let bidx = key.load();
+            assert!(bidx.get(130));
+            assert_eq!(bidx.words[0].count_ones(), 1);
+            assert_eq!(key.compare_exchange(empty, empty), Err(()));
+        }
+    }
+}
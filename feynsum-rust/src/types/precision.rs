@@ -0,0 +1,140 @@
+use std::iter::Sum;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use atomic_float::AtomicF64;
+use nalgebra::RealField;
+
+use super::{Complex, Real};
+use crate::utility;
+
+/// A complex weight whose parts are stored in precision `P`
+pub type Weight<P> = num_complex::Complex<P>;
+
+/// The floating point type the simulators store and accumulate weights in,
+/// selected with `--precision`. Circuits are built in `Real`, so gate
+/// parameters and matrices are converted once per application.
+pub trait Precision: RealField + Copy + Sum {
+    type AtomicWeight: AtomicWeight<Self>;
+
+    const BITS: usize;
+
+    fn convert(x: Real) -> Self;
+    fn into_real(self) -> Real;
+
+    fn from_complex(c: Complex) -> Weight<Self> {
+        Weight::new(Self::convert(c.re), Self::convert(c.im))
+    }
+
+    fn to_complex(w: Weight<Self>) -> Complex {
+        Complex::new(w.re.into_real(), w.im.into_real())
+    }
+}
+
+/// A weight in a concurrent state table
+pub trait AtomicWeight<P: Precision>: Send + Sync {
+    fn zero() -> Self;
+    fn load(&self) -> Weight<P>;
+    /// Not atomic with respect to concurrent `add`s
+    fn store(&self, weight: Weight<P>);
+    fn add(&self, weight: Weight<P>);
+}
+
+impl Precision for f32 {
+    type AtomicWeight = AtomicU64;
+
+    const BITS: usize = 32;
+
+    fn convert(x: Real) -> Self {
+        x as f32
+    }
+
+    fn into_real(self) -> Real {
+        self as Real
+    }
+}
+
+impl Precision for f64 {
+    type AtomicWeight = (AtomicF64, AtomicF64);
+
+    const BITS: usize = 64;
+
+    fn convert(x: Real) -> Self {
+        x
+    }
+
+    fn into_real(self) -> Real {
+        self
+    }
+}
+
+// both parts fit in a word, so they are updated together
+impl AtomicWeight<f32> for AtomicU64 {
+    fn zero() -> Self {
+        AtomicU64::new(0)
+    }
+
+    fn load(&self) -> Weight<f32> {
+        utility::unpack_complex(self.load(Ordering::Relaxed))
+    }
+
+    fn store(&self, weight: Weight<f32>) {
+        self.store(utility::pack_complex(weight), Ordering::Relaxed);
+    }
+
+    fn add(&self, weight: Weight<f32>) {
+        loop {
+            let old = self.load(Ordering::Relaxed);
+            let new = utility::pack_complex(utility::unpack_complex(old) + weight);
+
+            if self
+                .compare_exchange(old, new, Ordering::SeqCst, Ordering::Acquire)
+                .is_ok()
+            {
+                return;
+            }
+        }
+    }
+}
+
+// the parts are added separately, which is fine as long as the weight is not
+// read before all the puts are done
+impl AtomicWeight<f64> for (AtomicF64, AtomicF64) {
+    fn zero() -> Self {
+        (AtomicF64::new(0.0), AtomicF64::new(0.0))
+    }
+
+    fn load(&self) -> Weight<f64> {
+        Weight::new(
+            self.0.load(Ordering::Relaxed),
+            self.1.load(Ordering::Relaxed),
+        )
+    }
+
+    fn store(&self, weight: Weight<f64>) {
+        self.0.store(weight.re, Ordering::Relaxed);
+        self.1.store(weight.im, Ordering::Relaxed);
+    }
+
+    fn add(&self, weight: Weight<f64>) {
+        self.0.fetch_add(weight.re, Ordering::SeqCst);
+        self.1.fetch_add(weight.im, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atomic_add() {
+        let a = <f32 as Precision>::AtomicWeight::zero();
+        a.add(Weight::new(1.0, 2.0));
+        a.add(Weight::new(0.5, -1.0));
+        assert_eq!(AtomicWeight::load(&a), Weight::new(1.5, 1.0));
+
+        let b = <f64 as Precision>::AtomicWeight::zero();
+        b.add(Weight::new(1.0, 2.0));
+        b.add(Weight::new(0.5, -1.0));
+        assert_eq!(b.load(), Weight::new(1.5, 1.0));
+    }
+}
@@ -1,4 +1,6 @@
-use crate::types::{constants, Complex, Real};
+use num_complex::Complex32;
+
+use crate::types::{constants, Precision, Weight};
 
 #[macro_export]
 macro_rules! profile {
@@ -22,30 +24,31 @@ macro_rules! test_case {
     };
 }
 
-pub fn is_real_zero(x: Real) -> bool {
-    x.abs() < constants::ZERO_THRESHOLD
+pub fn is_real_zero<P: Precision>(x: P) -> bool {
+    let threshold = P::convert(constants::ZERO_THRESHOLD);
+    -threshold < x && x < threshold
 }
 
-pub fn is_zero(c: Complex) -> bool {
+pub fn is_zero<P: Precision>(c: Weight<P>) -> bool {
     is_real_zero(c.re) && is_real_zero(c.im)
 }
 
-pub fn is_nonzero(c: Complex) -> bool {
+pub fn is_nonzero<P: Precision>(c: Weight<P>) -> bool {
     !is_real_zero(c.re) || !is_real_zero(c.im)
 }
 
-pub fn unpack_complex(num: u64) -> Complex {
+pub fn unpack_complex(num: u64) -> Complex32 {
     let [re, im]: [f32; 2] = bytemuck::cast(num);
-    Complex::new(re, im)
+    Complex32::new(re, im)
 }
 
-pub fn pack_complex(c: Complex) -> u64 {
-    let Complex { re, im } = c;
+pub fn pack_complex(c: Complex32) -> u64 {
+    let Complex32 { re, im } = c;
     bytemuck::cast([re, im])
 }
 
-pub fn print_complex(c: &Complex) -> String {
-    if c.im > -constants::ZERO_THRESHOLD {
+pub fn print_complex<P: Precision>(c: &Weight<P>) -> String {
+    if c.im > -P::convert(constants::ZERO_THRESHOLD) {
         format!("{:.8}+{:.8}i", c.re, c.im.abs(),)
     } else {
         format!("{:.8}-{:.8}i", c.re, c.im.abs(),)
@@ -55,6 +58,7 @@ pub fn print_complex(c: &Complex) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::Complex;
 
     #[test]
     fn test_profile() {